@@ -0,0 +1,591 @@
+//! Fixed-size round-robin archives of consolidated probe results for unattended long runs,
+//! one file per target, like RRDtool but with the median instead of the mean.
+//!
+//! Every level of [`ARCHIVE_LEVELS`] holds a fixed number of rows; a row covers one step and
+//! is overwritten once the level wraps around, so files never grow.  Minute rows carry the
+//! exact median of their replies, hour and day rows the median of the minute medians.
+//!
+//! File layout, little-endian:
+//!
+//! ```text
+//! magic        6 bytes   "MPRRA" followed by format version 1
+//! label_len    u16
+//! label        label_len bytes of UTF-8, the target as shown in tables
+//! per level    step_secs u32, rows u32
+//! per level    rows × 28 byte records: start_secs u64 (0 = empty), sent u32, lost u32,
+//!              median_us u32, min_us u32, max_us u32 (u32::MAX = no reply)
+//! ```
+
+use crate::core::constants::ARCHIVE_LEVELS;
+use crate::events::{EventReceiver, PingEvent};
+use crate::network::client::PingTarget;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 6] = b"MPRRA\x01";
+const RECORD_SIZE: u64 = 28;
+const NO_REPLY: u32 = u32::MAX;
+
+/// Consolidated results of one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub start: SystemTime,
+    pub sent: u32,
+    pub lost: u32,
+    /// `None` if no probe was answered, likewise for `min` and `max`.
+    pub median: Option<Duration>,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl Row {
+    pub fn loss_rate(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        self.lost as f64 / self.sent as f64
+    }
+}
+
+/// An open archive file.
+#[derive(Debug)]
+pub struct Archive {
+    file: File,
+    label: String,
+    /// Offset of the first record.
+    records: u64,
+}
+
+impl Archive {
+    /// Opens the archive at `path`, or creates it for a target shown as `label`.
+    pub fn open_or_create(path: &Path, label: &str) -> io::Result<Self> {
+        if path.exists() {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            return Self::from_file(file);
+        }
+
+        let mut header = MAGIC.to_vec();
+        header.extend((label.len() as u16).to_le_bytes());
+        header.extend(label.as_bytes());
+        for (step, rows) in ARCHIVE_LEVELS {
+            header.extend((step as u32).to_le_bytes());
+            header.extend(rows.to_le_bytes());
+        }
+        let total_rows = ARCHIVE_LEVELS
+            .iter()
+            .map(|&(_, rows)| rows as u64)
+            .sum::<u64>();
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.write_all(&header)?;
+        // Zeroed records are empty
+        file.set_len(header.len() as u64 + total_rows * RECORD_SIZE)?;
+        Ok(Self {
+            file,
+            label: label.to_string(),
+            records: header.len() as u64,
+        })
+    }
+
+    /// Opens an existing archive for reading.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    fn from_file(mut file: File) -> io::Result<Self> {
+        let mut magic = [0; 6];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an mping archive"));
+        }
+        let mut len = [0; 2];
+        file.read_exact(&mut len)?;
+        let mut label = vec![0; u16::from_le_bytes(len) as usize];
+        file.read_exact(&mut label)?;
+        let label = String::from_utf8(label).map_err(|_| invalid("label is not UTF-8"))?;
+
+        for (step, rows) in ARCHIVE_LEVELS {
+            let mut level = [0; 8];
+            file.read_exact(&mut level)?;
+            let found = (
+                u32::from_le_bytes(level[..4].try_into().expect("4 bytes")),
+                u32::from_le_bytes(level[4..].try_into().expect("4 bytes")),
+            );
+            if found != (step as u32, rows) {
+                return Err(invalid("archive levels differ from this mping version"));
+            }
+        }
+        let records = file.stream_position()?;
+        Ok(Self {
+            file,
+            label,
+            records,
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The filled rows of `level`, oldest first.
+    pub fn rows(&mut self, level: usize) -> io::Result<Vec<Row>> {
+        let (_, count) = ARCHIVE_LEVELS[level];
+        let mut data = vec![0; (count as u64 * RECORD_SIZE) as usize];
+        self.file.seek(SeekFrom::Start(self.level_offset(level)))?;
+        self.file.read_exact(&mut data)?;
+
+        let mut rows = data
+            .chunks_exact(RECORD_SIZE as usize)
+            .filter_map(decode)
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|row| row.start);
+        Ok(rows)
+    }
+
+    /// The row of `level` starting at `start`, if the slot still holds it.
+    fn row(&mut self, level: usize, start: u64) -> io::Result<Option<Row>> {
+        let mut data = [0; RECORD_SIZE as usize];
+        self.file
+            .seek(SeekFrom::Start(self.slot_offset(level, start)))?;
+        self.file.read_exact(&mut data)?;
+        Ok(decode(&data).filter(|row| secs(row.start) == start))
+    }
+
+    fn write_row(&mut self, level: usize, row: &Row) -> io::Result<()> {
+        let offset = self.slot_offset(level, secs(row.start));
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&encode(row))
+    }
+
+    fn level_offset(&self, level: usize) -> u64 {
+        self.records
+            + ARCHIVE_LEVELS[..level]
+                .iter()
+                .map(|&(_, rows)| rows as u64 * RECORD_SIZE)
+                .sum::<u64>()
+    }
+
+    fn slot_offset(&self, level: usize, start: u64) -> u64 {
+        let (step, rows) = ARCHIVE_LEVELS[level];
+        self.level_offset(level) + (start / step % rows as u64) * RECORD_SIZE
+    }
+}
+
+/// The step of one level being filled.
+#[derive(Debug)]
+struct Bucket {
+    start: u64,
+    sent: u32,
+    lost: u32,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    /// RTTs for minutes, minute medians otherwise.
+    values: Vec<Duration>,
+    /// Probes sent and lost of the row this bucket continues, already counted in the coarser
+    /// rows on disk.
+    resumed: (u32, u32),
+}
+
+impl Bucket {
+    /// Continues a row written before, e.g. by a run that was restarted within the step.
+    fn new(start: u64, previous: Option<Row>) -> Self {
+        let mut bucket = Self {
+            start,
+            sent: 0,
+            lost: 0,
+            min: None,
+            max: None,
+            values: Vec::new(),
+            resumed: (0, 0),
+        };
+        if let Some(row) = previous {
+            bucket.merge(&row);
+            bucket.resumed = (row.sent, row.lost);
+        }
+        bucket
+    }
+
+    fn add(&mut self, rtt: Option<Duration>) {
+        self.sent += 1;
+        match rtt {
+            Some(rtt) => {
+                self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+                self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+                self.values.push(rtt);
+            }
+            None => self.lost += 1,
+        }
+    }
+
+    fn merge(&mut self, row: &Row) {
+        self.sent += row.sent;
+        self.lost += row.lost;
+        self.min = self.min.into_iter().chain(row.min).min();
+        self.max = self.max.into_iter().chain(row.max).max();
+        self.values.extend(row.median);
+    }
+
+    fn row(&self) -> Row {
+        Row {
+            start: UNIX_EPOCH + Duration::from_secs(self.start),
+            sent: self.sent,
+            lost: self.lost,
+            median: median(&self.values),
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+/// Consolidates the probe outcomes of one target into its archive.  A minute row is written
+/// when the minute is over; the hour and day rows it belongs to are rewritten with it.
+#[derive(Debug)]
+pub struct Consolidator {
+    archive: Archive,
+    buckets: [Option<Bucket>; ARCHIVE_LEVELS.len()],
+}
+
+impl Consolidator {
+    pub fn new(archive: Archive) -> Self {
+        Self {
+            archive,
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn add(&mut self, at: SystemTime, rtt: Option<Duration>) -> io::Result<()> {
+        let start = step_start(0, secs(at));
+        if self.buckets[0].as_ref().is_some_and(|b| b.start != start) {
+            self.flush()?;
+        }
+        if self.buckets[0].is_none() {
+            let previous = self.archive.row(0, start)?;
+            self.buckets[0] = Some(Bucket::new(start, previous));
+        }
+        self.buckets[0].as_mut().expect("created above").add(rtt);
+        Ok(())
+    }
+
+    /// Writes the current minute and the coarser rows it adds up to.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(minute) = self.buckets[0].take() else {
+            return Ok(());
+        };
+        let mut row = minute.row();
+        self.archive.write_row(0, &row)?;
+        // The coarser rows on disk already hold what a restarted minute had before
+        row.sent -= minute.resumed.0;
+        row.lost -= minute.resumed.1;
+
+        for level in 1..ARCHIVE_LEVELS.len() {
+            let start = step_start(level, minute.start);
+            if self.buckets[level]
+                .as_ref()
+                .is_none_or(|b| b.start != start)
+            {
+                let previous = self.archive.row(level, start)?;
+                self.buckets[level] = Some(Bucket::new(start, previous));
+            }
+            let bucket = self.buckets[level].as_mut().expect("created above");
+            bucket.merge(&row);
+            self.archive.write_row(level, &bucket.row())?;
+        }
+        Ok(())
+    }
+}
+
+/// Archives of all targets in one directory.
+#[derive(Debug)]
+pub struct Archiver {
+    consolidators: HashMap<String, (PathBuf, Consolidator)>,
+}
+
+impl Archiver {
+    /// Opens or creates the archive of every target in `dir`, creating the directory if needed.
+    pub fn open(dir: &Path, targets: &[PingTarget]) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut consolidators = HashMap::new();
+        for target in targets {
+            let path = dir.join(file_name(target));
+            let archive = Archive::open_or_create(&path, &target.label())
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            consolidators.insert(target.key(), (path, Consolidator::new(archive)));
+        }
+        Ok(Self { consolidators })
+    }
+}
+
+/// Feeds probe events into the archives until the channel is closed, then writes the rows in
+/// progress.  Blocks, so it runs on a thread of its own.  A target whose archive fails is not
+/// archived any further.
+pub fn run(mut events: EventReceiver, mut archiver: Archiver) {
+    while let Some(event) = events.blocking_recv() {
+        let PingEvent::Sample { target, sample, .. } = &event;
+        let key = target.key();
+        let Some((path, consolidator)) = archiver.consolidators.get_mut(&key) else {
+            continue;
+        };
+        if let Err(e) = consolidator.add(sample.at, sample.rtt) {
+            eprintln!("Archive {} stopped: {}", path.display(), e);
+            archiver.consolidators.remove(&key);
+        }
+    }
+    for (path, consolidator) in archiver.consolidators.values_mut() {
+        if let Err(e) = consolidator.flush() {
+            eprintln!("Archive {} stopped: {}", path.display(), e);
+        }
+    }
+}
+
+/// `<host>.rra`, with the probe kind and port appended for TLS and NTP targets, e.g.
+/// `api.example.com_tls_443.rra`.
+pub fn file_name(target: &PingTarget) -> String {
+    format!("{}.rra", file_stem(target))
+}
+
+/// The [name](PingTarget::name) of the target with everything but letters, digits, dots, dashes
+/// and underscores replaced so it is safe as a file name.
+pub(crate) fn file_stem(target: &PingTarget) -> String {
+    target.name().replace(
+        |c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)),
+        "_",
+    )
+}
+
+fn step_start(level: usize, secs: u64) -> u64 {
+    let (step, _) = ARCHIVE_LEVELS[level];
+    secs - secs % step
+}
+
+/// The middle value, or the mean of the two middle values.
+fn median(values: &[Duration]) -> Option<Duration> {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        n if n % 2 == 1 => Some(sorted[mid]),
+        _ => Some((sorted[mid - 1] + sorted[mid]) / 2),
+    }
+}
+
+fn encode(row: &Row) -> [u8; RECORD_SIZE as usize] {
+    let micros = |d: Option<Duration>| {
+        d.map_or(NO_REPLY, |d| {
+            u32::try_from(d.as_micros()).unwrap_or(NO_REPLY - 1)
+        })
+    };
+    let mut data = [0; RECORD_SIZE as usize];
+    data[..8].copy_from_slice(&secs(row.start).to_le_bytes());
+    for (i, value) in [
+        row.sent,
+        row.lost,
+        micros(row.median),
+        micros(row.min),
+        micros(row.max),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        data[8 + i * 4..12 + i * 4].copy_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> Option<Row> {
+    let start = u64::from_le_bytes(data[..8].try_into().expect("8 bytes"));
+    if start == 0 {
+        return None;
+    }
+    let field =
+        |i: usize| u32::from_le_bytes(data[8 + i * 4..12 + i * 4].try_into().expect("4 bytes"));
+    let duration = |i: usize| {
+        let micros = field(i);
+        (micros != NO_REPLY).then(|| Duration::from_micros(micros as u64))
+    };
+    Some(Row {
+        start: UNIX_EPOCH + Duration::from_secs(start),
+        sent: field(0),
+        lost: field(1),
+        median: duration(2),
+        min: duration(3),
+        max: duration(4),
+    })
+}
+
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mping-{}-{}.rra", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    /// 2024-05-14 00:00:00 UTC, the start of a day.
+    const DAY: u64 = 1_715_644_800;
+
+    #[test]
+    fn file_has_fixed_size() {
+        let path = path("size");
+        let mut consolidator = Consolidator::new(Archive::open_or_create(&path, "db1").unwrap());
+        let size = fs::metadata(&path).unwrap().len();
+        for minute in 0..100 {
+            consolidator.add(at(DAY + minute * 60), ms(5)).unwrap();
+        }
+        consolidator.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        let records = ARCHIVE_LEVELS
+            .iter()
+            .map(|&(_, rows)| rows as u64)
+            .sum::<u64>();
+        assert!(size > records * RECORD_SIZE);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn consolidates_minutes_hours_and_days() {
+        let path = path("levels");
+        let mut consolidator = Consolidator::new(Archive::open_or_create(&path, "db1").unwrap());
+        for (offset, rtt) in [(0, ms(1)), (10, ms(9)), (20, ms(2)), (30, None)] {
+            consolidator.add(at(DAY + offset), rtt).unwrap();
+        }
+        for (offset, rtt) in [(60, ms(10)), (70, ms(20))] {
+            consolidator.add(at(DAY + offset), rtt).unwrap();
+        }
+        consolidator.add(at(DAY + 3_600), ms(100)).unwrap();
+        consolidator.flush().unwrap();
+
+        let mut archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.label(), "db1");
+        let minutes = archive.rows(0).unwrap();
+        assert_eq!(minutes.len(), 3);
+        assert_eq!(
+            minutes[0],
+            Row {
+                start: at(DAY),
+                sent: 4,
+                lost: 1,
+                median: ms(2),
+                min: ms(1),
+                max: ms(9),
+            }
+        );
+        assert_eq!(minutes[1].median, ms(15));
+
+        let hours = archive.rows(1).unwrap();
+        assert_eq!(hours.len(), 2);
+        assert_eq!((hours[0].sent, hours[0].lost), (6, 1));
+        // Median of the minute medians 2 and 15
+        assert_eq!(hours[0].median, Some(Duration::from_micros(8_500)));
+        assert_eq!(hours[0].max, ms(20));
+
+        let days = archive.rows(2).unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(
+            (days[0].sent, days[0].min, days[0].max),
+            (7, ms(1), ms(100))
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn restart_continues_rows_in_progress() {
+        let path = path("restart");
+        for rtt in [ms(4), ms(6)] {
+            let archive = Archive::open_or_create(&path, "db1").unwrap();
+            let mut consolidator = Consolidator::new(archive);
+            consolidator.add(at(DAY + 5), rtt).unwrap();
+            consolidator.flush().unwrap();
+        }
+        let mut archive = Archive::open(&path).unwrap();
+        let minutes = archive.rows(0).unwrap();
+        assert_eq!(minutes.len(), 1);
+        assert_eq!((minutes[0].sent, minutes[0].median), (2, ms(5)));
+        assert_eq!(archive.rows(2).unwrap()[0].sent, 2);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn old_rows_are_overwritten() {
+        let path = path("wrap");
+        let mut consolidator = Consolidator::new(Archive::open_or_create(&path, "db1").unwrap());
+        let (step, rows) = ARCHIVE_LEVELS[0];
+        consolidator.add(at(DAY), ms(1)).unwrap();
+        consolidator
+            .add(at(DAY + step * rows as u64), ms(2))
+            .unwrap();
+        consolidator.flush().unwrap();
+
+        let minutes = Archive::open(&path).unwrap().rows(0).unwrap();
+        assert_eq!(minutes.len(), 1);
+        assert_eq!(minutes[0].median, ms(2));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = path("other");
+        fs::write(&path, b"not an archive at all").unwrap();
+        assert_eq!(
+            Archive::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_names_per_target() {
+        let target = PingTarget::with_host(
+            "db1.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        );
+        assert_eq!(file_name(&target), "db1.example.com.rra");
+        let target =
+            PingTarget::new("2001:db8::1".parse().unwrap()).with_kind(ProbeKind::Tls { port: 443 });
+        assert_eq!(file_name(&target), "2001_db8__1_tls_443.rra");
+    }
+
+    #[test]
+    fn median_of_even_count_is_mean_of_middle() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[ms(3).unwrap()]), ms(3));
+        assert_eq!(
+            median(&[
+                ms(4).unwrap(),
+                ms(1).unwrap(),
+                ms(2).unwrap(),
+                ms(9).unwrap()
+            ]),
+            Some(Duration::from_micros(3_000))
+        );
+    }
+}
@@ -1,10 +1,29 @@
+use crate::core::constants::{DEFAULT_METRICS_ADDR, MAX_PAYLOAD_SIZE, SWEEP_BUCKETS};
+use crate::emitters::{Endpoint, MetricPrefix};
+use crate::health::HealthCriteria;
+use crate::history::TimeBound;
+use crate::influx::InfluxDestination;
+use crate::metrics::Tag;
+use crate::mqtt::MqttUrl;
+use crate::nagios::NagiosThreshold;
+#[cfg(feature = "otlp")]
+use crate::network::http::HttpUrl;
+use crate::state::StateThresholds;
+use crate::webhook::{AlertThresholds, Webhook};
 use anyhow::{Result, anyhow};
-use clap::Parser;
-use std::time::Duration;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(value_delimiter = ' ', num_args = 1..)]
     pub hosts: Option<Vec<String>>,
 
@@ -13,6 +32,371 @@ pub struct Args {
 
     #[clap(short, long)]
     pub delay: Option<f32>,
+
+    /// TCP ports to check per host; prints a reachability matrix instead of ping statistics
+    #[clap(long, value_delimiter = ',')]
+    pub tcp: Vec<u16>,
+
+    /// UDP ports to check per host; prints a reachability matrix instead of ping statistics
+    #[clap(long, value_delimiter = ',')]
+    pub udp: Vec<u16>,
+
+    /// Discover the path MTU to each host with don't-fragment probes (needs root or CAP_NET_RAW)
+    #[clap(long, conflicts_with_all = ["tcp", "udp"])]
+    pub pmtu: bool,
+
+    /// Cycle probes through payload sizes MIN-MAX and estimate link bandwidth from the RTT slope
+    #[clap(long, value_name = "MIN-MAX", conflicts_with_all = ["tcp", "udp", "pmtu"])]
+    pub sweep: Option<SweepRange>,
+
+    /// Send N back-to-back ICMP probes per interval; --count then counts bursts
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"]
+    )]
+    pub burst: Option<u16>,
+
+    /// Consecutive lost probes after which a host counts as degraded [default: 1]
+    #[clap(long, value_name = "N")]
+    pub degraded_after: Option<u32>,
+
+    /// Consecutive lost probes after which a host counts as down [default: 3]
+    #[clap(long, value_name = "N")]
+    pub down_after: Option<u32>,
+
+    /// Keep probing until interrupted with Ctrl-C instead of sending --count packets
+    #[clap(long, conflicts_with_all = ["count", "tcp", "udp", "pmtu", "sweep"])]
+    pub continuous: bool,
+
+    /// Shell command to run when a host goes down; details are passed in MPING_* variables
+    #[clap(long, value_name = "CMD")]
+    pub on_down: Option<String>,
+
+    /// Shell command to run when a host recovers from an outage
+    #[clap(long, value_name = "CMD")]
+    pub on_up: Option<String>,
+
+    /// POST a JSON alert to URL when a host goes down, recovers or breaches an alert threshold;
+    /// prefix with slack= or teams= for chat-compatible payloads.  May be given more than once
+    #[clap(long, value_name = "[FORMAT=]URL")]
+    pub webhook: Vec<Webhook>,
+
+    /// Alert the webhooks when a host loses more than PERCENT of its last 10 probes, or of all
+    /// probes at the end of the run
+    #[clap(long, value_name = "PERCENT")]
+    pub alert_loss: Option<f64>,
+
+    /// Alert the webhooks when the average RTT of a host over its last 10 probes, or over the
+    /// whole run at its end, exceeds MS milliseconds
+    #[clap(long, value_name = "MS")]
+    pub alert_rtt: Option<f64>,
+
+    /// Exit with 1 if any host loses more than PERCENT of its probes
+    #[clap(long, value_name = "PERCENT", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub max_loss: Option<f64>,
+
+    /// Exit with 1 if the average RTT of any host exceeds MS milliseconds
+    #[clap(long, value_name = "MS", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub max_avg_rtt: Option<f64>,
+
+    /// Exit with 1 if any single reply takes longer than MS milliseconds
+    #[clap(long, value_name = "MS", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub max_rtt: Option<f64>,
+
+    /// Exit with 2 if fewer than N hosts answer [default: all hosts if a --max-* limit is given]
+    #[clap(long, value_name = "N", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub min_alive: Option<usize>,
+
+    /// Print a single Nagios/Icinga plugin status line with perfdata and exit with its code
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "tcp", "udp", "pmtu", "sweep", "continuous",
+            "max_loss", "max_avg_rtt", "max_rtt", "min_alive",
+        ]
+    )]
+    pub nagios: bool,
+
+    /// Nagios WARNING threshold as round trip average in ms and loss [default: 100,20%]
+    #[clap(long, value_name = "RTA,PL%", requires = "nagios")]
+    pub warning: Option<NagiosThreshold>,
+
+    /// Nagios CRITICAL threshold as round trip average in ms and loss [default: 500,60%]
+    #[clap(long, value_name = "RTA,PL%", requires = "nagios")]
+    pub critical: Option<NagiosThreshold>,
+
+    /// Output format of the results
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["tcp", "udp", "pmtu", "sweep", "nagios"]
+    )]
+    pub format: OutputFormat,
+
+    /// Write one CSV row per probe to PATH while the run is in progress
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub csv_out: Option<PathBuf>,
+
+    /// Write one CSV row per host with the columns of the results table to PATH
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub summary_csv: Option<PathBuf>,
+
+    /// Atomically write the results to PATH for the node_exporter textfile collector
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub prom_textfile: Option<PathBuf>,
+
+    /// Write every probe in InfluxDB line protocol to DEST: - for stdout, a file to append to,
+    /// or an http(s) write endpoint (token from INFLUX_TOKEN)
+    #[clap(long, value_name = "DEST", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub influx: Option<InfluxDestination>,
+
+    /// Write one aggregate point per host every SECS seconds instead of one point per probe
+    #[clap(
+        long,
+        value_name = "SECS",
+        requires = "influx",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub influx_aggregate: Option<u64>,
+
+    /// Tag added to every exported point, e.g. --tag site=fra1
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub tag: Vec<Tag>,
+
+    /// Send RTT timings and loss counters for every probe to a StatsD server at HOST[:PORT]
+    #[clap(long, value_name = "HOST[:PORT]", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub statsd: Option<Endpoint>,
+
+    /// Send RTTs and losses for every probe to a Graphite plaintext listener at HOST[:PORT]
+    #[clap(long, value_name = "HOST[:PORT]", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub graphite: Option<Endpoint>,
+
+    /// Metric path for --statsd and --graphite; {host} stands for the target host name
+    #[clap(long, value_name = "PREFIX", default_value = "mping.{host}")]
+    pub metric_prefix: MetricPrefix,
+
+    /// Record the run and every probe in the SQLite database at PATH for `mping history`
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub db: Option<PathBuf>,
+
+    /// Consolidate every probe into fixed-size round-robin archives of 1-minute, 1-hour and
+    /// 1-day rows, one file per target in DIR, for `mping archive`
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub archive: Option<PathBuf>,
+
+    /// Draw a Smokeping-style SVG graph of the RTT spread and loss over the run for every
+    /// target into DIR
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub svg: Option<PathBuf>,
+
+    /// Publish per-host summaries and retained state changes to an MQTT broker at
+    /// mqtt[s]://[USER:PASSWORD@]HOST[:PORT]
+    #[clap(long, value_name = "URL", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub mqtt: Option<MqttUrl>,
+
+    /// First topic level; messages go to TOPIC/<agent>/<host> and TOPIC/<agent>/<host>/state
+    #[clap(long, value_name = "TOPIC", default_value = "mping", requires = "mqtt")]
+    pub mqtt_topic: String,
+
+    /// Agent name in the topics [default: the name of this machine]
+    #[clap(long, value_name = "NAME", requires = "mqtt")]
+    pub mqtt_agent: Option<String>,
+
+    /// MQTT quality of service: 0 at most once, 1 at least once, 2 exactly once
+    #[clap(
+        long,
+        value_name = "QOS",
+        default_value_t = 0,
+        requires = "mqtt",
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    pub mqtt_qos: u8,
+
+    /// Push RTT histograms, probe counters and up and availability gauges to an OTLP/HTTP
+    /// collector, e.g. http://collector:4318/v1/metrics (headers from OTEL_EXPORTER_OTLP_HEADERS)
+    #[cfg(feature = "otlp")]
+    #[clap(long, value_name = "URL", conflicts_with_all = ["tcp", "udp", "pmtu", "sweep"])]
+    pub otlp: Option<HttpUrl>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Tables for reading in a terminal
+    #[default]
+    Table,
+    /// A single JSON document with all results once the run has finished
+    Json,
+    /// One JSON object per probe outcome and state change as they happen
+    Ndjson,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Trace the path to a host and print per-hop statistics
+    Trace(TraceArgs),
+    /// Continuously probe every hop to each host and refresh a report like `mtr --report`
+    Mtr(MtrArgs),
+    /// Continuously probe the hosts and expose Prometheus metrics over HTTP
+    Serve(ServeArgs),
+    /// Show min/avg/max RTT and loss of a host per run from a database written with --db
+    History(HistoryArgs),
+    /// Dump or graph a round-robin archive written with --archive
+    Archive(ArchiveArgs),
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct TraceArgs {
+    pub host: String,
+
+    #[clap(short, long)]
+    pub count: Option<u16>,
+
+    #[clap(short, long)]
+    pub delay: Option<f32>,
+
+    /// Maximum number of hops (TTL) to probe
+    #[clap(short, long, default_value_t = 30)]
+    pub max_hops: u8,
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct MtrArgs {
+    #[clap(required = true, num_args = 1..)]
+    pub hosts: Vec<String>,
+
+    /// Number of rounds; runs until interrupted if omitted
+    #[clap(short, long)]
+    pub count: Option<u16>,
+
+    #[clap(short, long)]
+    pub delay: Option<f32>,
+
+    /// Maximum number of hops (TTL) to probe
+    #[clap(short, long, default_value_t = 30)]
+    pub max_hops: u8,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    #[clap(required = true, num_args = 1..)]
+    pub hosts: Vec<String>,
+
+    /// Address and port to serve /metrics on
+    #[clap(long, value_name = "ADDR:PORT", default_value = DEFAULT_METRICS_ADDR)]
+    pub listen: SocketAddr,
+
+    #[clap(short, long)]
+    pub delay: Option<f32>,
+
+    /// Consecutive lost probes after which mping_up drops to 0 [default: 3]
+    #[clap(long, value_name = "N")]
+    pub down_after: Option<u32>,
+
+    /// Label added to every series, e.g. --tag site=fra1
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub tag: Vec<Tag>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct HistoryArgs {
+    /// Host name or address as probed
+    pub host: String,
+
+    /// Database written by runs with --db
+    #[clap(long, value_name = "PATH")]
+    pub db: PathBuf,
+
+    /// Start of the range: a time ago like 6h or 7d, or a local date and time like 2024-05-14
+    /// or "2024-05-14 13:30"
+    #[clap(long, value_name = "TIME", default_value = "24h")]
+    pub since: TimeBound,
+
+    /// End of the range, in the same forms as --since [default: now]
+    #[clap(long, value_name = "TIME")]
+    pub until: Option<TimeBound>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ArchiveArgs {
+    /// Archive file of one target, e.g. archives/db1.example.com.rra
+    pub file: PathBuf,
+
+    /// Which consolidation level to show
+    #[clap(short, long, value_enum, default_value_t)]
+    pub resolution: Resolution,
+
+    /// Show only the newest N rows
+    #[clap(short, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub last: Option<u32>,
+
+    /// Draw the median RTT of every row as a bar instead of printing a table
+    #[clap(short, long)]
+    pub graph: bool,
+}
+
+/// Consolidation level of a round-robin archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Resolution {
+    #[default]
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    /// Index into [`crate::core::constants::ARCHIVE_LEVELS`].
+    pub fn level(self) -> usize {
+        self as usize
+    }
+}
+
+/// Range of payload sizes in bytes, both ends inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepRange {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl SweepRange {
+    /// Up to [`SWEEP_BUCKETS`] evenly spaced sizes from `min` to `max`.
+    pub fn sizes(&self) -> Vec<usize> {
+        let steps = SWEEP_BUCKETS.min(self.max - self.min + 1);
+        if steps == 1 {
+            return vec![self.min];
+        }
+        (0..steps)
+            .map(|i| self.min + (self.max - self.min) * i / (steps - 1))
+            .collect()
+    }
+}
+
+impl FromStr for SweepRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected MIN-MAX, got '{}'", s))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid payload size '{}'", n))
+        };
+        let (min, max) = (parse(min)?, parse(max)?);
+        if min > max {
+            return Err(format!("{} is larger than {}", min, max));
+        }
+        if max > MAX_PAYLOAD_SIZE {
+            return Err(format!(
+                "payload size is limited to {} bytes",
+                MAX_PAYLOAD_SIZE
+            ));
+        }
+        Ok(Self { min, max })
+    }
 }
 
 #[derive(Debug)]
@@ -20,25 +404,284 @@ pub struct PingConfig {
     pub hosts: Vec<String>,
     pub packet_count: u16,
     pub interval: Duration,
+    pub tcp_ports: Vec<u16>,
+    pub udp_ports: Vec<u16>,
+    pub pmtu: bool,
+    pub sweep: Option<SweepRange>,
+    /// Probes per interval; 1 unless `--burst` is given.
+    pub burst_size: u16,
+    pub thresholds: StateThresholds,
+    pub continuous: bool,
+    pub on_down: Option<String>,
+    pub on_up: Option<String>,
+    pub webhooks: Vec<Webhook>,
+    pub alert_thresholds: AlertThresholds,
+    pub criteria: HealthCriteria,
+    pub nagios: bool,
+    pub nagios_warning: NagiosThreshold,
+    pub nagios_critical: NagiosThreshold,
+    pub format: OutputFormat,
+    pub csv_out: Option<PathBuf>,
+    pub summary_csv: Option<PathBuf>,
+    pub prom_textfile: Option<PathBuf>,
+    pub influx: Option<InfluxDestination>,
+    /// `None` writes one point per probe.
+    pub influx_aggregate: Option<Duration>,
+    pub tags: Vec<Tag>,
+    pub statsd: Option<Endpoint>,
+    pub graphite: Option<Endpoint>,
+    pub metric_prefix: MetricPrefix,
+    pub db: Option<PathBuf>,
+    pub archive: Option<PathBuf>,
+    pub svg: Option<PathBuf>,
+    pub mqtt: Option<MqttUrl>,
+    pub mqtt_topic: String,
+    /// `None` uses the name of this machine.
+    pub mqtt_agent: Option<String>,
+    pub mqtt_qos: u8,
+    #[cfg(feature = "otlp")]
+    pub otlp: Option<HttpUrl>,
 }
 
 impl PingConfig {
     pub fn from_args(args: Args) -> Result<Self> {
         let hosts = args.hosts.ok_or_else(|| anyhow!("No hosts specified."))?;
-        let count = args.count.unwrap_or(5);
-        let delay = Duration::from_secs_f32(args.delay.unwrap_or(1.0).max(0.1));
+        let nagios_warning = args.warning.unwrap_or(NagiosThreshold::DEFAULT_WARNING);
+        let nagios_critical = args.critical.unwrap_or(NagiosThreshold::DEFAULT_CRITICAL);
+        if nagios_warning.rta > nagios_critical.rta || nagios_warning.pl > nagios_critical.pl {
+            return Err(anyhow!("--warning must not exceed --critical."));
+        }
+        if args.influx == Some(InfluxDestination::Stdout)
+            && (args.nagios || args.format != OutputFormat::Table)
+        {
+            return Err(anyhow!(
+                "--influx - cannot share stdout with --nagios or --format."
+            ));
+        }
+        if let Some(min_alive) = args.min_alive
+            && min_alive > hosts.len()
+        {
+            return Err(anyhow!(
+                "--min-alive {} exceeds the {} hosts given.",
+                min_alive,
+                hosts.len()
+            ));
+        }
+        let tags = unique_tags(args.tag)?;
 
         Ok(Self {
             hosts,
-            packet_count: count,
-            interval: delay,
+            packet_count: packet_count(args.count),
+            interval: interval(args.delay),
+            tcp_ports: args.tcp,
+            udp_ports: args.udp,
+            pmtu: args.pmtu,
+            sweep: args.sweep,
+            burst_size: args.burst.unwrap_or(1),
+            thresholds: thresholds(args.degraded_after, args.down_after)?,
+            continuous: args.continuous,
+            on_down: args.on_down,
+            on_up: args.on_up,
+            webhooks: args.webhook,
+            alert_thresholds: AlertThresholds {
+                loss_percent: percent("--alert-loss", args.alert_loss)?,
+                avg_rtt: millis("--alert-rtt", args.alert_rtt)?,
+            },
+            criteria: HealthCriteria {
+                max_loss: percent("--max-loss", args.max_loss)?,
+                max_avg_rtt: millis("--max-avg-rtt", args.max_avg_rtt)?,
+                max_rtt: millis("--max-rtt", args.max_rtt)?,
+                min_alive: args.min_alive,
+            },
+            nagios: args.nagios,
+            nagios_warning,
+            nagios_critical,
+            format: args.format,
+            csv_out: args.csv_out,
+            summary_csv: args.summary_csv,
+            prom_textfile: args.prom_textfile,
+            influx: args.influx,
+            influx_aggregate: args.influx_aggregate.map(Duration::from_secs),
+            tags,
+            statsd: args.statsd,
+            graphite: args.graphite,
+            metric_prefix: args.metric_prefix,
+            db: args.db,
+            archive: args.archive,
+            svg: args.svg,
+            mqtt: args.mqtt,
+            mqtt_topic: args.mqtt_topic,
+            mqtt_agent: args.mqtt_agent,
+            mqtt_qos: args.mqtt_qos,
+            #[cfg(feature = "otlp")]
+            otlp: args.otlp,
+        })
+    }
+
+    /// Whether stdout carries a format for other programs, so progress messages must stay off it.
+    pub fn is_machine_readable(&self) -> bool {
+        self.nagios
+            || self.format != OutputFormat::Table
+            || self.influx == Some(InfluxDestination::Stdout)
+    }
+
+    /// Whether the run checks ports and prints a reachability matrix.
+    pub fn is_matrix(&self) -> bool {
+        !self.tcp_ports.is_empty() || !self.udp_ports.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct TraceConfig {
+    pub host: String,
+    pub packet_count: u16,
+    pub interval: Duration,
+    pub max_hops: u8,
+}
+
+impl TraceConfig {
+    pub fn from_args(args: TraceArgs) -> Result<Self> {
+        if args.max_hops == 0 {
+            return Err(anyhow!("--max-hops must be at least 1."));
+        }
+
+        Ok(Self {
+            host: args.host,
+            packet_count: packet_count(args.count),
+            interval: interval(args.delay),
+            max_hops: args.max_hops,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MtrConfig {
+    pub hosts: Vec<String>,
+    /// `None` keeps probing until interrupted.
+    pub rounds: Option<u64>,
+    pub interval: Duration,
+    pub max_hops: u8,
+}
+
+impl MtrConfig {
+    pub fn from_args(args: MtrArgs) -> Result<Self> {
+        if args.max_hops == 0 {
+            return Err(anyhow!("--max-hops must be at least 1."));
+        }
+
+        Ok(Self {
+            hosts: args.hosts,
+            rounds: args.count.map(u64::from),
+            interval: interval(args.delay),
+            max_hops: args.max_hops,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ServeConfig {
+    pub hosts: Vec<String>,
+    pub listen: SocketAddr,
+    pub interval: Duration,
+    pub thresholds: StateThresholds,
+    pub tags: Vec<Tag>,
+}
+
+impl ServeConfig {
+    pub fn from_args(args: ServeArgs) -> Result<Self> {
+        Ok(Self {
+            hosts: args.hosts,
+            listen: args.listen,
+            interval: interval(args.delay),
+            thresholds: thresholds(None, args.down_after)?,
+            tags: unique_tags(args.tag)?,
         })
     }
 }
 
+#[derive(Debug)]
+pub struct HistoryConfig {
+    pub host: String,
+    pub db: PathBuf,
+    pub since: SystemTime,
+    pub until: SystemTime,
+}
+
+impl HistoryConfig {
+    pub fn from_args(args: HistoryArgs) -> Result<Self> {
+        let now = SystemTime::now();
+        let since = args.since.resolve(now);
+        let until = args.until.map_or(now, |until| until.resolve(now));
+        if since >= until {
+            return Err(anyhow!("--since must be before --until."));
+        }
+
+        Ok(Self {
+            host: args.host,
+            db: args.db,
+            since,
+            until,
+        })
+    }
+}
+
+fn unique_tags(tags: Vec<Tag>) -> Result<Vec<Tag>> {
+    let mut keys = tags.iter().map(|tag| &tag.key).collect::<Vec<_>>();
+    keys.sort_unstable();
+    if let Some(key) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(anyhow!("--tag {} is given more than once.", key[0]));
+    }
+    Ok(tags)
+}
+
+fn thresholds(degraded_after: Option<u32>, down_after: Option<u32>) -> Result<StateThresholds> {
+    let defaults = StateThresholds::default();
+    let thresholds = StateThresholds {
+        degraded_after: degraded_after.unwrap_or(defaults.degraded_after),
+        down_after: down_after.unwrap_or(defaults.down_after),
+    };
+
+    if thresholds.degraded_after == 0 || thresholds.down_after == 0 {
+        return Err(anyhow!(
+            "--degraded-after and --down-after must be at least 1."
+        ));
+    }
+    if thresholds.degraded_after > thresholds.down_after {
+        return Err(anyhow!("--degraded-after must not exceed --down-after."));
+    }
+    Ok(thresholds)
+}
+
+fn percent(flag: &str, value: Option<f64>) -> Result<Option<f64>> {
+    if value.is_some_and(|v| !(0.0..=100.0).contains(&v)) {
+        return Err(anyhow!("{} must be between 0 and 100.", flag));
+    }
+    Ok(value)
+}
+
+fn millis(flag: &str, value: Option<f64>) -> Result<Option<Duration>> {
+    match value {
+        Some(ms) if !ms.is_finite() || ms <= 0.0 => Err(anyhow!(
+            "{} must be a positive number of milliseconds.",
+            flag
+        )),
+        value => Ok(value.map(|ms| Duration::from_secs_f64(ms / 1000.0))),
+    }
+}
+
+fn packet_count(count: Option<u16>) -> u16 {
+    count.unwrap_or(5)
+}
+
+/// Delay between packets; anything below 100 ms is raised to 100 ms to avoid flooding.
+fn interval(delay: Option<f32>) -> Duration {
+    Duration::from_secs_f32(delay.unwrap_or(1.0).max(0.1))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::webhook::WebhookFormat;
 
     #[test]
     fn from_args_no_hosts_returns_error() {
@@ -46,6 +689,7 @@ mod tests {
             hosts: None,
             count: None,
             delay: None,
+            ..Default::default()
         };
         let result = PingConfig::from_args(args);
         assert!(result.is_err());
@@ -57,6 +701,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: None,
             delay: None,
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.hosts, vec!["example.com"]);
@@ -70,6 +715,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: Some(20),
             delay: Some(2.0),
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.packet_count, 20);
@@ -82,6 +728,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: None,
             delay: Some(0.05),
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.interval, Duration::from_secs_f32(0.1));
@@ -93,6 +740,7 @@ mod tests {
             hosts: Some(vec!["google.com".to_string(), "8.8.8.8".to_string()]),
             count: None,
             delay: None,
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.hosts.len(), 2);
@@ -104,6 +752,7 @@ mod tests {
             hosts: Some(vec![]),
             count: None,
             delay: None,
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert!(config.hosts.is_empty());
@@ -115,6 +764,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: None,
             delay: Some(0.1),
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.interval, Duration::from_secs_f32(0.1));
@@ -126,6 +776,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: Some(0),
             delay: None,
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.packet_count, 0);
@@ -137,6 +788,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: None,
             delay: Some(-5.0),
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.interval, Duration::from_secs_f32(0.1));
@@ -148,6 +800,7 @@ mod tests {
             hosts: Some(vec!["example.com".to_string()]),
             count: None,
             delay: Some(86400.0),
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.interval, Duration::from_secs_f32(86400.0));
@@ -159,8 +812,630 @@ mod tests {
             hosts: Some(vec!["".to_string()]),
             count: None,
             delay: None,
+            ..Default::default()
         };
         let config = PingConfig::from_args(args).unwrap();
         assert_eq!(config.hosts, vec![""]);
     }
+
+    #[test]
+    fn from_args_without_ports_is_not_matrix() {
+        let args = Args {
+            hosts: Some(vec!["example.com".to_string()]),
+            ..Default::default()
+        };
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(!config.is_matrix());
+    }
+
+    #[test]
+    fn from_args_with_ports_is_matrix() {
+        let args = Args {
+            hosts: Some(vec!["example.com".to_string()]),
+            tcp: vec![22, 443],
+            udp: vec![53],
+            ..Default::default()
+        };
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(config.is_matrix());
+        assert_eq!(config.tcp_ports, vec![22, 443]);
+        assert_eq!(config.udp_ports, vec![53]);
+    }
+
+    #[test]
+    fn args_parse_comma_separated_ports() {
+        let args = Args::parse_from(["mping", "--tcp", "22,80", "--udp", "53", "example.com"]);
+        assert_eq!(args.tcp, vec![22, 80]);
+        assert_eq!(args.udp, vec![53]);
+    }
+
+    #[test]
+    fn args_parse_pmtu_flag() {
+        let args = Args::parse_from(["mping", "--pmtu", "example.com"]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(config.pmtu);
+        assert!(!config.is_matrix());
+    }
+
+    #[test]
+    fn args_pmtu_conflicts_with_port_checks() {
+        assert!(Args::try_parse_from(["mping", "--pmtu", "--tcp", "22", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_sweep_range() {
+        let args = Args::parse_from(["mping", "--sweep", "64-1400", "example.com"]);
+        assert_eq!(args.sweep, Some(SweepRange { min: 64, max: 1400 }));
+    }
+
+    #[test]
+    fn sweep_range_rejects_invalid_ranges() {
+        assert!("1400-64".parse::<SweepRange>().is_err());
+        assert!("64".parse::<SweepRange>().is_err());
+        assert!("0-70000".parse::<SweepRange>().is_err());
+    }
+
+    #[test]
+    fn sweep_range_sizes_are_evenly_spaced() {
+        let range = SweepRange { min: 0, max: 1000 };
+        assert_eq!(range.sizes(), vec![0, 250, 500, 750, 1000]);
+    }
+
+    #[test]
+    fn sweep_range_sizes_for_narrow_ranges() {
+        assert_eq!(SweepRange { min: 56, max: 56 }.sizes(), vec![56]);
+        assert_eq!(SweepRange { min: 56, max: 58 }.sizes(), vec![56, 57, 58]);
+    }
+
+    #[test]
+    fn from_args_burst_defaults_to_single_probe() {
+        let args = Args::parse_from(["mping", "example.com"]);
+        assert_eq!(PingConfig::from_args(args).unwrap().burst_size, 1);
+
+        let args = Args::parse_from(["mping", "--burst", "10", "example.com"]);
+        assert_eq!(PingConfig::from_args(args).unwrap().burst_size, 10);
+    }
+
+    #[test]
+    fn args_reject_empty_burst() {
+        assert!(Args::try_parse_from(["mping", "--burst", "0", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn from_args_state_thresholds() {
+        let args = Args::parse_from(["mping", "--down-after", "5", "example.com"]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(
+            config.thresholds,
+            StateThresholds {
+                degraded_after: 1,
+                down_after: 5
+            }
+        );
+    }
+
+    #[test]
+    fn from_args_rejects_inverted_thresholds() {
+        let args = Args::parse_from([
+            "mping",
+            "--degraded-after",
+            "4",
+            "--down-after",
+            "2",
+            "example.com",
+        ]);
+        assert!(PingConfig::from_args(args).is_err());
+
+        let args = Args::parse_from(["mping", "--down-after", "0", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+    }
+
+    #[test]
+    fn args_parse_continuous_with_hooks() {
+        let args = Args::parse_from([
+            "mping",
+            "--continuous",
+            "--on-down",
+            "systemctl restart wg-quick@wg0",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(config.continuous);
+        assert_eq!(
+            config.on_down.as_deref(),
+            Some("systemctl restart wg-quick@wg0")
+        );
+        assert!(config.on_up.is_none());
+    }
+
+    #[test]
+    fn args_continuous_conflicts_with_count() {
+        assert!(Args::try_parse_from(["mping", "--continuous", "-c", "5", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_webhooks_and_alert_thresholds() {
+        let args = Args::parse_from([
+            "mping",
+            "--webhook",
+            "slack=https://hooks.slack.com/services/T0/B0/x",
+            "--webhook",
+            "http://localhost:8080/alerts",
+            "--alert-loss",
+            "5",
+            "--alert-rtt",
+            "150",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(config.webhooks.len(), 2);
+        assert_eq!(config.webhooks[0].format, WebhookFormat::Slack);
+        assert_eq!(config.webhooks[1].url.port, 8080);
+        assert_eq!(config.alert_thresholds.loss_percent, Some(5.0));
+        assert_eq!(
+            config.alert_thresholds.avg_rtt,
+            Some(Duration::from_millis(150))
+        );
+    }
+
+    #[test]
+    fn args_invalid_alert_thresholds_return_error() {
+        assert!(Args::try_parse_from(["mping", "--webhook", "ftp://x", "example.com"]).is_err());
+
+        let args = Args::parse_from(["mping", "--alert-loss", "120", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+
+        let args = Args::parse_from(["mping", "--alert-rtt", "0", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+    }
+
+    #[test]
+    fn args_parse_health_criteria() {
+        let args = Args::parse_from([
+            "mping",
+            "--max-loss",
+            "2.5",
+            "--max-avg-rtt",
+            "80",
+            "--max-rtt",
+            "250",
+            "--min-alive",
+            "2",
+            "a.example.com",
+            "b.example.com",
+            "c.example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(
+            config.criteria,
+            HealthCriteria {
+                max_loss: Some(2.5),
+                max_avg_rtt: Some(Duration::from_millis(80)),
+                max_rtt: Some(Duration::from_millis(250)),
+                min_alive: Some(2),
+            }
+        );
+
+        let args = Args::parse_from([
+            "mping",
+            "--min-alive",
+            "3",
+            "a.example.com",
+            "b.example.com",
+        ]);
+        assert!(PingConfig::from_args(args).is_err());
+    }
+
+    #[test]
+    fn args_parse_nagios_thresholds() {
+        let args = Args::parse_from([
+            "mping",
+            "--nagios",
+            "--warning",
+            "50,5%",
+            "--critical",
+            "200,30%",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(config.nagios);
+        assert_eq!(config.nagios_warning.rta, Duration::from_millis(50));
+        assert_eq!(config.nagios_critical.pl, 30.0);
+    }
+
+    #[test]
+    fn args_nagios_defaults_and_validation() {
+        let config =
+            PingConfig::from_args(Args::parse_from(["mping", "--nagios", "example.com"])).unwrap();
+        assert_eq!(config.nagios_warning, NagiosThreshold::DEFAULT_WARNING);
+        assert_eq!(config.nagios_critical, NagiosThreshold::DEFAULT_CRITICAL);
+
+        let args = Args::parse_from(["mping", "--nagios", "--warning", "600,20%", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+
+        assert!(Args::try_parse_from(["mping", "--warning", "50,5%", "example.com"]).is_err());
+        assert!(
+            Args::try_parse_from(["mping", "--nagios", "--max-loss", "5", "example.com"]).is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_csv_paths() {
+        let args = Args::parse_from([
+            "mping",
+            "--csv-out",
+            "samples.csv",
+            "--summary-csv",
+            "summary.csv",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(config.csv_out, Some(PathBuf::from("samples.csv")));
+        assert_eq!(config.summary_csv, Some(PathBuf::from("summary.csv")));
+        assert_eq!(config.prom_textfile, None);
+
+        assert!(
+            Args::try_parse_from(["mping", "--csv-out", "x.csv", "--tcp", "22", "example.com"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_prom_textfile() {
+        let args = Args::parse_from([
+            "mping",
+            "--prom-textfile",
+            "/var/lib/node_exporter/mping.prom",
+            "example.com",
+        ]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().prom_textfile,
+            Some(PathBuf::from("/var/lib/node_exporter/mping.prom"))
+        );
+        assert!(
+            Args::try_parse_from([
+                "mping",
+                "--prom-textfile",
+                "x.prom",
+                "--pmtu",
+                "example.com"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_influx_output() {
+        let args = Args::parse_from([
+            "mping",
+            "--influx",
+            "http://localhost:8086/api/v2/write?bucket=net",
+            "--influx-aggregate",
+            "60",
+            "--tag",
+            "site=fra1",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert!(matches!(config.influx, Some(InfluxDestination::Http(_))));
+        assert_eq!(config.influx_aggregate, Some(Duration::from_secs(60)));
+        assert_eq!(config.tags[0].key, "site");
+        assert!(!config.is_machine_readable());
+
+        let args = Args::parse_from(["mping", "--influx", "-", "example.com"]);
+        assert!(PingConfig::from_args(args).unwrap().is_machine_readable());
+
+        let args = Args::parse_from(["mping", "--influx", "-", "--format", "json", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+        assert!(
+            Args::try_parse_from(["mping", "--influx-aggregate", "10", "example.com"]).is_err()
+        );
+        assert!(
+            Args::try_parse_from(["mping", "--influx", "-", "--influx-aggregate", "0", "x"])
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn args_parse_otlp_endpoint() {
+        let args = Args::parse_from([
+            "mping",
+            "--otlp",
+            "http://collector:4318/v1/metrics",
+            "example.com",
+        ]);
+        let url = PingConfig::from_args(args).unwrap().otlp.unwrap();
+        assert_eq!((url.port, url.path.as_str()), (4318, "/v1/metrics"));
+        assert!(
+            Args::try_parse_from(["mping", "--otlp", "collector:4318", "example.com"]).is_err()
+        );
+    }
+
+    fn history(args: &[&str]) -> Result<HistoryConfig> {
+        let args = Args::try_parse_from(args)?;
+        let Some(Command::History(history)) = args.command else {
+            panic!("expected history subcommand");
+        };
+        HistoryConfig::from_args(history)
+    }
+
+    #[test]
+    fn history_defaults_to_last_day() {
+        let config = history(&["mping", "history", "--db", "pings.db", "db1"]).unwrap();
+        assert_eq!(config.host, "db1");
+        assert_eq!(
+            config.until.duration_since(config.since).unwrap(),
+            Duration::from_secs(86_400)
+        );
+
+        let config = history(&[
+            "mping",
+            "history",
+            "--db",
+            "pings.db",
+            "--since",
+            "2024-05-14",
+            "--until",
+            "2024-05-15",
+            "db1",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.until.duration_since(config.since).unwrap(),
+            Duration::from_secs(86_400)
+        );
+    }
+
+    #[test]
+    fn history_rejects_empty_range_and_missing_db() {
+        assert!(history(&["mping", "history", "--db", "x.db", "--until", "2d", "db1"]).is_err());
+        assert!(
+            history(&[
+                "mping", "history", "--since", "tuesday", "--db", "x.db", "db1"
+            ])
+            .is_err()
+        );
+        assert!(Args::try_parse_from(["mping", "history", "db1"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_db_path() {
+        let args = Args::parse_from(["mping", "--db", "pings.db", "example.com"]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().db,
+            Some(PathBuf::from("pings.db"))
+        );
+        assert!(Args::try_parse_from(["mping", "--db", "x.db", "--pmtu", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_archive_options() {
+        let args = Args::parse_from(["mping", "--archive", "archives", "example.com"]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().archive,
+            Some(PathBuf::from("archives"))
+        );
+
+        let args = Args::parse_from(["mping", "archive", "-r", "hour", "-l", "48", "-g", "x.rra"]);
+        let Some(Command::Archive(archive)) = args.command else {
+            panic!("expected archive subcommand");
+        };
+        assert_eq!(archive.resolution.level(), 1);
+        assert_eq!(archive.last, Some(48));
+        assert!(archive.graph);
+        assert!(Args::try_parse_from(["mping", "archive", "--last", "0", "x.rra"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_svg_output() {
+        let args = Args::parse_from(["mping", "--svg", "graphs", "example.com"]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().svg,
+            Some(PathBuf::from("graphs"))
+        );
+        assert!(
+            Args::try_parse_from(["mping", "--svg", "graphs", "--tcp", "22", "example.com"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_mqtt_output() {
+        let args = Args::parse_from([
+            "mping",
+            "--mqtt",
+            "mqtts://edge:pw@broker.example.com",
+            "--mqtt-agent",
+            "edge1",
+            "--mqtt-qos",
+            "1",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(config.mqtt.unwrap().port, 8883);
+        assert_eq!(config.mqtt_topic, "mping");
+        assert_eq!(config.mqtt_agent.as_deref(), Some("edge1"));
+        assert_eq!(config.mqtt_qos, 1);
+
+        assert!(Args::try_parse_from(["mping", "--mqtt-qos", "1", "example.com"]).is_err());
+        assert!(
+            Args::try_parse_from(["mping", "--mqtt", "mqtt://b", "--mqtt-qos", "3", "x"]).is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_metric_emitters() {
+        let args = Args::parse_from([
+            "mping",
+            "--statsd",
+            "localhost",
+            "--graphite",
+            "carbon.example.com:2013",
+            "--metric-prefix",
+            "net.{host}.ping",
+            "example.com",
+        ]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(config.statsd.unwrap().to_string(), "localhost");
+        assert_eq!(config.graphite.unwrap().port, Some(2013));
+        assert_eq!(config.metric_prefix, "net.{host}.ping".parse().unwrap());
+
+        let args = Args::parse_from(["mping", "example.com"]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().metric_prefix,
+            MetricPrefix::default()
+        );
+        assert!(Args::try_parse_from(["mping", "--statsd", "localhost:x", "example.com"]).is_err());
+        assert!(Args::try_parse_from(["mping", "--metric-prefix", "a b", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn args_parse_output_format() {
+        let config = PingConfig::from_args(Args::parse_from(["mping", "example.com"])).unwrap();
+        assert_eq!(config.format, OutputFormat::Table);
+
+        let args = Args::parse_from(["mping", "--format", "json", "example.com"]);
+        assert_eq!(
+            PingConfig::from_args(args).unwrap().format,
+            OutputFormat::Json
+        );
+
+        let args = Args::parse_from(["mping", "--format", "ndjson", "--continuous", "example.com"]);
+        let config = PingConfig::from_args(args).unwrap();
+        assert_eq!(config.format, OutputFormat::Ndjson);
+        assert!(config.is_machine_readable());
+
+        assert!(Args::try_parse_from(["mping", "--format", "xml", "example.com"]).is_err());
+        assert!(
+            Args::try_parse_from(["mping", "--format", "json", "--nagios", "example.com"]).is_err()
+        );
+    }
+
+    #[test]
+    fn args_invalid_health_criteria_return_error() {
+        let args = Args::parse_from(["mping", "--max-loss", "150", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+
+        let args = Args::parse_from(["mping", "--max-rtt", "nan", "example.com"]);
+        assert!(PingConfig::from_args(args).is_err());
+
+        assert!(
+            Args::try_parse_from(["mping", "--max-loss", "5", "--tcp", "22", "example.com"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn args_parse_trace_subcommand() {
+        let args = Args::parse_from(["mping", "trace", "-m", "12", "example.com"]);
+        match args.command {
+            Some(Command::Trace(trace)) => {
+                assert_eq!(trace.host, "example.com");
+                assert_eq!(trace.max_hops, 12);
+            }
+            _ => panic!("expected trace subcommand"),
+        }
+        assert!(args.hosts.is_none());
+    }
+
+    #[test]
+    fn args_parse_hosts_without_subcommand() {
+        let args = Args::parse_from(["mping", "-c", "3", "example.com", "8.8.8.8"]);
+        assert!(args.command.is_none());
+        assert_eq!(
+            args.hosts,
+            Some(vec!["example.com".to_string(), "8.8.8.8".to_string()])
+        );
+    }
+
+    #[test]
+    fn trace_config_defaults_and_minimum_delay() {
+        let args = TraceArgs {
+            host: "example.com".to_string(),
+            delay: Some(0.01),
+            max_hops: 30,
+            ..Default::default()
+        };
+        let config = TraceConfig::from_args(args).unwrap();
+        assert_eq!(config.packet_count, 5);
+        assert_eq!(config.interval, Duration::from_secs_f32(0.1));
+        assert_eq!(config.max_hops, 30);
+    }
+
+    #[test]
+    fn trace_config_zero_max_hops_returns_error() {
+        let args = TraceArgs {
+            host: "example.com".to_string(),
+            max_hops: 0,
+            ..Default::default()
+        };
+        assert!(TraceConfig::from_args(args).is_err());
+    }
+
+    #[test]
+    fn args_parse_mtr_subcommand_with_multiple_hosts() {
+        let args = Args::parse_from(["mping", "mtr", "-c", "10", "example.com", "1.1.1.1"]);
+        let Some(Command::Mtr(mtr)) = args.command else {
+            panic!("expected mtr subcommand");
+        };
+        let config = MtrConfig::from_args(mtr).unwrap();
+        assert_eq!(config.hosts, vec!["example.com", "1.1.1.1"]);
+        assert_eq!(config.rounds, Some(10));
+        assert_eq!(config.max_hops, 30);
+    }
+
+    #[test]
+    fn mtr_config_without_count_runs_until_interrupted() {
+        let args = MtrArgs {
+            hosts: vec!["example.com".to_string()],
+            max_hops: 30,
+            ..Default::default()
+        };
+        let config = MtrConfig::from_args(args).unwrap();
+        assert_eq!(config.rounds, None);
+    }
+
+    fn serve(args: &[&str]) -> Result<ServeConfig> {
+        let args = Args::try_parse_from(args)?;
+        let Some(Command::Serve(serve)) = args.command else {
+            panic!("expected serve subcommand");
+        };
+        ServeConfig::from_args(serve)
+    }
+
+    #[test]
+    fn args_parse_serve_subcommand() {
+        let config = serve(&["mping", "serve", "db1", "tls://db2"]).unwrap();
+        assert_eq!(config.hosts, vec!["db1", "tls://db2"]);
+        assert_eq!(config.listen.to_string(), DEFAULT_METRICS_ADDR);
+        assert_eq!(config.thresholds.down_after, 3);
+        assert!(config.tags.is_empty());
+
+        let config = serve(&[
+            "mping",
+            "serve",
+            "--listen",
+            "[::]:9000",
+            "--down-after",
+            "5",
+            "--tag",
+            "site=fra1",
+            "--tag",
+            "env=prod",
+            "db1",
+        ])
+        .unwrap();
+        assert_eq!(config.listen.port(), 9000);
+        assert_eq!(config.thresholds.down_after, 5);
+        assert_eq!(config.tags.len(), 2);
+        assert_eq!(config.tags[1].value, "prod");
+    }
+
+    #[test]
+    fn serve_rejects_invalid_tags() {
+        assert!(serve(&["mping", "serve", "--tag", "host=x", "db1"]).is_err());
+        assert!(serve(&["mping", "serve", "--tag", "a=1", "--tag", "a=2", "db1"]).is_err());
+        assert!(Args::try_parse_from(["mping", "serve", "--listen", "localhost", "db1"]).is_err());
+    }
 }
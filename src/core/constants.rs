@@ -1,4 +1,73 @@
+use std::time::Duration;
+
 pub const PERCENTAGE_FACTOR: f64 = 100.0;
 pub const ZERO_THRESHOLD: f32 = 0.0;
 pub const LOSS_TIMEOUT: u8 = 1;
 pub const MILLISECOND_IN_SECOND: u64 = 1000;
+pub const DEFAULT_TLS_PORT: u16 = 443;
+pub const DEFAULT_NTP_PORT: u16 = 123;
+pub const SECONDS_IN_DAY: i64 = 86_400;
+pub const DEFAULT_PAYLOAD_SIZE: usize = 56;
+/// Largest packet size, in bytes, tried by path MTU discovery.
+pub const PMTU_CEILING: usize = 1500;
+/// Number of payload sizes a `--sweep` range is divided into.
+pub const SWEEP_BUCKETS: usize = 5;
+/// Largest ICMP payload that fits into an IPv4 packet.
+pub const MAX_PAYLOAD_SIZE: usize = 65_507;
+/// Attempts made to deliver a webhook before giving up.
+pub const WEBHOOK_ATTEMPTS: u32 = 3;
+/// Delay before the first webhook retry; doubled for every further attempt.
+pub const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Time allowed for a single webhook request, from connect to response status.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Repeats of the same alert for the same host within this window are not sent again.
+pub const WEBHOOK_DEDUP_WINDOW: Duration = Duration::from_secs(300);
+/// Address `mping serve` exposes its metrics on unless `--listen` is given.
+pub const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:9374";
+/// Upper bounds in seconds of the RTT histogram buckets exposed by `mping serve`.  Replies
+/// slower than [`LOSS_TIMEOUT`] count as lost, so larger buckets would stay empty.
+pub const RTT_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+/// Lines for an InfluxDB write endpoint are collected this long and posted together.
+pub const INFLUX_BATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Time allowed for a single InfluxDB write request, from connect to response status.
+pub const INFLUX_TIMEOUT: Duration = Duration::from_secs(10);
+/// Port a `--statsd` endpoint is assumed to listen on when none is given.
+pub const DEFAULT_STATSD_PORT: u16 = 8125;
+/// Port of the Graphite plaintext protocol, used when `--graphite` names no port.
+pub const DEFAULT_GRAPHITE_PORT: u16 = 2003;
+/// Time allowed for connecting to a `--graphite` endpoint or for writing to it.
+pub const GRAPHITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before reconnecting to a `--graphite` endpoint after a failure; doubled for every
+/// further failure up to [`GRAPHITE_MAX_RETRY_DELAY`].  Lines are dropped in the meantime.
+pub const GRAPHITE_RETRY_DELAY: Duration = Duration::from_secs(1);
+pub const GRAPHITE_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Probe events queued for a `--graphite` endpoint; further ones are dropped until it catches
+/// up.
+pub const GRAPHITE_QUEUE: usize = 1_024;
+/// Metrics are pushed to an OTLP collector this often, and once more when the run ends.
+pub const OTLP_EXPORT_INTERVAL: Duration = Duration::from_secs(15);
+/// Time allowed for a single OTLP export request, from connect to response status.
+pub const OTLP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Port of an `mqtt://` broker unless the URL names one.
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+/// Port of an `mqtts://` broker unless the URL names one.
+pub const DEFAULT_MQTTS_PORT: u16 = 8883;
+/// Per-host summaries are published to MQTT this often, and once more when the run ends.  The
+/// summaries also keep the broker connection alive.
+pub const MQTT_SUMMARY_INTERVAL: Duration = Duration::from_secs(30);
+/// Time allowed for connecting to an MQTT broker or for it to acknowledge a message.
+pub const MQTT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Step and number of rows of each round-robin archive level: two days of minutes, 90 days of
+/// hours and three years of days.
+pub const ARCHIVE_LEVELS: [(u64, u32); 3] = [(60, 2_880), (3_600, 2_160), (86_400, 1_095)];
+/// Most time buckets, and so columns of smoke, in an SVG latency graph.
+pub const SVG_COLUMNS: usize = 120;
+/// Most recent probe outcomes kept per target, enough for every probe of a `--count` run
+/// without bursts.  Older ones only count towards the running statistics, so continuous runs
+/// use constant memory.
+pub const SAMPLE_HISTORY: usize = 65_536;
+/// Time an `--on-down` or `--on-up` command may run before it is killed, so a hanging command
+/// cannot hold up the end of the run.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
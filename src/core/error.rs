@@ -0,0 +1,90 @@
+use std::fmt;
+use std::io;
+use surge_ping::SurgeError;
+
+/// Reason a single probe did not produce a sample.
+#[derive(Debug)]
+pub enum ProbeError {
+    Icmp(SurgeError),
+    Io(io::Error),
+    Tls(String),
+    Ntp(String),
+    Timeout { seq: u16 },
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Icmp(e) => write!(f, "{}", e),
+            ProbeError::Io(e) => write!(f, "{}", e),
+            ProbeError::Tls(e) => write!(f, "TLS handshake failed: {}", e),
+            ProbeError::Ntp(e) => write!(f, "invalid NTP response: {}", e),
+            ProbeError::Timeout { seq } => write!(f, "Request timeout for seq {}", seq),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl ProbeError {
+    /// Short machine-readable name of the kind of failure.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProbeError::Icmp(SurgeError::Timeout { .. }) | ProbeError::Timeout { .. } => "timeout",
+            ProbeError::Icmp(SurgeError::IOError(_)) | ProbeError::Io(_) => "io",
+            ProbeError::Icmp(_) => "icmp",
+            ProbeError::Tls(_) => "tls",
+            ProbeError::Ntp(_) => "ntp",
+        }
+    }
+}
+
+/// A probe error reduced to what is passed on in events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeFailure {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<&ProbeError> for ProbeFailure {
+    fn from(e: &ProbeError) -> Self {
+        Self {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<SurgeError> for ProbeError {
+    fn from(e: SurgeError) -> Self {
+        ProbeError::Icmp(e)
+    }
+}
+
+impl From<io::Error> for ProbeError {
+    fn from(e: io::Error) -> Self {
+        ProbeError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surge_ping::PingSequence;
+
+    #[test]
+    fn timeouts_share_a_kind() {
+        let icmp = ProbeError::Icmp(SurgeError::Timeout {
+            seq: PingSequence(3),
+        });
+        assert_eq!(icmp.kind(), "timeout");
+        assert_eq!(ProbeError::Timeout { seq: 3 }.kind(), "timeout");
+    }
+
+    #[test]
+    fn failure_keeps_kind_and_message() {
+        let failure = ProbeFailure::from(&ProbeError::Tls("bad record".to_string()));
+        assert_eq!(failure.kind, "tls");
+        assert_eq!(failure.message, "TLS handshake failed: bad record");
+    }
+}
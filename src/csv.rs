@@ -0,0 +1,190 @@
+//! CSV export of probe outcomes and per-host summaries for spreadsheets and pandas.
+
+use crate::core::constants::PERCENTAGE_FACTOR;
+use crate::display::rfc3339;
+use crate::events::PingEvent;
+use crate::network::ping::PingResults;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+pub const SAMPLE_HEADER: &str = "timestamp,host,addr,seq,outcome,rtt_us,ttl";
+
+/// The columns of the results table, with times in microseconds and without units.
+pub const SUMMARY_HEADER: &str = "host,addr,sent,recv,loss_percent,min_us,max_us,avg_us,\
+                                  offset_us,expiry_days,worst_burst_lost,burst_spread_us";
+
+/// Writes one row per probe outcome while the run is in progress.  Rows are flushed as they are
+/// written, so the file can be followed during long runs.
+#[derive(Debug)]
+pub struct SampleWriter {
+    file: LineWriter<File>,
+}
+
+impl SampleWriter {
+    /// Creates or truncates the file at `path` and writes the header.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = LineWriter::new(File::create(path)?);
+        writeln!(file, "{}", SAMPLE_HEADER)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, event: &PingEvent) -> io::Result<()> {
+        writeln!(self.file, "{}", sample_row(event))
+    }
+}
+
+/// Writes one row per host to the file at `path`.
+pub fn write_summary(path: &Path, results: &[PingResults]) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    writeln!(file, "{}", SUMMARY_HEADER)?;
+    for result in results {
+        writeln!(file, "{}", summary_row(result))?;
+    }
+    file.flush()
+}
+
+/// `outcome` is `ok` for an answered probe, otherwise the kind of error that made it a loss.
+pub fn sample_row(event: &PingEvent) -> String {
+    let PingEvent::Sample {
+        target,
+        seq,
+        sample,
+        failure,
+    } = event;
+    let outcome = match (sample.rtt, failure) {
+        (Some(_), _) => "ok",
+        (None, Some(failure)) => failure.kind,
+        (None, None) => "lost",
+    };
+    [
+        rfc3339(sample.at),
+        field(&target.host_label()),
+        target.addr.to_string(),
+        seq.to_string(),
+        outcome.to_string(),
+        optional(sample.rtt.map(micros)),
+        optional(sample.ttl),
+    ]
+    .join(",")
+}
+
+/// Unknown values are left empty, so they read as missing rather than as text.
+pub fn summary_row(result: &PingResults) -> String {
+    let worst_burst = result.worst_burst();
+    [
+        field(&result.target.host_label()),
+        result.target.addr.to_string(),
+        result.total_count().to_string(),
+        result.num_recv.to_string(),
+        format!("{:.1}", result.loss_rate() * PERCENTAGE_FACTOR as f32),
+        optional(result.min_duration.map(micros)),
+        optional(result.max_duration.map(micros)),
+        optional(result.avg_duration().map(micros)),
+        optional(result.avg_offset()),
+        optional(
+            result
+                .certificate
+                .as_ref()
+                .map(|certificate| certificate.days_to_expiry()),
+        ),
+        optional(worst_burst.map(|burst| burst.lost)),
+        optional(result.avg_burst_spread().map(micros)),
+    ]
+    .join(",")
+}
+
+fn micros(duration: Duration) -> u128 {
+    duration.as_micros()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Quotes a text field if it contains a separator, quote or line break.
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::ProbeFailure;
+    use crate::network::client::{PingTarget, ProbeKind};
+    use crate::network::ping::{PingResponse, Sample};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::UNIX_EPOCH;
+
+    fn event(rtt: Option<Duration>, failure: Option<&'static str>) -> PingEvent {
+        PingEvent::Sample {
+            target: PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            seq: 4,
+            sample: Sample {
+                at: UNIX_EPOCH,
+                rtt,
+                ttl: rtt.map(|_| 57),
+            },
+            failure: failure.map(|kind| ProbeFailure {
+                kind,
+                message: String::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn sample_rows() {
+        assert_eq!(
+            sample_row(&event(Some(Duration::from_micros(1_234)), None)),
+            "1970-01-01T00:00:00.000Z,gw.example.com,10.0.0.1,4,ok,1234,57"
+        );
+        assert_eq!(
+            sample_row(&event(None, Some("timeout"))),
+            "1970-01-01T00:00:00.000Z,gw.example.com,10.0.0.1,4,timeout,,"
+        );
+        assert_eq!(
+            SAMPLE_HEADER.split(',').count(),
+            sample_row(&event(None, None)).split(',').count()
+        );
+    }
+
+    #[test]
+    fn summary_rows() {
+        let mut result = PingResults::new(PingTarget::with_host(
+            "db1".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        ));
+        result.add_received(PingResponse::new(Duration::from_micros(1_000)));
+        result.add_received(PingResponse::new(Duration::from_micros(3_000)));
+        result.add_loss();
+        assert_eq!(
+            summary_row(&result),
+            "db1,10.0.0.2,3,2,33.3,1000,3000,2000,,,,"
+        );
+        assert_eq!(
+            SUMMARY_HEADER.split(',').count(),
+            summary_row(&result).split(',').count()
+        );
+
+        let lost = PingResults::new(
+            PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)))
+                .with_kind(ProbeKind::Ntp { port: 123 }),
+        );
+        assert!(summary_row(&lost).starts_with("ntp://10.0.0.3:123,10.0.0.3,0,0,0.0,,,"));
+    }
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        assert_eq!(field("db1"), "db1");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -1,4 +1,5 @@
-use std::time::Duration;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use std::time::{Duration, SystemTime};
 
 pub trait DurationExt {
     fn display(&self) -> String;
@@ -17,6 +18,39 @@ impl DurationExt for Duration {
     }
 }
 
+/// Formats a signed offset in microseconds, e.g. `+1.50 ms` or `-250.00 μs`.
+pub fn display_offset(micros: i64) -> String {
+    let sign = if micros < 0 { '-' } else { '+' };
+    format!(
+        "{}{}",
+        sign,
+        Duration::from_micros(micros.unsigned_abs()).display()
+    )
+}
+
+/// Formats a bandwidth in bits per second, e.g. `12.34 Mbit/s`.
+pub fn display_bandwidth(bits_per_second: f64) -> String {
+    match bits_per_second {
+        b if b >= 1e9 => format!("{:.2} Gbit/s", b / 1e9),
+        b if b >= 1e6 => format!("{:.2} Mbit/s", b / 1e6),
+        b if b >= 1e3 => format!("{:.2} kbit/s", b / 1e3),
+        b => format!("{:.2} bit/s", b),
+    }
+}
+
+/// Formats a point in time as local date and time, e.g. `2024-05-01 14:03:27`.
+pub fn display_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Formats a point in time as an RFC 3339 UTC timestamp with milliseconds, e.g.
+/// `2024-05-01T12:03:27.125Z`, for machine-readable output.
+pub fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,4 +144,32 @@ mod tests {
         let d = Duration::from_nanos(500);
         assert_eq!(d.display(), "0.00 ns");
     }
+
+    #[test]
+    fn display_positive_offset() {
+        assert_eq!(display_offset(1500), "+1.50 ms");
+    }
+
+    #[test]
+    fn display_negative_offset() {
+        assert_eq!(display_offset(-250), "-250.00 μs");
+    }
+
+    #[test]
+    fn display_zero_offset() {
+        assert_eq!(display_offset(0), "+0.00 ns");
+    }
+
+    #[test]
+    fn display_bandwidth_picks_unit() {
+        assert_eq!(display_bandwidth(950.0), "950.00 bit/s");
+        assert_eq!(display_bandwidth(12_340_000.0), "12.34 Mbit/s");
+        assert_eq!(display_bandwidth(1e9), "1.00 Gbit/s");
+    }
+
+    #[test]
+    fn rfc3339_is_utc_with_milliseconds() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_125);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20.125Z");
+    }
 }
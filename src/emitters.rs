@@ -0,0 +1,425 @@
+//! StatsD and Graphite plaintext emitters for per-probe RTT timings and loss counters.
+
+use crate::core::constants::{
+    DEFAULT_GRAPHITE_PORT, DEFAULT_STATSD_PORT, GRAPHITE_MAX_RETRY_DELAY, GRAPHITE_RETRY_DELAY,
+    GRAPHITE_TIMEOUT,
+};
+use crate::events::{EventReceiver, PingEvent};
+use crate::network::client::PingTarget;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// A `HOST[:PORT]` endpoint; IPv6 addresses go in brackets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Endpoint {
+    fn address(&self, default_port: u16) -> (&str, u16) {
+        (self.host.as_str(), self.port.unwrap_or(default_port))
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = if let Some(bracketed) = s.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("unterminated IPv6 address in '{}'", s))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match s.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (s, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", s));
+        }
+        let port = port
+            .map(|port| {
+                port.parse()
+                    .map_err(|_| format!("invalid port '{}' in '{}'", port, s))
+            })
+            .transpose()?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            Some(port) => write!(f, "{}:{}", host, port),
+            None => write!(f, "{}", host),
+        }
+    }
+}
+
+/// Metric path prefix with `{host}` standing for the target, e.g. `net.ping.{host}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricPrefix(String);
+
+impl Default for MetricPrefix {
+    fn default() -> Self {
+        Self("mping.{host}".to_string())
+    }
+}
+
+impl FromStr for MetricPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.starts_with('.') || s.ends_with('.') {
+            return Err(format!("invalid metric prefix '{}'", s));
+        }
+        if s.contains([' ', ':', '|', '@']) {
+            return Err(format!(
+                "metric prefix '{}' may not contain spaces, ':', '|' or '@'",
+                s
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl MetricPrefix {
+    /// The prefix for `target`.  The host name, or the address if there is none, becomes a
+    /// single path component with dots replaced, e.g. `db1_example_com`; probes other than ICMP
+    /// get their kind and port appended, e.g. `db1_example_com_tls_443`.
+    pub fn for_target(&self, target: &PingTarget) -> String {
+        let host = target
+            .name()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        if self.0.contains("{host}") {
+            self.0.replace("{host}", &host)
+        } else {
+            format!("{}.{}", self.0, host)
+        }
+    }
+}
+
+/// StatsD lines for one probe: a `sent` counter, a `lost` counter for lost probes and an `rtt`
+/// timing for answered ones.
+pub fn statsd_lines(event: &PingEvent, prefix: &MetricPrefix) -> Vec<String> {
+    let PingEvent::Sample { target, sample, .. } = event;
+    let prefix = prefix.for_target(target);
+    let mut lines = vec![format!("{}.sent:1|c", prefix)];
+    match sample.rtt {
+        Some(rtt) => lines.push(format!("{}.rtt:{}|ms", prefix, millis(rtt))),
+        None => lines.push(format!("{}.lost:1|c", prefix)),
+    }
+    lines
+}
+
+/// Graphite plaintext lines for one probe: `lost` as 0 or 1, so sums count lost probes, and
+/// `rtt_ms` for answered ones.
+pub fn graphite_lines(event: &PingEvent, prefix: &MetricPrefix) -> Vec<String> {
+    let PingEvent::Sample { target, sample, .. } = event;
+    let prefix = prefix.for_target(target);
+    let at = sample
+        .at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut lines = vec![format!(
+        "{}.lost {} {}",
+        prefix,
+        u8::from(sample.rtt.is_none()),
+        at
+    )];
+    if let Some(rtt) = sample.rtt {
+        lines.push(format!("{}.rtt_ms {} {}", prefix, millis(rtt), at));
+    }
+    lines
+}
+
+/// Opens a UDP socket sending to the StatsD `endpoint`.
+pub async fn connect_statsd(endpoint: &Endpoint) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(match endpoint.host.parse::<IpAddr>() {
+        Ok(addr) if addr.is_ipv6() => "[::]:0",
+        _ => "0.0.0.0:0",
+    })
+    .await?;
+    socket
+        .connect(endpoint.address(DEFAULT_STATSD_PORT))
+        .await?;
+    Ok(socket)
+}
+
+/// Sends one StatsD packet per probe event until the channel is closed.  Lost packets are not
+/// noticed; that is the nature of StatsD.
+pub async fn run_statsd(mut events: EventReceiver, socket: UdpSocket, prefix: MetricPrefix) {
+    let mut failed = false;
+    while let Some(event) = events.recv().await {
+        let packet = statsd_lines(&event, &prefix).join("\n");
+        match socket.send(packet.as_bytes()).await {
+            Ok(_) => failed = false,
+            // E.g. ICMP port unreachable for an earlier packet; reported once per streak
+            Err(e) if !failed => {
+                eprintln!("StatsD output failed: {}", e);
+                failed = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Writes Graphite plaintext lines for every probe event until the channel is closed.  The
+/// connection is opened on demand and reopened after errors, waiting longer after every failure;
+/// lines of events that arrive in the meantime, or that cannot be written, are dropped.
+pub async fn run_graphite(
+    mut events: mpsc::Receiver<PingEvent>,
+    endpoint: Endpoint,
+    prefix: MetricPrefix,
+) {
+    let mut connection: Option<TcpStream> = None;
+    let mut retry_delay = GRAPHITE_RETRY_DELAY;
+    let mut retry_at: Option<Instant> = None;
+    let mut failed = false;
+    while let Some(event) = events.recv().await {
+        if connection.is_none() && retry_at.is_some_and(|at| Instant::now() < at) {
+            continue;
+        }
+        let mut payload = graphite_lines(&event, &prefix).join("\n");
+        payload.push('\n');
+
+        let result = async {
+            if connection.is_none() {
+                let stream = within(
+                    TcpStream::connect(endpoint.address(DEFAULT_GRAPHITE_PORT)),
+                    "connect",
+                )
+                .await?;
+                connection = Some(stream);
+            }
+            let stream = connection.as_mut().expect("connected above");
+            within(stream.write_all(payload.as_bytes()), "write").await
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                failed = false;
+                retry_delay = GRAPHITE_RETRY_DELAY;
+                retry_at = None;
+            }
+            Err(e) => {
+                connection = None;
+                retry_at = Some(Instant::now() + retry_delay);
+                retry_delay = (retry_delay * 2).min(GRAPHITE_MAX_RETRY_DELAY);
+                if !failed {
+                    eprintln!("Graphite output to {} failed: {}", endpoint, e);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if let Some(mut stream) = connection {
+        let _ = within(stream.shutdown(), "shutdown").await;
+    }
+}
+
+/// Runs `io` for at most [`GRAPHITE_TIMEOUT`].
+async fn within<T>(io: impl Future<Output = io::Result<T>>, what: &str) -> io::Result<T> {
+    tokio::time::timeout(GRAPHITE_TIMEOUT, io)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what)))?
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::constants::GRAPHITE_QUEUE;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::Sample;
+    use std::net::Ipv4Addr;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn target() -> PingTarget {
+        PingTarget::with_host(
+            "db1.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        )
+    }
+
+    fn event(rtt_us: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: target(),
+            seq: 0,
+            sample: Sample {
+                at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                rtt: rtt_us.map(Duration::from_micros),
+                ttl: None,
+            },
+            failure: None,
+        }
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        assert_eq!(
+            "localhost:8125".parse(),
+            Ok(Endpoint {
+                host: "localhost".to_string(),
+                port: Some(8125),
+            })
+        );
+        assert_eq!("graphite".parse::<Endpoint>().unwrap().port, None);
+        assert_eq!("[::1]:2003".parse::<Endpoint>().unwrap().host, "::1");
+        assert_eq!("::1".parse::<Endpoint>().unwrap().port, None);
+        assert!("localhost:statsd".parse::<Endpoint>().is_err());
+        assert!(":8125".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn prefix_derives_host_component() {
+        let prefix = MetricPrefix::default();
+        assert_eq!(prefix.for_target(&target()), "mping.db1_example_com");
+
+        let tls = PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+            .with_kind(ProbeKind::Tls { port: 443 });
+        assert_eq!(prefix.for_target(&tls), "mping.10_0_0_2_tls_443");
+
+        let prefix = "net.{host}.ping".parse::<MetricPrefix>().unwrap();
+        assert_eq!(prefix.for_target(&target()), "net.db1_example_com.ping");
+        let prefix = "net".parse::<MetricPrefix>().unwrap();
+        assert_eq!(prefix.for_target(&target()), "net.db1_example_com");
+
+        assert!("".parse::<MetricPrefix>().is_err());
+        assert!("net.".parse::<MetricPrefix>().is_err());
+        assert!("net ping".parse::<MetricPrefix>().is_err());
+    }
+
+    #[test]
+    fn statsd_and_graphite_lines() {
+        let prefix = MetricPrefix::default();
+        assert_eq!(
+            statsd_lines(&event(Some(1_500)), &prefix),
+            vec![
+                "mping.db1_example_com.sent:1|c",
+                "mping.db1_example_com.rtt:1.5|ms"
+            ]
+        );
+        assert_eq!(
+            statsd_lines(&event(None), &prefix),
+            vec![
+                "mping.db1_example_com.sent:1|c",
+                "mping.db1_example_com.lost:1|c"
+            ]
+        );
+        assert_eq!(
+            graphite_lines(&event(Some(1_500)), &prefix),
+            vec![
+                "mping.db1_example_com.lost 0 1700000000",
+                "mping.db1_example_com.rtt_ms 1.5 1700000000"
+            ]
+        );
+        assert_eq!(
+            graphite_lines(&event(None), &prefix),
+            vec!["mping.db1_example_com.lost 1 1700000000"]
+        );
+    }
+
+    #[tokio::test]
+    async fn statsd_sends_one_packet_per_probe() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("127.0.0.1:{}", receiver.local_addr().unwrap().port())
+            .parse()
+            .unwrap();
+        let socket = connect_statsd(&endpoint).await.unwrap();
+        let (events, received) = mpsc::unbounded_channel();
+        events.send(event(Some(2_000))).unwrap();
+        events.send(event(None)).unwrap();
+        drop(events);
+        run_statsd(received, socket, MetricPrefix::default()).await;
+
+        let mut buf = [0; 512];
+        let n = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            &buf[..n],
+            b"mping.db1_example_com.sent:1|c\nmping.db1_example_com.rtt:2|ms"
+        );
+        let n = receiver.recv(&mut buf).await.unwrap();
+        assert!(buf[..n].ends_with(b".lost:1|c"));
+    }
+
+    #[tokio::test]
+    async fn graphite_writes_lines_over_one_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            received
+        });
+
+        let (events, received) = mpsc::channel(4);
+        events.try_send(event(Some(2_000))).unwrap();
+        events.try_send(event(None)).unwrap();
+        drop(events);
+        run_graphite(received, endpoint, "net".parse().unwrap()).await;
+
+        assert_eq!(
+            server.await.unwrap(),
+            "net.db1_example_com.lost 0 1700000000\n\
+             net.db1_example_com.rtt_ms 2 1700000000\n\
+             net.db1_example_com.lost 1 1700000000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn graphite_drops_lines_while_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let endpoint = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        let (events, received) = mpsc::channel(GRAPHITE_QUEUE);
+        let sink = tokio::spawn(run_graphite(received, endpoint, MetricPrefix::default()));
+        for _ in 0..GRAPHITE_QUEUE {
+            events.send(event(Some(1_000))).await.unwrap();
+        }
+        drop(events);
+        // Only the first event tries to connect, the rest fall into the retry delay
+        tokio::time::timeout(GRAPHITE_RETRY_DELAY, sink)
+            .await
+            .expect("lines are dropped without reconnecting")
+            .unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+//! Live probe events and the host state changes derived from them.
+
+use crate::core::error::ProbeFailure;
+use crate::network::client::PingTarget;
+use crate::network::ping::Sample;
+use crate::state::{HostState, HostTracker, StateThresholds, Transition};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Number of most recent samples summarized in a [`StateChange`].
+pub const RECENT_SAMPLES: usize = 10;
+
+#[derive(Debug, Clone)]
+pub enum PingEvent {
+    /// A probe was answered or given up as lost.
+    Sample {
+        target: PingTarget,
+        /// Position of the probe in the run of `target`, starting at 0.
+        seq: u64,
+        sample: Sample,
+        /// Why the probe was lost, if it was.
+        failure: Option<ProbeFailure>,
+    },
+}
+
+pub type EventSender = mpsc::UnboundedSender<PingEvent>;
+pub type EventReceiver = mpsc::UnboundedReceiver<PingEvent>;
+
+/// Where the dispatcher passes every probe event on to a sink.
+#[derive(Debug, Clone)]
+pub enum Tap {
+    /// Gets every event, however far the sink falls behind.
+    Unbounded(EventSender),
+    /// Drops events while the queue of the sink is full, for sinks that may stall.
+    Bounded(mpsc::Sender<PingEvent>),
+}
+
+impl Tap {
+    pub fn send(&self, event: PingEvent) {
+        match self {
+            Tap::Unbounded(sender) => {
+                let _ = sender.send(event);
+            }
+            Tap::Bounded(sender) => {
+                let _ = sender.try_send(event);
+            }
+        }
+    }
+}
+
+/// A host changed state, together with what led up to it.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub target: PingTarget,
+    pub transition: Transition,
+    /// For changes into or out of `Down`: time since the first lost probe of the outage.
+    pub outage: Option<Duration>,
+    /// Loss rate over the last [`RECENT_SAMPLES`] probes, from 0.0 to 1.0.
+    pub recent_loss: f64,
+    /// Mean RTT over the answered probes among the last [`RECENT_SAMPLES`].
+    pub recent_rtt: Option<Duration>,
+}
+
+/// Loss and RTT over the last [`RECENT_SAMPLES`] probes of a host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecentStats {
+    /// Number of probes covered, less than [`RECENT_SAMPLES`] early in the run.
+    pub probes: usize,
+    /// Loss rate from 0.0 to 1.0.
+    pub loss: f64,
+    /// Mean RTT over the answered probes.
+    pub rtt: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct HostWatch {
+    tracker: HostTracker,
+    recent: VecDeque<Sample>,
+}
+
+impl HostWatch {
+    fn recent_stats(&self) -> RecentStats {
+        let lost = self.recent.iter().filter(|s| s.rtt.is_none()).count();
+        let rtts = self.recent.iter().filter_map(|s| s.rtt).collect::<Vec<_>>();
+        RecentStats {
+            probes: self.recent.len(),
+            loss: lost as f64 / self.recent.len().max(1) as f64,
+            rtt: (!rtts.is_empty()).then(|| rtts.iter().sum::<Duration>() / rtts.len() as u32),
+        }
+    }
+}
+
+/// Follows the state of every host seen in the event stream.
+#[derive(Debug)]
+pub struct StateWatcher {
+    thresholds: StateThresholds,
+    hosts: HashMap<String, HostWatch>,
+}
+
+impl StateWatcher {
+    pub fn new(thresholds: StateThresholds) -> Self {
+        Self {
+            thresholds,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Feeds one event through the state machine of its host.
+    pub fn observe(&mut self, event: &PingEvent) -> Option<StateChange> {
+        let PingEvent::Sample { target, sample, .. } = event;
+        let host = self.hosts.entry(target.key()).or_insert_with(|| HostWatch {
+            tracker: HostTracker::new(self.thresholds),
+            recent: VecDeque::with_capacity(RECENT_SAMPLES),
+        });
+
+        if host.recent.len() == RECENT_SAMPLES {
+            host.recent.pop_front();
+        }
+        host.recent.push_back(*sample);
+
+        let transition = host.tracker.record(sample.at, sample.rtt.is_some())?;
+        let involves_down = transition.from == HostState::Down || transition.to == HostState::Down;
+        let outage = host
+            .tracker
+            .outages()
+            .last()
+            .filter(|_| involves_down)
+            .map(|outage| outage.duration(sample.at));
+
+        let recent = host.recent_stats();
+        Some(StateChange {
+            target: target.clone(),
+            transition,
+            outage,
+            recent_loss: recent.loss,
+            recent_rtt: recent.rtt,
+        })
+    }
+
+    /// Loss and RTT over the latest probes of `target`, `None` before its first probe.
+    pub fn recent(&self, target: &PingTarget) -> Option<RecentStats> {
+        self.hosts.get(&target.key()).map(HostWatch::recent_stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn sample(secs: u64, rtt_ms: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            seq: secs,
+            sample: Sample {
+                at: UNIX_EPOCH + Duration::from_secs(secs),
+                rtt: rtt_ms.map(Duration::from_millis),
+                ttl: None,
+            },
+            failure: None,
+        }
+    }
+
+    fn changes(events: &[PingEvent]) -> Vec<StateChange> {
+        let mut watcher = StateWatcher::new(StateThresholds::default());
+        events.iter().filter_map(|e| watcher.observe(e)).collect()
+    }
+
+    #[test]
+    fn reports_down_with_time_since_first_loss() {
+        let changes = changes(&[
+            sample(0, Some(10)),
+            sample(1, None),
+            sample(2, None),
+            sample(3, None),
+        ]);
+        let down = changes.last().unwrap();
+        assert_eq!(down.transition.to, HostState::Down);
+        assert_eq!(down.outage, Some(Duration::from_secs(2)));
+        assert_eq!(down.recent_loss, 0.75);
+        assert_eq!(down.recent_rtt, Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn reports_recovery_with_outage_duration() {
+        let changes = changes(&[
+            sample(0, None),
+            sample(1, None),
+            sample(2, None),
+            sample(7, Some(20)),
+        ]);
+        let up = changes.last().unwrap();
+        assert_eq!(
+            (up.transition.from, up.transition.to),
+            (HostState::Down, HostState::Up)
+        );
+        assert_eq!(up.outage, Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn degraded_changes_carry_no_outage() {
+        let changes = changes(&[sample(0, Some(10)), sample(1, None)]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].transition.to, HostState::Degraded);
+        assert_eq!(changes[0].outage, None);
+    }
+
+    #[test]
+    fn recent_stats_cover_the_latest_probes() {
+        let mut watcher = StateWatcher::new(StateThresholds::default());
+        let target = PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(watcher.recent(&target), None);
+
+        watcher.observe(&sample(0, None));
+        for secs in 1..=RECENT_SAMPLES as u64 {
+            watcher.observe(&sample(secs, Some(12)));
+        }
+        watcher.observe(&sample(11, None));
+
+        assert_eq!(
+            watcher.recent(&target),
+            Some(RecentStats {
+                probes: RECENT_SAMPLES,
+                loss: 0.1,
+                rtt: Some(Duration::from_millis(12)),
+            })
+        );
+    }
+
+    #[test]
+    fn hosts_are_tracked_separately() {
+        let mut watcher = StateWatcher::new(StateThresholds::default());
+        let other = PingEvent::Sample {
+            target: PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+            seq: 0,
+            sample: Sample {
+                at: SystemTime::now(),
+                rtt: None,
+                ttl: None,
+            },
+            failure: None,
+        };
+        assert!(watcher.observe(&sample(0, None)).is_some());
+        assert!(watcher.observe(&other).is_some());
+    }
+}
@@ -0,0 +1,296 @@
+//! Pass/fail criteria for a run and the exit code that reports the outcome to scripts.
+
+use crate::core::constants::PERCENTAGE_FACTOR;
+use crate::display::DurationExt;
+use crate::network::client::PingTarget;
+use crate::network::ping::PingResults;
+use crate::stats::OverallStats;
+use std::fmt;
+use std::time::Duration;
+
+/// Exit code for errors that kept the run from completing, e.g. invalid arguments.
+pub const EXIT_RUNTIME_ERROR: u8 = 3;
+
+/// Limits a run must stay within to count as healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HealthCriteria {
+    /// Packet loss per host in percent.
+    pub max_loss: Option<f64>,
+    pub max_avg_rtt: Option<Duration>,
+    /// Limit for the slowest reply of each host.
+    pub max_rtt: Option<Duration>,
+    /// Hosts that must answer at least one probe.  Without it, all of them if a loss or RTT
+    /// limit is given and none otherwise, so a plain run keeps exiting with 0.
+    pub min_alive: Option<usize>,
+}
+
+/// A host that answered but exceeded a limit.
+#[derive(Debug, Clone)]
+pub enum Breach {
+    Loss {
+        target: PingTarget,
+        loss_percent: f64,
+        limit: f64,
+    },
+    AvgRtt {
+        target: PingTarget,
+        rtt: Duration,
+        limit: Duration,
+    },
+    MaxRtt {
+        target: PingTarget,
+        rtt: Duration,
+        limit: Duration,
+    },
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breach::Loss {
+                target,
+                loss_percent,
+                limit,
+            } => write!(
+                f,
+                "{} loss {:.2} % exceeds {} %",
+                target, loss_percent, limit
+            ),
+            Breach::AvgRtt { target, rtt, limit } => write!(
+                f,
+                "{} average RTT {} exceeds {}",
+                target,
+                rtt.display(),
+                limit.display()
+            ),
+            Breach::MaxRtt { target, rtt, limit } => write!(
+                f,
+                "{} maximum RTT {} exceeds {}",
+                target,
+                rtt.display(),
+                limit.display()
+            ),
+        }
+    }
+}
+
+/// Overall outcome of a run, in increasing order of severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    /// Enough hosts answered and all stayed within the limits.
+    Healthy,
+    /// Enough hosts answered, but some exceeded a loss or RTT limit.
+    Breached,
+    /// Fewer hosts answered than required.
+    Unreachable,
+}
+
+impl Verdict {
+    /// Process exit code: 0 healthy, 1 limits breached, 2 hosts unreachable.  Runtime errors
+    /// exit with [`EXIT_RUNTIME_ERROR`].
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Verdict::Healthy => 0,
+            Verdict::Breached => 1,
+            Verdict::Unreachable => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub breaches: Vec<Breach>,
+    /// Hosts that did not answer any probe.
+    pub unreachable: Vec<PingTarget>,
+    pub alive: usize,
+    pub required_alive: usize,
+}
+
+impl HealthReport {
+    pub fn verdict(&self) -> Verdict {
+        if self.alive < self.required_alive {
+            Verdict::Unreachable
+        } else if !self.breaches.is_empty() {
+            Verdict::Breached
+        } else {
+            Verdict::Healthy
+        }
+    }
+}
+
+impl HealthCriteria {
+    fn has_limits(&self) -> bool {
+        self.max_loss.is_some() || self.max_avg_rtt.is_some() || self.max_rtt.is_some()
+    }
+
+    /// Checks `results` against the criteria.  `expected_hosts` is the number of hosts given on
+    /// the command line, so hosts that could not even be resolved count as unreachable.  Loss
+    /// and RTT limits only apply to hosts that answered; the others are covered by
+    /// `min_alive`.
+    pub fn evaluate(
+        &self,
+        results: &[PingResults],
+        overall: &OverallStats,
+        expected_hosts: usize,
+    ) -> HealthReport {
+        let mut breaches = Vec::new();
+        for result in results.iter().filter(|r| r.num_recv > 0) {
+            let target = &result.target;
+            let loss_percent = result.loss_rate() as f64 * PERCENTAGE_FACTOR;
+            if let Some(limit) = self.max_loss
+                && loss_percent > limit
+            {
+                breaches.push(Breach::Loss {
+                    target: target.clone(),
+                    loss_percent,
+                    limit,
+                });
+            }
+            if let (Some(limit), Some(rtt)) = (self.max_avg_rtt, result.avg_duration())
+                && rtt > limit
+            {
+                breaches.push(Breach::AvgRtt {
+                    target: target.clone(),
+                    rtt,
+                    limit,
+                });
+            }
+            if let (Some(limit), Some(rtt)) = (self.max_rtt, result.max_duration)
+                && rtt > limit
+            {
+                breaches.push(Breach::MaxRtt {
+                    target: target.clone(),
+                    rtt,
+                    limit,
+                });
+            }
+        }
+
+        HealthReport {
+            breaches,
+            unreachable: results
+                .iter()
+                .filter(|r| r.num_recv == 0)
+                .map(|r| r.target.clone())
+                .collect(),
+            alive: overall.hosts_alive,
+            required_alive: self.min_alive.unwrap_or(if self.has_limits() {
+                expected_hosts
+            } else {
+                0
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::ping::PingResponse;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn results(last_octet: u8, rtts_ms: &[Option<u64>]) -> PingResults {
+        let target = PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet)));
+        let mut results = PingResults::new(target);
+        for rtt in rtts_ms {
+            match rtt {
+                Some(ms) => results.add_received(PingResponse::new(Duration::from_millis(*ms))),
+                None => results.add_loss(),
+            }
+        }
+        results
+    }
+
+    fn evaluate(criteria: HealthCriteria, results: &[PingResults]) -> HealthReport {
+        criteria.evaluate(results, &OverallStats::from_results(results), results.len())
+    }
+
+    #[test]
+    fn healthy_without_criteria_when_all_hosts_answer() {
+        let report = evaluate(
+            HealthCriteria::default(),
+            &[results(1, &[Some(10), None]), results(2, &[Some(20)])],
+        );
+        assert_eq!(report.verdict(), Verdict::Healthy);
+        assert_eq!(report.verdict().exit_code(), 0);
+    }
+
+    #[test]
+    fn silent_hosts_pass_without_criteria() {
+        let report = evaluate(
+            HealthCriteria::default(),
+            &[results(1, &[Some(10)]), results(2, &[None])],
+        );
+        assert_eq!(report.unreachable.len(), 1);
+        assert_eq!(report.verdict(), Verdict::Healthy);
+    }
+
+    #[test]
+    fn each_exceeded_limit_is_a_breach() {
+        let criteria = HealthCriteria {
+            max_loss: Some(10.0),
+            max_avg_rtt: Some(Duration::from_millis(50)),
+            max_rtt: Some(Duration::from_millis(100)),
+            min_alive: None,
+        };
+        let report = evaluate(
+            criteria,
+            &[
+                results(1, &[Some(10), None]),
+                results(2, &[Some(60), Some(60)]),
+                results(3, &[Some(10), Some(150), Some(10)]),
+            ],
+        );
+        assert_eq!(report.breaches.len(), 4);
+        assert!(matches!(report.breaches[0], Breach::Loss { .. }));
+        assert!(matches!(report.breaches[1], Breach::AvgRtt { .. }));
+        assert!(matches!(report.breaches[2], Breach::AvgRtt { .. }));
+        assert!(matches!(report.breaches[3], Breach::MaxRtt { .. }));
+        assert_eq!(report.verdict().exit_code(), 1);
+    }
+
+    #[test]
+    fn unreachable_host_fails_by_default() {
+        let criteria = HealthCriteria {
+            max_loss: Some(10.0),
+            ..Default::default()
+        };
+        let report = evaluate(criteria, &[results(1, &[Some(10)]), results(2, &[None])]);
+        // The silent host is reported as unreachable, not as a loss breach
+        assert!(report.breaches.is_empty());
+        assert_eq!(report.unreachable.len(), 1);
+        assert_eq!(report.verdict(), Verdict::Unreachable);
+        assert_eq!(report.verdict().exit_code(), 2);
+    }
+
+    #[test]
+    fn min_alive_tolerates_unreachable_hosts() {
+        let criteria = HealthCriteria {
+            min_alive: Some(1),
+            ..Default::default()
+        };
+        let report = evaluate(criteria, &[results(1, &[Some(10)]), results(2, &[None])]);
+        assert_eq!(report.verdict(), Verdict::Healthy);
+    }
+
+    #[test]
+    fn unresolved_hosts_count_as_unreachable() {
+        let results = [results(1, &[Some(10)])];
+        let criteria = HealthCriteria {
+            max_loss: Some(10.0),
+            ..Default::default()
+        };
+        let report = criteria.evaluate(&results, &OverallStats::from_results(&results), 2);
+        assert_eq!(report.verdict(), Verdict::Unreachable);
+    }
+
+    #[test]
+    fn breach_display() {
+        let breach = Breach::Loss {
+            target: PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+            loss_percent: 20.0,
+            limit: 5.0,
+        };
+        assert_eq!(breach.to_string(), "10.0.0.1 loss 20.00 % exceeds 5 %");
+    }
+}
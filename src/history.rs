@@ -0,0 +1,606 @@
+//! SQLite store of every run, target and probe outcome for `--db`, and the queries behind
+//! `mping history`.
+//!
+//! The schema is a stable interface for ad hoc SQL; changes bump [`SCHEMA_VERSION`], which is
+//! kept in `PRAGMA user_version`.  Times are Unix milliseconds, RTTs microseconds.
+//!
+//! ```sql
+//! CREATE TABLE runs (
+//!     id            INTEGER PRIMARY KEY,
+//!     started_ms    INTEGER NOT NULL,
+//!     finished_ms   INTEGER,          -- NULL while running or if mping was killed
+//!     mping_version TEXT NOT NULL,
+//!     command_line  TEXT NOT NULL
+//! );
+//! CREATE TABLE targets (
+//!     id     INTEGER PRIMARY KEY,
+//!     run_id INTEGER NOT NULL REFERENCES runs(id),
+//!     host   TEXT,                    -- NULL if unknown
+//!     addr   TEXT NOT NULL,
+//!     probe  TEXT NOT NULL,           -- icmp, tls or ntp
+//!     port   INTEGER                  -- NULL for icmp
+//! );
+//! CREATE TABLE samples (
+//!     target_id INTEGER NOT NULL REFERENCES targets(id),
+//!     seq       INTEGER NOT NULL,
+//!     at_ms     INTEGER NOT NULL,
+//!     rtt_us    INTEGER,              -- NULL for a lost probe
+//!     ttl       INTEGER,
+//!     error     TEXT                  -- kind of error that lost the probe, if known
+//! );
+//! ```
+
+use crate::events::{EventReceiver, PingEvent};
+use crate::network::client::PingTarget;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// Why the history database could not be opened or read.
+#[derive(Debug)]
+pub enum HistoryError {
+    Sqlite(rusqlite::Error),
+    /// The database was written by a newer mping with an incompatible schema.
+    SchemaVersion {
+        found: i64,
+        expected: i64,
+    },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Sqlite(e) => write!(f, "{}", e),
+            HistoryError::SchemaVersion { found, expected } => write!(
+                f,
+                "database user_version {} is newer than {} supported by this mping",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<rusqlite::Error> for HistoryError {
+    fn from(e: rusqlite::Error) -> Self {
+        HistoryError::Sqlite(e)
+    }
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id            INTEGER PRIMARY KEY,
+        started_ms    INTEGER NOT NULL,
+        finished_ms   INTEGER,
+        mping_version TEXT NOT NULL,
+        command_line  TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS targets (
+        id     INTEGER PRIMARY KEY,
+        run_id INTEGER NOT NULL REFERENCES runs(id),
+        host   TEXT,
+        addr   TEXT NOT NULL,
+        probe  TEXT NOT NULL,
+        port   INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS samples (
+        target_id INTEGER NOT NULL REFERENCES targets(id),
+        seq       INTEGER NOT NULL,
+        at_ms     INTEGER NOT NULL,
+        rtt_us    INTEGER,
+        ttl       INTEGER,
+        error     TEXT
+    );
+    CREATE INDEX IF NOT EXISTS targets_by_name ON targets(host, addr);
+    CREATE INDEX IF NOT EXISTS samples_by_time ON samples(target_id, at_ms);
+";
+
+/// A database opened for recording one run.
+#[derive(Debug)]
+pub struct HistoryStore {
+    connection: Connection,
+    run_id: i64,
+    targets: HashMap<String, i64>,
+}
+
+impl HistoryStore {
+    /// Opens or creates the database at `path` and records the start of a run probing
+    /// `targets`.
+    pub fn create(
+        path: &Path,
+        targets: &[PingTarget],
+        command_line: &str,
+    ) -> Result<Self, HistoryError> {
+        let mut connection = open(path)?;
+        let tx = connection.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (started_ms, mping_version, command_line) VALUES (?1, ?2, ?3)",
+            params![
+                millis(SystemTime::now()),
+                env!("CARGO_PKG_VERSION"),
+                command_line
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+        let mut ids = HashMap::new();
+        for target in targets {
+            tx.execute(
+                "INSERT INTO targets (run_id, host, addr, probe, port) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    run_id,
+                    target.host,
+                    target.addr.to_string(),
+                    target.kind.scheme().unwrap_or("icmp"),
+                    target.kind.port(),
+                ],
+            )?;
+            ids.insert(target.key(), tx.last_insert_rowid());
+        }
+        tx.commit()?;
+
+        Ok(Self {
+            connection,
+            run_id,
+            targets: ids,
+        })
+    }
+
+    /// Stores a batch of probe outcomes in one transaction.  Events of unregistered targets are
+    /// ignored.
+    pub fn record(&mut self, events: &[PingEvent]) -> rusqlite::Result<()> {
+        let tx = self.connection.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO samples (target_id, seq, at_ms, rtt_us, ttl, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for event in events {
+                let PingEvent::Sample {
+                    target,
+                    seq,
+                    sample,
+                    failure,
+                } = event;
+                let Some(target_id) = self.targets.get(&target.key()) else {
+                    continue;
+                };
+                insert.execute(params![
+                    target_id,
+                    seq,
+                    millis(sample.at),
+                    sample.rtt.map(|rtt| rtt.as_micros() as i64),
+                    sample.ttl,
+                    failure.as_ref().map(|failure| failure.kind),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Marks the run as finished.
+    pub fn finish(&self) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE runs SET finished_ms = ?1 WHERE id = ?2",
+            params![millis(SystemTime::now()), self.run_id],
+        )?;
+        Ok(())
+    }
+}
+
+/// Stores probe events until the channel is closed, then marks the run as finished.  Blocks, so
+/// it runs on a thread of its own; events that arrive while a batch is written go into the
+/// next.  Recording stops at the first error.
+pub fn run(mut events: EventReceiver, mut store: HistoryStore) {
+    while let Some(event) = events.blocking_recv() {
+        let mut batch = vec![event];
+        while let Ok(event) = events.try_recv() {
+            batch.push(event);
+        }
+        if let Err(e) = store.record(&batch) {
+            eprintln!("Database recording stopped: {}", e);
+            // Keep draining, so the dispatcher is not left with a dead tap
+            while events.blocking_recv().is_some() {}
+            return;
+        }
+    }
+    if let Err(e) = store.finish() {
+        eprintln!("Database recording stopped: {}", e);
+    }
+}
+
+/// One end of the time range of `mping history`: a time ago like `90m`, `6h` or `7d`, or a
+/// local date and time like `2024-05-14` or `2024-05-14 13:30`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    Ago(Duration),
+    At(SystemTime),
+}
+
+impl TimeBound {
+    pub fn resolve(self, now: SystemTime) -> SystemTime {
+        match self {
+            TimeBound::Ago(duration) => now.checked_sub(duration).unwrap_or(UNIX_EPOCH),
+            TimeBound::At(time) => time,
+        }
+    }
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unit = match s.chars().last() {
+            Some('s') => Some(1),
+            Some('m') => Some(60),
+            Some('h') => Some(3_600),
+            Some('d') => Some(86_400),
+            Some('w') => Some(7 * 86_400),
+            _ => None,
+        };
+        if let Some(unit) = unit
+            && let Ok(count) = s[..s.len() - 1].parse::<u64>()
+        {
+            return count
+                .checked_mul(unit)
+                .map(|secs| TimeBound::Ago(Duration::from_secs(secs)))
+                .ok_or_else(|| format!("'{}' is too far back", s));
+        }
+
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeBound::At(time.into()));
+        }
+        let naive = [
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| {
+            format!(
+                "expected a time ago like 6h or 7d, or a date like 2024-05-14 [13:30], got '{}'",
+                s
+            )
+        })?;
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|time| TimeBound::At(time.into()))
+            .ok_or_else(|| format!("'{}' does not exist in the local time zone", s))
+    }
+}
+
+/// Statistics of one target in one run over the queried time range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStats {
+    pub run_id: i64,
+    pub started: SystemTime,
+    pub target: String,
+    pub addr: String,
+    pub sent: u64,
+    pub received: u64,
+    pub min: Option<Duration>,
+    pub avg: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl RunStats {
+    pub fn loss_rate(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (self.sent - self.received) as f64 / self.sent as f64
+    }
+
+    /// All `runs` combined, with the average weighted by answered probes.  `None` for no runs.
+    pub fn total(runs: &[RunStats]) -> Option<Self> {
+        let first = runs.first()?;
+        let received = runs.iter().map(|run| run.received).sum::<u64>();
+        let rtt_sum = runs
+            .iter()
+            .filter_map(|run| Some(run.avg? * run.received as u32))
+            .sum::<Duration>();
+        Some(Self {
+            run_id: first.run_id,
+            started: first.started,
+            target: first.target.clone(),
+            addr: first.addr.clone(),
+            sent: runs.iter().map(|run| run.sent).sum(),
+            received,
+            min: runs.iter().filter_map(|run| run.min).min(),
+            avg: (received > 0).then(|| rtt_sum / received as u32),
+            max: runs.iter().filter_map(|run| run.max).max(),
+        })
+    }
+}
+
+/// Per run statistics of the targets named or addressed `host` from `since` until `until`,
+/// oldest run first.
+pub fn query(
+    path: &Path,
+    host: &str,
+    since: SystemTime,
+    until: SystemTime,
+) -> Result<Vec<RunStats>, HistoryError> {
+    let connection = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    check_version(&connection)?;
+    let mut statement = connection.prepare(
+        "SELECT r.id, r.started_ms, t.host, t.addr, t.probe, t.port,
+                COUNT(*), COUNT(s.rtt_us), MIN(s.rtt_us), AVG(s.rtt_us), MAX(s.rtt_us)
+         FROM samples s
+         JOIN targets t ON s.target_id = t.id
+         JOIN runs r ON t.run_id = r.id
+         WHERE (t.host = ?1 OR t.addr = ?1) AND s.at_ms >= ?2 AND s.at_ms < ?3
+         GROUP BY t.id
+         ORDER BY r.started_ms, t.id",
+    )?;
+    let rows = statement.query_map(params![host, millis(since), millis(until)], |row| {
+        let host = row.get::<_, Option<String>>(2)?;
+        let probe = row.get::<_, String>(4)?;
+        let port = row.get::<_, Option<u16>>(5)?;
+        let name = host.unwrap_or_else(|| "-".to_string());
+        let target = match port {
+            Some(port) => format!("{}://{}:{}", probe, name, port),
+            None => name,
+        };
+        Ok(RunStats {
+            run_id: row.get(0)?,
+            started: UNIX_EPOCH + Duration::from_millis(row.get::<_, i64>(1)?.max(0) as u64),
+            target,
+            addr: row.get(3)?,
+            sent: row.get::<_, i64>(6)? as u64,
+            received: row.get::<_, i64>(7)? as u64,
+            min: row.get::<_, Option<i64>>(8)?.map(micros),
+            avg: row
+                .get::<_, Option<f64>>(9)?
+                .map(|avg| Duration::from_secs_f64(avg.max(0.0) / 1e6)),
+            max: row.get::<_, Option<i64>>(10)?.map(micros),
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+fn open(path: &Path) -> Result<Connection, HistoryError> {
+    let connection = Connection::open(path)?;
+    // Lets `mping history` read while a long run is writing
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    check_version(&connection)?;
+    connection.execute_batch(SCHEMA)?;
+    connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(connection)
+}
+
+/// Refuses databases written by a newer, incompatible mping.
+fn check_version(connection: &Connection) -> Result<(), HistoryError> {
+    let version = connection
+        .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .optional()?
+        .unwrap_or(0);
+    if version > SCHEMA_VERSION {
+        return Err(HistoryError::SchemaVersion {
+            found: version,
+            expected: SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn micros(us: i64) -> Duration {
+    Duration::from_micros(us.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::ProbeFailure;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::Sample;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::sync::mpsc;
+
+    fn database(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("mping-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn targets() -> Vec<PingTarget> {
+        vec![
+            PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            )
+            .with_kind(ProbeKind::Tls { port: 443 }),
+        ]
+    }
+
+    fn event(target: &PingTarget, secs: u64, rtt_us: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: target.clone(),
+            seq: secs,
+            sample: Sample {
+                at: UNIX_EPOCH + Duration::from_secs(secs),
+                rtt: rtt_us.map(Duration::from_micros),
+                ttl: rtt_us.map(|_| 57),
+            },
+            failure: rtt_us.is_none().then(|| ProbeFailure {
+                kind: "timeout",
+                message: String::new(),
+            }),
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn parses_time_bounds() {
+        assert_eq!(
+            "90m".parse(),
+            Ok(TimeBound::Ago(Duration::from_secs(90 * 60)))
+        );
+        assert_eq!(
+            "7d".parse(),
+            Ok(TimeBound::Ago(Duration::from_secs(7 * 86_400)))
+        );
+        assert_eq!(
+            "2024-05-14T12:00:00Z".parse(),
+            Ok(TimeBound::At(at(1_715_688_000)))
+        );
+        let TimeBound::At(day) = "2024-05-14".parse().unwrap() else {
+            panic!("expected a point in time");
+        };
+        let TimeBound::At(noon) = "2024-05-14 12:00".parse().unwrap() else {
+            panic!("expected a point in time");
+        };
+        assert_eq!(
+            noon.duration_since(day).unwrap(),
+            Duration::from_secs(12 * 3_600)
+        );
+        assert!("yesterday".parse::<TimeBound>().is_err());
+        assert!("5x".parse::<TimeBound>().is_err());
+        assert!("99999999999999999d".parse::<TimeBound>().is_err());
+
+        assert_eq!(
+            TimeBound::Ago(Duration::from_secs(10)).resolve(at(100)),
+            at(90)
+        );
+    }
+
+    #[test]
+    fn records_runs_and_queries_ranges() {
+        let path = database("history");
+        let targets = targets();
+        for run in 0..2 {
+            let mut store = HistoryStore::create(&path, &targets, "mping gw.example.com").unwrap();
+            let base = run * 1_000;
+            store
+                .record(&[
+                    event(&targets[0], base + 1, Some(1_000)),
+                    event(&targets[0], base + 2, Some(3_000 + base)),
+                    event(&targets[0], base + 3, None),
+                    event(&targets[1], base + 1, Some(20_000)),
+                ])
+                .unwrap();
+            store.finish().unwrap();
+        }
+
+        let runs = query(&path, "gw.example.com", at(0), at(2_000)).unwrap();
+        assert_eq!(runs.len(), 4);
+        assert_eq!(runs[0].target, "gw.example.com");
+        assert_eq!(runs[1].target, "tls://gw.example.com:443");
+        assert_eq!((runs[0].sent, runs[0].received), (3, 2));
+        assert_eq!(runs[0].min, Some(Duration::from_micros(1_000)));
+        assert_eq!(runs[0].avg, Some(Duration::from_micros(2_000)));
+        assert!((runs[0].loss_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(runs[2].max, Some(Duration::from_micros(4_000)));
+
+        // Only the second run, and the address works as well as the name
+        let runs = query(&path, "10.0.0.1", at(1_000), at(2_000)).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(query(&path, "db1", at(0), at(2_000)).unwrap().is_empty());
+
+        let connection = Connection::open(&path).unwrap();
+        let (ttl, error) = connection
+            .query_row("SELECT MAX(ttl), MAX(error) FROM samples", [], |row| {
+                Ok((row.get::<_, u8>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap();
+        assert_eq!((ttl, error.as_str()), (57, "timeout"));
+        let unfinished = connection
+            .query_row(
+                "SELECT COUNT(*) FROM runs WHERE finished_ms IS NULL",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap();
+        assert_eq!(unfinished, 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn refuses_newer_schema_versions() {
+        let path = database("newer");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        let error = query(&path, "gw.example.com", at(0), at(1)).unwrap_err();
+        assert!(matches!(
+            error,
+            HistoryError::SchemaVersion { found, expected: SCHEMA_VERSION }
+                if found == SCHEMA_VERSION + 1
+        ));
+        assert_eq!(
+            error.to_string(),
+            "database user_version 2 is newer than 1 supported by this mping"
+        );
+        assert!(HistoryStore::create(&path, &targets(), "mping").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn totals_weight_average_by_answers() {
+        let run = |received, avg_us, min_us, max_us| RunStats {
+            run_id: 1,
+            started: UNIX_EPOCH,
+            target: "db1".to_string(),
+            addr: "10.0.0.2".to_string(),
+            sent: 4,
+            received,
+            min: Some(Duration::from_micros(min_us)),
+            avg: Some(Duration::from_micros(avg_us)),
+            max: Some(Duration::from_micros(max_us)),
+        };
+        let total =
+            RunStats::total(&[run(1, 1_000, 1_000, 1_000), run(3, 5_000, 2_000, 9_000)]).unwrap();
+        assert_eq!((total.sent, total.received), (8, 4));
+        assert_eq!(total.avg, Some(Duration::from_micros(4_000)));
+        assert_eq!(total.min, Some(Duration::from_micros(1_000)));
+        assert_eq!(total.max, Some(Duration::from_micros(9_000)));
+        assert_eq!(RunStats::total(&[]), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_records_until_channel_closes() {
+        let path = database("run");
+        let targets = targets();
+        let store = HistoryStore::create(&path, &targets, "mping").unwrap();
+        let (events, received) = mpsc::unbounded_channel();
+        for secs in 0..5 {
+            events.send(event(&targets[0], secs, Some(1_000))).unwrap();
+        }
+        drop(events);
+        tokio::task::spawn_blocking(move || run(received, store))
+            .await
+            .unwrap();
+
+        let runs = query(&path, "gw.example.com", at(0), at(10)).unwrap();
+        assert_eq!(runs[0].sent, 5);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,222 @@
+//! External commands run when a host goes down or comes back up.
+
+use crate::core::constants::HOOK_TIMEOUT;
+use crate::display::DurationExt;
+use crate::events::StateChange;
+use crate::state::HostState;
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time;
+
+/// Shell commands for `--on-down` and `--on-up`.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub on_down: Option<String>,
+    pub on_up: Option<String>,
+}
+
+impl Hooks {
+    /// The command to run for `change`, if any.  `--on-up` only runs on recovery from an outage,
+    /// not when a degraded host gets better.
+    pub fn command_for(&self, change: &StateChange) -> Option<&str> {
+        let transition = &change.transition;
+        match (transition.from, transition.to) {
+            (_, HostState::Down) => self.on_down.as_deref(),
+            (HostState::Down, HostState::Up) => self.on_up.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Runs the command for `change` through `sh -c` and waits for it to exit, killing it after
+    /// [`HOOK_TIMEOUT`].  Returns `None` if no command is configured for this change.
+    pub async fn run(&self, change: &StateChange) -> Option<io::Result<ExitStatus>> {
+        self.run_with_timeout(change, HOOK_TIMEOUT).await
+    }
+
+    async fn run_with_timeout(
+        &self,
+        change: &StateChange,
+        timeout: Duration,
+    ) -> Option<io::Result<ExitStatus>> {
+        let command = self.command_for(change)?;
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(environment(change))
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match time::timeout(timeout, child.wait()).await {
+            Ok(status) => status,
+            Err(_) => {
+                let _ = child.kill().await;
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("killed after {}", timeout.display()),
+                ))
+            }
+        })
+    }
+}
+
+/// Variables describing `change` to the hook command.  Durations are in seconds and RTTs in
+/// milliseconds; values that are not known are left empty.
+fn environment(change: &StateChange) -> Vec<(&'static str, String)> {
+    vec![
+        ("MPING_HOST", change.target.host_label()),
+        ("MPING_ADDR", change.target.addr.to_string()),
+        ("MPING_STATE", change.transition.to.to_string()),
+        ("MPING_PREVIOUS_STATE", change.transition.from.to_string()),
+        (
+            "MPING_OUTAGE_SECONDS",
+            change
+                .outage
+                .map(|d| format!("{:.3}", d.as_secs_f64()))
+                .unwrap_or_default(),
+        ),
+        (
+            "MPING_LOSS_PERCENT",
+            format!("{:.1}", change.recent_loss * 100.0),
+        ),
+        (
+            "MPING_RTT_MS",
+            change
+                .recent_rtt
+                .map(|d| format!("{:.3}", d.as_secs_f64() * 1000.0))
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::PingTarget;
+    use crate::state::Transition;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, SystemTime};
+
+    fn change(from: HostState, to: HostState) -> StateChange {
+        StateChange {
+            target: PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            transition: Transition {
+                from,
+                to,
+                at: SystemTime::now(),
+            },
+            outage: Some(Duration::from_millis(30_500)),
+            recent_loss: 0.4,
+            recent_rtt: Some(Duration::from_micros(12_250)),
+        }
+    }
+
+    fn hooks() -> Hooks {
+        Hooks {
+            on_down: Some("down".to_string()),
+            on_up: Some("up".to_string()),
+        }
+    }
+
+    #[test]
+    fn command_for_matches_transition() {
+        let hooks = hooks();
+        assert_eq!(
+            hooks.command_for(&change(HostState::Degraded, HostState::Down)),
+            Some("down")
+        );
+        assert_eq!(
+            hooks.command_for(&change(HostState::Down, HostState::Up)),
+            Some("up")
+        );
+        assert_eq!(
+            hooks.command_for(&change(HostState::Degraded, HostState::Up)),
+            None
+        );
+        assert_eq!(
+            hooks.command_for(&change(HostState::Up, HostState::Degraded)),
+            None
+        );
+    }
+
+    #[test]
+    fn environment_formats_values() {
+        let env = environment(&change(HostState::Down, HostState::Up));
+        let get = |name| env.iter().find(|(k, _)| *k == name).unwrap().1.clone();
+        assert_eq!(get("MPING_HOST"), "gw.example.com");
+        assert_eq!(get("MPING_ADDR"), "10.0.0.1");
+        assert_eq!(get("MPING_STATE"), "up");
+        assert_eq!(get("MPING_PREVIOUS_STATE"), "down");
+        assert_eq!(get("MPING_OUTAGE_SECONDS"), "30.500");
+        assert_eq!(get("MPING_LOSS_PERCENT"), "40.0");
+        assert_eq!(get("MPING_RTT_MS"), "12.250");
+
+        let mut change = change(HostState::Up, HostState::Down);
+        change.target = PingTarget::new(change.target.addr);
+        let env = environment(&change);
+        assert!(env.contains(&("MPING_HOST", "10.0.0.1".to_string())));
+    }
+
+    #[tokio::test]
+    async fn run_passes_environment_to_command() {
+        let output = std::env::temp_dir().join(format!("mping-hook-{}", std::process::id()));
+        let hooks = Hooks {
+            on_down: Some(format!(
+                "echo \"$MPING_HOST $MPING_STATE\" > {}",
+                output.display()
+            )),
+            on_up: None,
+        };
+
+        let status = hooks
+            .run(&change(HostState::Up, HostState::Down))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(status.success());
+        let written = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(written, "gw.example.com down\n");
+    }
+
+    #[tokio::test]
+    async fn hanging_hook_is_killed_after_timeout() {
+        let hooks = Hooks {
+            on_down: Some("sleep 10".to_string()),
+            on_up: None,
+        };
+
+        let started = std::time::Instant::now();
+        let error = hooks
+            .run_with_timeout(
+                &change(HostState::Up, HostState::Down),
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(error.to_string(), "killed after 50.00 ms");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn run_without_matching_hook_does_nothing() {
+        let hooks = Hooks::default();
+        assert!(
+            hooks
+                .run(&change(HostState::Up, HostState::Down))
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! InfluxDB line protocol output of probe results, one point per probe or one aggregate per host
+//! and interval.
+
+use crate::core::constants::{INFLUX_BATCH_INTERVAL, INFLUX_TIMEOUT};
+use crate::events::{EventReceiver, PingEvent};
+use crate::metrics::{PerTarget, ProbeTally, Tag};
+use crate::network::client::PingTarget;
+use crate::network::http::{HttpUrl, post};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+
+/// Where the lines are written: `-` for stdout, an `http(s)://` write endpoint, or a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxDestination {
+    Stdout,
+    File(PathBuf),
+    Http(HttpUrl),
+}
+
+impl FromStr for InfluxDestination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            Ok(InfluxDestination::Stdout)
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            s.parse().map(InfluxDestination::Http)
+        } else if s.is_empty() {
+            Err("expected -, a file or an http(s):// URL".to_string())
+        } else {
+            Ok(InfluxDestination::File(PathBuf::from(s)))
+        }
+    }
+}
+
+impl fmt::Display for InfluxDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InfluxDestination::Stdout => write!(f, "stdout"),
+            InfluxDestination::File(path) => write!(f, "{}", path.display()),
+            InfluxDestination::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// An open destination.
+#[derive(Debug)]
+pub struct InfluxWriter {
+    destination: InfluxDestination,
+    file: Option<File>,
+    /// Sent as `Authorization: Token ...` to HTTP endpoints.
+    token: Option<String>,
+}
+
+impl InfluxWriter {
+    /// Opens a file destination for appending, so repeated runs add up to one series.
+    pub fn open(destination: InfluxDestination, token: Option<String>) -> io::Result<Self> {
+        let file = match &destination {
+            InfluxDestination::File(path) => {
+                Some(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            destination,
+            file,
+            token,
+        })
+    }
+
+    /// Whether lines are written as they come rather than posted in batches.
+    fn is_streaming(&self) -> bool {
+        !matches!(self.destination, InfluxDestination::Http(_))
+    }
+
+    async fn write(&mut self, lines: &[String]) -> Result<(), String> {
+        let mut body = lines.join("\n");
+        body.push('\n');
+        match &self.destination {
+            InfluxDestination::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout
+                    .write_all(body.as_bytes())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|e| e.to_string())
+            }
+            InfluxDestination::File(_) => self
+                .file
+                .as_mut()
+                .expect("file destinations are opened with the writer")
+                .write_all(body.as_bytes())
+                .map_err(|e| e.to_string()),
+            InfluxDestination::Http(url) => {
+                let authorization = self.token.as_ref().map(|token| format!("Token {}", token));
+                let headers = authorization
+                    .as_deref()
+                    .map(|value| vec![("Authorization", value)])
+                    .unwrap_or_default();
+                let request = post(url, "text/plain; charset=utf-8", &headers, &body);
+                match time::timeout(INFLUX_TIMEOUT, request).await {
+                    Ok(Ok(status)) if (200..300).contains(&status) => Ok(()),
+                    Ok(Ok(status)) => Err(format!("HTTP status {}", status)),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("timed out".to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// One `mping_probe` point: `seq`, `lost` and, for answered probes, `rtt_ms` and `ttl` if known.
+pub fn probe_line(event: &PingEvent, tags: &[Tag]) -> String {
+    let PingEvent::Sample {
+        target,
+        seq,
+        sample,
+        ..
+    } = event;
+    let mut fields = vec![
+        format!("seq={}i", seq),
+        format!("lost={}", sample.rtt.is_none()),
+    ];
+    if let Some(rtt) = sample.rtt {
+        fields.push(format!("rtt_ms={}", millis(rtt)));
+    }
+    if let Some(ttl) = sample.ttl {
+        fields.push(format!("ttl={}i", ttl));
+    }
+    line("mping_probe", target, tags, &fields, sample.at)
+}
+
+#[derive(Debug)]
+struct Window {
+    target: PingTarget,
+    tally: ProbeTally,
+}
+
+/// Sums up probes per host until the end of an interval.
+#[derive(Debug)]
+pub struct Aggregator {
+    tags: Vec<Tag>,
+    windows: PerTarget<Window>,
+}
+
+impl Aggregator {
+    pub fn new(tags: Vec<Tag>) -> Self {
+        Self {
+            tags,
+            windows: PerTarget::default(),
+        }
+    }
+
+    pub fn add(&mut self, event: &PingEvent) {
+        let PingEvent::Sample { target, sample, .. } = event;
+        let window = self.windows.get_or_insert_with(target, || Window {
+            target: target.clone(),
+            tally: ProbeTally::default(),
+        });
+        window.tally.record(sample.rtt);
+    }
+
+    /// One `mping_interval` point per host that was probed since the last call, stamped `at`.
+    pub fn drain(&mut self, at: SystemTime) -> Vec<String> {
+        let mut lines = Vec::new();
+        for window in self.windows.iter_mut().filter(|w| w.tally.sent > 0) {
+            let tally = &window.tally;
+            let mut fields = vec![
+                format!("sent={}i", tally.sent),
+                format!("received={}i", tally.received),
+                format!("lost={}i", tally.lost()),
+                format!("loss={}", tally.lost() as f64 / tally.sent as f64),
+            ];
+            if let (Some(min), Some(avg), Some(max)) =
+                (tally.rtt_min, tally.avg_rtt(), tally.rtt_max)
+            {
+                fields.push(format!("rtt_min_ms={}", millis(min)));
+                fields.push(format!("rtt_avg_ms={}", millis(avg)));
+                fields.push(format!("rtt_max_ms={}", millis(max)));
+            }
+            lines.push(line(
+                "mping_interval",
+                &window.target,
+                &self.tags,
+                &fields,
+                at,
+            ));
+            window.tally = ProbeTally::default();
+        }
+        lines
+    }
+}
+
+/// Writes every probe event to `writer`, or with `aggregate` one point per host and interval.
+/// Lines for HTTP endpoints are posted in batches.  Returns once the event channel is closed and
+/// everything is written.
+pub async fn run(
+    mut events: EventReceiver,
+    mut writer: InfluxWriter,
+    tags: Vec<Tag>,
+    aggregate: Option<Duration>,
+) {
+    let mut aggregator = aggregate.map(|_| Aggregator::new(tags.clone()));
+    let mut ticker = time::interval(aggregate.unwrap_or(INFLUX_BATCH_INTERVAL));
+    // The first tick completes immediately
+    ticker.tick().await;
+    let mut pending = Vec::new();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                match &mut aggregator {
+                    Some(aggregator) => aggregator.add(&event),
+                    None => pending.push(probe_line(&event, &tags)),
+                }
+                if writer.is_streaming() {
+                    flush(&mut writer, &mut pending).await;
+                }
+            }
+            _ = ticker.tick() => {
+                if let Some(aggregator) = &mut aggregator {
+                    pending.extend(aggregator.drain(SystemTime::now()));
+                }
+                flush(&mut writer, &mut pending).await;
+            }
+        }
+    }
+
+    if let Some(aggregator) = &mut aggregator {
+        pending.extend(aggregator.drain(SystemTime::now()));
+    }
+    flush(&mut writer, &mut pending).await;
+}
+
+/// Writes and clears `pending`.  A failed batch is dropped rather than piling up.
+async fn flush(writer: &mut InfluxWriter, pending: &mut Vec<String>) {
+    if pending.is_empty() {
+        return;
+    }
+    if let Err(e) = writer.write(pending).await {
+        eprintln!(
+            "InfluxDB output to {} failed, {} points dropped: {}",
+            writer.destination,
+            pending.len(),
+            e
+        );
+    }
+    pending.clear();
+}
+
+fn line(
+    measurement: &str,
+    target: &PingTarget,
+    tags: &[Tag],
+    fields: &[String],
+    at: SystemTime,
+) -> String {
+    let mut tag_set = Vec::new();
+    // Tag values may not be empty
+    if let Some(host) = &target.host {
+        tag_set.push(("host", host.clone()));
+    }
+    tag_set.push(("addr", target.addr.to_string()));
+    tag_set.push(("probe", target.kind.scheme().unwrap_or("icmp").to_string()));
+    if let Some(port) = target.kind.port() {
+        tag_set.push(("port", port.to_string()));
+    }
+    tag_set.extend(
+        tags.iter()
+            .filter(|tag| !tag.value.is_empty())
+            .map(|tag| (tag.key.as_str(), tag.value.clone())),
+    );
+
+    format!(
+        "{},{} {} {}",
+        measurement,
+        tag_set
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape(key), escape(value)))
+            .collect::<Vec<_>>()
+            .join(","),
+        fields.join(","),
+        at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+    )
+}
+
+/// Escapes a tag key or value.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::ProbeFailure;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::Sample;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn event(target: &PingTarget, secs: u64, rtt_us: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: target.clone(),
+            seq: secs,
+            sample: Sample {
+                at: UNIX_EPOCH + Duration::from_secs(secs),
+                rtt: rtt_us.map(Duration::from_micros),
+                ttl: rtt_us.map(|_| 57),
+            },
+            failure: rtt_us.is_none().then(|| ProbeFailure {
+                kind: "timeout",
+                message: String::new(),
+            }),
+        }
+    }
+
+    fn target() -> PingTarget {
+        PingTarget::with_host(
+            "gw.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        )
+    }
+
+    fn tags() -> Vec<Tag> {
+        vec!["site=fra 1".parse().unwrap()]
+    }
+
+    #[test]
+    fn parses_destinations() {
+        assert_eq!("-".parse(), Ok(InfluxDestination::Stdout));
+        assert_eq!(
+            "points.lp".parse(),
+            Ok(InfluxDestination::File(PathBuf::from("points.lp")))
+        );
+        assert!(matches!(
+            "http://localhost:8086/write?db=net".parse(),
+            Ok(InfluxDestination::Http(_))
+        ));
+        assert!("https://".parse::<InfluxDestination>().is_err());
+        assert!("".parse::<InfluxDestination>().is_err());
+    }
+
+    #[test]
+    fn probe_lines() {
+        assert_eq!(
+            probe_line(&event(&target(), 2, Some(1_500)), &tags()),
+            "mping_probe,host=gw.example.com,addr=10.0.0.1,probe=icmp,site=fra\\ 1 \
+             seq=2i,lost=false,rtt_ms=1.5,ttl=57i 2000000000"
+        );
+
+        let ntp = PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+            .with_kind(ProbeKind::Ntp { port: 123 });
+        assert_eq!(
+            probe_line(&event(&ntp, 3, None), &[]),
+            "mping_probe,addr=10.0.0.2,probe=ntp,port=123 seq=3i,lost=true 3000000000"
+        );
+    }
+
+    #[test]
+    fn aggregates_per_interval() {
+        let mut aggregator = Aggregator::new(Vec::new());
+        let target = target();
+        aggregator.add(&event(&target, 0, Some(1_000)));
+        aggregator.add(&event(&target, 1, Some(3_000)));
+        aggregator.add(&event(&target, 2, None));
+        aggregator.add(&event(&target, 3, None));
+
+        let at = UNIX_EPOCH + Duration::from_secs(10);
+        assert_eq!(
+            aggregator.drain(at),
+            vec![
+                "mping_interval,host=gw.example.com,addr=10.0.0.1,probe=icmp \
+                 sent=4i,received=2i,lost=2i,loss=0.5,rtt_min_ms=1,rtt_avg_ms=2,rtt_max_ms=3 \
+                 10000000000"
+            ]
+        );
+        // Nothing new was probed
+        assert!(aggregator.drain(at).is_empty());
+
+        aggregator.add(&event(&target, 11, None));
+        assert_eq!(
+            aggregator.drain(at),
+            vec![
+                "mping_interval,host=gw.example.com,addr=10.0.0.1,probe=icmp \
+                 sent=1i,received=0i,lost=1i,loss=1 10000000000"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_batches_to_write_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"000000000\n") {
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = format!("http://127.0.0.1:{}/api/v2/write?bucket=net", port);
+        let writer = InfluxWriter::open(url.parse().unwrap(), Some("secret".to_string())).unwrap();
+        let (events, received) = mpsc::unbounded_channel();
+        events.send(event(&target(), 1, Some(1_000))).unwrap();
+        events.send(event(&target(), 2, None)).unwrap();
+        drop(events);
+        run(received, writer, Vec::new(), None).await;
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /api/v2/write?bucket=net HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Token secret\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(body.lines().count(), 2);
+        assert!(body.ends_with("seq=2i,lost=true 2000000000\n"));
+    }
+
+    #[tokio::test]
+    async fn appends_to_files() {
+        let path = std::env::temp_dir().join(format!("mping-{}.lp", std::process::id()));
+        for secs in [1, 2] {
+            let writer = InfluxWriter::open(InfluxDestination::File(path.clone()), None).unwrap();
+            let (events, received) = mpsc::unbounded_channel();
+            events.send(event(&target(), secs, Some(1_000))).unwrap();
+            drop(events);
+            run(received, writer, Vec::new(), None).await;
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+    }
+}
@@ -1,4 +1,21 @@
+pub mod archive;
 pub mod core;
+pub mod csv;
 pub mod display;
+pub mod emitters;
+pub mod events;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod influx;
+pub mod metrics;
+pub mod mqtt;
+pub mod nagios;
 pub mod network;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod report;
+pub mod state;
 pub mod stats;
+pub mod svg;
+pub mod webhook;
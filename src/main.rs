@@ -1,68 +1,919 @@
 use clap::Parser;
-use comfy_table::ContentArrangement;
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_BORDERS_ONLY;
+use comfy_table::{ContentArrangement, Table};
 use futures::future::join_all;
+use mping::archive::{self, Archive, Archiver};
 use mping::core::config::Args;
-use mping::core::config::PingConfig;
-use mping::display::DurationExt;
-use mping::network::client::PingClients;
-use mping::network::ping;
-use mping::network::resolver::resolve_targets;
+use mping::core::config::{
+    ArchiveArgs, Command, HistoryConfig, MtrConfig, OutputFormat, PingConfig, ServeConfig,
+    TraceConfig,
+};
+use mping::core::constants::{DEFAULT_PAYLOAD_SIZE, GRAPHITE_QUEUE};
+use mping::csv::{self, SampleWriter};
+use mping::display::{DurationExt, display_time};
+use mping::emitters;
+use mping::events::{EventReceiver, PingEvent, StateChange, StateWatcher, Tap};
+use mping::health::{EXIT_RUNTIME_ERROR, Verdict};
+use mping::history::{self, HistoryStore};
+use mping::hooks::Hooks;
+use mping::influx::{self, InfluxWriter};
+use mping::metrics::{self, Metrics};
+use mping::mqtt::{self, MqttPublisher};
+use mping::nagios::{NagiosReport, Status};
+use mping::network::client::{PingClients, PingTarget, ProbeKind};
+use mping::network::ping::{PingResults, Schedule};
+use mping::network::reachability::{self, Check};
+use mping::network::resolver::{lookup_name, resolve_target, resolve_targets};
+use mping::network::trace::{Hop, Tracer, is_transient_loss};
+use mping::network::{http, ntp, ping, pmtu, sweep, tls, trace};
+use mping::report::{EventLine, Report};
 use mping::stats;
-use mping::stats::OverallStats;
+use mping::stats::{OverallStats, TRANSIENT_LOSS_MARKER};
+use mping::svg;
+use mping::webhook::{Alert, AlertThresholds, Deduplicator, Webhooks};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::net::IpAddr;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time;
 
 type Result<T> = anyhow::Result<T>;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let config = PingConfig::from_args(Args::parse())?;
+async fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // --help and --version
+        Err(e) if !e.use_stderr() => e.exit(),
+        // Clap would exit with 2, which means unreachable hosts here
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(EXIT_RUNTIME_ERROR);
+        }
+    };
+    let nagios = args.nagios;
+    match run(args).await {
+        Ok(code) => code,
+        Err(e) if nagios => {
+            println!("PING {} - {}", Status::Unknown, e);
+            ExitCode::from(Status::Unknown.exit_code())
+        }
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
+
+async fn run(mut args: Args) -> Result<ExitCode> {
+    match args.command.take() {
+        Some(Command::Trace(trace_args)) => {
+            run_trace(TraceConfig::from_args(trace_args)?).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Mtr(mtr_args)) => {
+            run_mtr(MtrConfig::from_args(mtr_args)?).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Serve(serve_args)) => {
+            run_serve(ServeConfig::from_args(serve_args)?).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::History(history_args)) => {
+            run_history(HistoryConfig::from_args(history_args)?)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Archive(archive_args)) => {
+            show_archive(archive_args)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    let config = PingConfig::from_args(args)?;
     let clients = PingClients::new()?;
-    let targets = resolve_targets(&config).await;
+    let targets = resolve_targets(&config.hosts).await;
+
+    if config.is_matrix() {
+        run_matrix(&config, &clients, targets).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if config.pmtu {
+        run_pmtu(&config, targets).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if config.sweep.is_some() {
+        run_sweep(&config, &clients, targets).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !config.is_machine_readable() {
+        print_ping_header(&config, targets.len());
+    }
+
+    let (event_sender, event_receiver) = mpsc::unbounded_channel();
+    let (stop_sender, stop_receiver) = watch::channel(false);
+    let schedule = if config.continuous {
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                let _ = stop_sender.send(true);
+            }
+        });
+        Schedule::continuous(config.interval)
+    } else {
+        Schedule::new(config.packet_count, config.interval)
+    }
+    .with_events(event_sender)
+    .with_stop(stop_receiver)
+    .with_thresholds(config.thresholds);
+
+    let hooks = Hooks {
+        on_down: config.on_down.clone(),
+        on_up: config.on_up.clone(),
+    };
+    let webhooks = Webhooks::new(config.webhooks.clone());
+    let csv_out = config
+        .csv_out
+        .as_deref()
+        .map(|path| {
+            SampleWriter::create(path)
+                .map_err(|e| anyhow::anyhow!("cannot write {}: {}", path.display(), e))
+        })
+        .transpose()?;
+    let mut taps = Vec::new();
+    let mut sinks = Vec::new();
+    if let Some(destination) = &config.influx {
+        let writer = InfluxWriter::open(destination.clone(), env::var("INFLUX_TOKEN").ok())
+            .map_err(|e| anyhow::anyhow!("cannot write {}: {}", destination, e))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::spawn(influx::run(
+            receiver,
+            writer,
+            config.tags.clone(),
+            config.influx_aggregate,
+        )));
+    }
+    if let Some(endpoint) = &config.statsd {
+        let socket = emitters::connect_statsd(endpoint)
+            .await
+            .map_err(|e| anyhow::anyhow!("cannot send to StatsD at {}: {}", endpoint, e))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::spawn(emitters::run_statsd(
+            receiver,
+            socket,
+            config.metric_prefix.clone(),
+        )));
+    }
+    if let Some(path) = &config.db {
+        let command_line = env::args().collect::<Vec<_>>().join(" ");
+        let store = HistoryStore::create(path, &targets, &command_line)
+            .map_err(|e| anyhow::anyhow!("cannot record to {}: {}", path.display(), e))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::task::spawn_blocking(move || {
+            history::run(receiver, store)
+        }));
+    }
+    if let Some(dir) = &config.archive {
+        let archiver = Archiver::open(dir, &targets)
+            .map_err(|e| anyhow::anyhow!("cannot archive to {}: {}", dir.display(), e))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::task::spawn_blocking(move || {
+            archive::run(receiver, archiver)
+        }));
+    }
+    if let Some(url) = &config.mqtt {
+        let agent = config.mqtt_agent.clone().unwrap_or_else(mqtt::local_agent);
+        let publisher =
+            MqttPublisher::new(url.clone(), &config.mqtt_topic, &agent, config.mqtt_qos);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::spawn(mqtt::run(
+            receiver,
+            publisher,
+            targets.clone(),
+            config.thresholds,
+        )));
+    }
+    #[cfg(feature = "otlp")]
+    if let Some(url) = &config.otlp {
+        let exporter = mping::otlp::OtlpExporter::new(
+            url.clone(),
+            &targets,
+            config.tags.clone(),
+            config.thresholds,
+        )
+        .with_headers(&env::var("OTEL_EXPORTER_OTLP_HEADERS").unwrap_or_default());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        taps.push(Tap::Unbounded(sender));
+        sinks.push(tokio::spawn(mping::otlp::run(receiver, exporter)));
+    }
+    if let Some(endpoint) = &config.graphite {
+        let (sender, receiver) = mpsc::channel(GRAPHITE_QUEUE);
+        taps.push(Tap::Bounded(sender));
+        sinks.push(tokio::spawn(emitters::run_graphite(
+            receiver,
+            endpoint.clone(),
+            config.metric_prefix.clone(),
+        )));
+    }
+    let output = match config.format {
+        OutputFormat::Ndjson => EventOutput::Ndjson,
+        _ if config.is_machine_readable() => EventOutput::Silent,
+        _ => EventOutput::Text {
+            announce: config.continuous,
+        },
+    };
+    let notifiers = Notifiers {
+        hooks,
+        webhooks: webhooks.clone(),
+        alert_thresholds: config.alert_thresholds,
+    };
+    let dispatcher = tokio::spawn(dispatch_events(
+        event_receiver,
+        StateWatcher::new(config.thresholds),
+        notifiers,
+        output,
+        csv_out,
+        taps,
+    ));
+
+    let tasks = targets
+        .into_iter()
+        .map(|target| spawn_probe(&clients, target, schedule.clone(), config.burst_size))
+        .collect::<Vec<_>>();
+    // The dispatcher finishes once every copy of the event sender is gone
+    drop(schedule);
+
+    let mut results = join_all(tasks)
+        .await
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect::<Vec<_>>();
+
+    let mut dedup = dispatcher.await?;
+    // The sinks finish once the dispatcher has dropped their senders
+    for sink in join_all(sinks).await {
+        sink?;
+    }
+    if !webhooks.is_empty() {
+        let breaches = results
+            .iter()
+            .flat_map(|r| config.alert_thresholds.breaches(r))
+            .filter(|alert| dedup.admit(alert, time::Instant::now()))
+            .collect::<Vec<_>>();
+        join_all(breaches.iter().map(|alert| notify(&webhooks, alert))).await;
+    }
+    let overall_stats = OverallStats::from_results(&results);
+    if let Some(path) = &config.summary_csv {
+        csv::write_summary(path, &results)
+            .map_err(|e| anyhow::anyhow!("cannot write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &config.prom_textfile {
+        let content = metrics::textfile(&results, &overall_stats, SystemTime::now());
+        metrics::write_atomically(path, &content)
+            .map_err(|e| anyhow::anyhow!("cannot write {}: {}", path.display(), e))?;
+    }
+    if let Some(dir) = &config.svg {
+        svg::write_all(dir, &results)
+            .map_err(|e| anyhow::anyhow!("cannot draw graphs in {}: {}", dir.display(), e))?;
+    }
+    if config.nagios {
+        let unresolved = config.hosts.len().saturating_sub(results.len());
+        let report = NagiosReport::new(
+            &results,
+            config.nagios_warning,
+            config.nagios_critical,
+            unresolved,
+        );
+        println!("{}", report);
+        return Ok(ExitCode::from(report.status().exit_code()));
+    }
+
+    stats::sort_results(&mut results);
+    match config.format {
+        // Stdout carries line protocol instead
+        OutputFormat::Table if config.is_machine_readable() => {}
+        OutputFormat::Table => print_results(&results, &overall_stats),
+        OutputFormat::Json => {
+            let report = Report::new(&results, &overall_stats);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        // Everything was already streamed
+        OutputFormat::Ndjson => {}
+    }
 
+    let report = config
+        .criteria
+        .evaluate(&results, &overall_stats, config.hosts.len());
+    let verdict = report.verdict();
+    let mut messages = report
+        .breaches
+        .iter()
+        .map(|breach| format!("Threshold breached: {}", breach))
+        .collect::<Vec<_>>();
+    if verdict == Verdict::Unreachable {
+        messages.insert(
+            0,
+            format!(
+                "{} of {} hosts answered, {} required",
+                report.alive,
+                config.hosts.len(),
+                report.required_alive
+            ),
+        );
+    }
+    // Keep stdout parseable in machine-readable formats
+    for message in messages {
+        if config.is_machine_readable() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    Ok(ExitCode::from(verdict.exit_code()))
+}
+
+/// Starts probing `target` with the probe its kind calls for.
+fn spawn_probe(
+    clients: &PingClients,
+    target: PingTarget,
+    schedule: Schedule,
+    burst_size: u16,
+) -> JoinHandle<PingResults> {
+    match target.kind {
+        ProbeKind::Icmp => {
+            let client = clients.get_client(target.addr).clone();
+            if burst_size > 1 {
+                tokio::spawn(ping::ping_burst(
+                    client,
+                    target,
+                    schedule,
+                    DEFAULT_PAYLOAD_SIZE,
+                    burst_size,
+                ))
+            } else {
+                tokio::spawn(ping::ping(client, target, schedule, DEFAULT_PAYLOAD_SIZE))
+            }
+        }
+        ProbeKind::Tls { .. } => tokio::spawn(tls::probe(target, schedule)),
+        ProbeKind::Ntp { .. } => tokio::spawn(ntp::probe(target, schedule)),
+    }
+}
+
+fn print_results(results: &[PingResults], overall_stats: &OverallStats) {
+    let mut table = stats::create_results_table(results);
+    style_table(&mut table);
+
+    print!("\n{}\n\n", table);
     println!(
-        "PING {} hosts with {} packets each in {} intervals ...",
+        "Overall {} sent, {} received ({:.2} % loss)",
+        overall_stats.total_sent, overall_stats.total_received, overall_stats.loss_percentage
+    );
+
+    let mut table = stats::create_availability_table(results);
+    style_table(&mut table);
+    print!("\n{}\n\n", table);
+}
+
+fn print_ping_header(config: &PingConfig, num_targets: usize) {
+    let per_interval = if config.burst_size > 1 {
+        format!("bursts of {} packets", config.burst_size)
+    } else {
+        "packets".to_string()
+    };
+    if config.continuous {
+        let bursts = if config.burst_size > 1 {
+            format!(" in {}", per_interval)
+        } else {
+            String::new()
+        };
+        println!(
+            "PING {} hosts continuously{} at {} intervals, press Ctrl-C to stop ...",
+            num_targets,
+            bursts,
+            config.interval.display()
+        );
+    } else {
+        println!(
+            "PING {} hosts with {} {} each in {} intervals ...",
+            num_targets,
+            config.packet_count,
+            per_interval,
+            config.interval.display()
+        );
+    }
+}
+
+/// How live probe events are written to stdout.
+#[derive(Debug, Clone, Copy)]
+enum EventOutput {
+    /// Probe errors, and state changes if `announce` is set.
+    Text { announce: bool },
+    /// One JSON line per probe outcome and state change.
+    Ndjson,
+    /// Nothing, stdout is reserved for the final report.
+    Silent,
+}
+
+impl EventOutput {
+    fn probe(self, event: &PingEvent) {
+        match self {
+            EventOutput::Text { .. } => {
+                let PingEvent::Sample {
+                    target, failure, ..
+                } = event;
+                if let Some(failure) = failure {
+                    println!("{} ping error: {}", target.addr, failure.message);
+                }
+            }
+            EventOutput::Ndjson => write_line(&EventLine::probe(event)),
+            EventOutput::Silent => {}
+        }
+    }
+
+    fn state(self, change: &StateChange) {
+        match self {
+            EventOutput::Text { announce: true } => println!(
+                "{} is {} (was {})",
+                change.target, change.transition.to, change.transition.from
+            ),
+            EventOutput::Ndjson => write_line(&EventLine::state(change)),
+            _ => {}
+        }
+    }
+}
+
+/// Writes and flushes one NDJSON line.  Write errors are ignored so that piping into e.g. `head`
+/// does not abort the run.
+fn write_line(line: &EventLine) {
+    let mut stdout = io::stdout().lock();
+    if let Ok(json) = serde_json::to_string(line) {
+        let _ = writeln!(stdout, "{}", json).and_then(|_| stdout.flush());
+    }
+}
+
+/// Follows host state from live probe events, writes them out and to the CSV file, passes them
+/// on to the `taps` of other sinks, runs the hooks on state changes and alerts the webhooks on
+/// outages, recoveries and breaches of the alert thresholds by the recent probes.  Waits for
+/// all hooks and deliveries to finish before returning the deduplicator, so alerts raised after
+/// the run take the ones already sent into account.
+async fn dispatch_events(
+    mut events: EventReceiver,
+    mut watcher: StateWatcher,
+    notifiers: Notifiers,
+    output: EventOutput,
+    mut csv_out: Option<SampleWriter>,
+    taps: Vec<Tap>,
+) -> Deduplicator {
+    let mut dedup = Deduplicator::default();
+    let mut running = JoinSet::new();
+    while let Some(event) = events.recv().await {
+        // Continuous runs would otherwise collect a handle for every hook and delivery
+        while running.try_join_next().is_some() {}
+        output.probe(&event);
+        if let Some(writer) = &mut csv_out
+            && let Err(e) = writer.write(&event)
+        {
+            // Keep probing, the other outputs may still work
+            eprintln!("CSV export stopped: {}", e);
+            csv_out = None;
+        }
+        for tap in &taps {
+            tap.send(event.clone());
+        }
+        let Notifiers {
+            hooks,
+            webhooks,
+            alert_thresholds,
+        } = &notifiers;
+        let change = watcher.observe(&event);
+        if let Some(change) = &change {
+            output.state(change);
+        }
+
+        let PingEvent::Sample { target, sample, .. } = &event;
+        let mut alerts = Vec::new();
+        if !webhooks.is_empty() {
+            if let Some(alert) = change.clone().and_then(Alert::from_state_change) {
+                alerts.push(alert);
+            }
+            if let Some(recent) = watcher.recent(target) {
+                alerts.extend(alert_thresholds.recent_breaches(target, sample.at, recent));
+            }
+        }
+        for alert in alerts {
+            if dedup.admit(&alert, time::Instant::now()) {
+                let webhooks = webhooks.clone();
+                running.spawn(async move { notify(&webhooks, &alert).await });
+            }
+        }
+
+        let Some(change) = change else {
+            continue;
+        };
+        if hooks.command_for(&change).is_none() {
+            continue;
+        }
+        let hooks = hooks.clone();
+        running.spawn(async move {
+            match hooks.run(&change).await {
+                Some(Ok(status)) if !status.success() => {
+                    eprintln!("{} hook exited with {}", change.target, status)
+                }
+                Some(Err(e)) => eprintln!("{} hook failed: {}", change.target, e),
+                _ => {}
+            }
+        });
+    }
+    running.join_all().await;
+    dedup
+}
+
+/// Where the dispatcher reports state changes and threshold breaches.
+struct Notifiers {
+    hooks: Hooks,
+    webhooks: Webhooks,
+    alert_thresholds: AlertThresholds,
+}
+
+async fn notify(webhooks: &Webhooks, alert: &Alert) {
+    for error in webhooks.send(alert).await {
+        eprintln!("{} {}", alert.target(), error);
+    }
+}
+
+async fn run_serve(config: ServeConfig) -> Result<()> {
+    let clients = PingClients::new()?;
+    let targets = resolve_targets(&config.hosts).await;
+    let listener = TcpListener::bind(config.listen)
+        .await
+        .map_err(|e| anyhow::anyhow!("cannot listen on {}: {}", config.listen, e))?;
+
+    println!(
+        "SERVE {} hosts at {} intervals on http://{}/metrics, press Ctrl-C to stop ...",
         targets.len(),
-        config.packet_count,
-        config.interval.display()
+        config.interval.display(),
+        config.listen
+    );
+
+    let metrics = Arc::new(Mutex::new(Metrics::new(
+        &targets,
+        &config.tags,
+        config.thresholds,
+    )));
+    let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+    // Only the recorder reads the outcomes, so the probes keep none of their own
+    let schedule = Schedule::continuous(config.interval)
+        .with_events(event_sender)
+        .with_history(0);
+    for target in targets {
+        spawn_probe(&clients, target, schedule.clone(), 1);
+    }
+    drop(schedule);
+
+    let recorder = Arc::clone(&metrics);
+    tokio::spawn(async move {
+        while let Some(event) = event_receiver.recv().await {
+            recorder.lock().unwrap().record(&event);
+        }
+    });
+
+    let server = http::serve(
+        listener,
+        "/metrics",
+        "text/plain; version=0.0.4; charset=utf-8",
+        move || metrics.lock().unwrap().render(),
+    );
+    tokio::select! {
+        _ = server => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    Ok(())
+}
+
+async fn run_matrix(
+    config: &PingConfig,
+    clients: &PingClients,
+    targets: Vec<PingTarget>,
+) -> Result<()> {
+    let checks = Check::from_ports(&config.tcp_ports, &config.udp_ports);
+
+    println!(
+        "CHECK {} hosts on {} with up to {} attempts each ...",
+        targets.len(),
+        checks
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        config.packet_count
     );
 
     let tasks = targets
         .into_iter()
         .map(|target| {
             let client = clients.get_client(target.addr).clone();
-            tokio::spawn(ping::ping(
+            tokio::spawn(reachability::check(
                 client,
                 target,
+                checks.clone(),
                 config.packet_count,
                 config.interval,
             ))
         })
         .collect::<Vec<_>>();
 
-    let mut results = join_all(tasks)
+    let results = join_all(tasks)
         .await
         .into_iter()
         .map(|r| r.unwrap())
         .collect::<Vec<_>>();
 
-    let overall_stats = OverallStats::from_results(&results);
+    let mut table = stats::create_matrix_table(&results);
+    style_table(&mut table);
+    print!("\n{}\n\n", table);
+
+    Ok(())
+}
+
+async fn run_pmtu(config: &PingConfig, targets: Vec<PingTarget>) -> Result<()> {
+    println!(
+        "PMTU {} hosts with up to {} attempts per size in {} intervals ...",
+        targets.len(),
+        config.packet_count,
+        config.interval.display()
+    );
+
+    let tasks = targets
+        .into_iter()
+        .map(|target| tokio::spawn(pmtu::discover(target, config.packet_count, config.interval)))
+        .collect::<Vec<_>>();
+
+    let mut results = Vec::new();
+    for result in join_all(tasks).await {
+        results.push(result.unwrap().map_err(|e| {
+            anyhow::anyhow!(
+                "PMTU discovery failed (raw sockets need root or CAP_NET_RAW): {}",
+                e
+            )
+        })?);
+    }
 
     stats::sort_results(&mut results);
     let mut table = stats::create_results_table(&results);
-    table
-        .set_content_arrangement(ContentArrangement::Dynamic)
-        .load_preset(UTF8_BORDERS_ONLY)
-        .apply_modifier(UTF8_ROUND_CORNERS)
-        .set_header(vec![
-            "Host", "Addr", "Sent", "Recv", "Loss", "Min", "Max", "Avg",
-        ]);
+    style_table(&mut table);
+    print!("\n{}\n\n", table);
+
+    Ok(())
+}
+
+async fn run_sweep(
+    config: &PingConfig,
+    clients: &PingClients,
+    targets: Vec<PingTarget>,
+) -> Result<()> {
+    let sizes = config.sweep.map(|range| range.sizes()).unwrap_or_default();
+
+    println!(
+        "SWEEP {} hosts with {} packets each over payload sizes {} in {} intervals ...",
+        targets.len(),
+        config.packet_count,
+        sizes
+            .iter()
+            .map(|size| size.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        config.interval.display()
+    );
+
+    let tasks = targets
+        .into_iter()
+        .map(|target| {
+            let client = clients.get_client(target.addr).clone();
+            tokio::spawn(sweep::sweep(
+                client,
+                target,
+                sizes.clone(),
+                config.packet_count,
+                config.interval,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let results = join_all(tasks)
+        .await
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect::<Vec<_>>();
 
+    let mut table = stats::create_sweep_table(&results);
+    style_table(&mut table);
     print!("\n{}\n\n", table);
+
+    Ok(())
+}
+
+async fn run_trace(config: TraceConfig) -> Result<()> {
+    let target = resolve_target(&config.host)
+        .await
+        .map_err(|e| anyhow::anyhow!("{} resolve error: {}", config.host, e))?;
+
     println!(
-        "Overall {} sent, {} received ({:.2} % loss)",
-        overall_stats.total_sent, overall_stats.total_received, overall_stats.loss_percentage
+        "TRACE {} with up to {} hops, {} probes per hop in {} intervals ...",
+        target,
+        config.max_hops,
+        config.packet_count,
+        config.interval.display()
     );
 
+    let mut hops = trace::trace(
+        &target,
+        config.max_hops,
+        config.packet_count,
+        config.interval,
+    )
+    .await
+    .map_err(trace_error)?;
+
+    resolve_hop_names(&mut hops, &mut HashMap::new()).await;
+    print_hop_table(&hops);
+
+    Ok(())
+}
+
+async fn run_mtr(config: MtrConfig) -> Result<()> {
+    let mut tracers = Vec::new();
+    for host in &config.hosts {
+        match resolve_target(host).await {
+            Ok(target) => {
+                let tracer = Tracer::new(&target, config.max_hops).map_err(trace_error)?;
+                tracers.push((target, tracer));
+            }
+            Err(e) => eprintln!("{} resolve error: {}", host, e),
+        }
+    }
+
+    println!(
+        "MTR {} hosts with up to {} hops in {} intervals{} ...",
+        tracers.len(),
+        config.max_hops,
+        config.interval.display(),
+        if config.rounds.is_none() {
+            ", press Ctrl-C to stop"
+        } else {
+            ""
+        }
+    );
+
+    // Redraw after every round on a terminal, otherwise print the final report only
+    let live = io::stdout().is_terminal();
+    let mut names = HashMap::new();
+    let mut interval = time::interval(config.interval);
+    let mut rounds: u64 = 0;
+    let interrupted = tokio::signal::ctrl_c();
+    tokio::pin!(interrupted);
+
+    while config.rounds.is_none_or(|limit| rounds < limit) {
+        let round = async {
+            interval.tick().await;
+            join_all(tracers.iter_mut().map(|(_, tracer)| tracer.round())).await
+        };
+        tokio::select! {
+            _ = &mut interrupted => break,
+            results = round => {
+                for result in results {
+                    result.map_err(trace_error)?;
+                }
+            }
+        }
+        rounds += 1;
+
+        for (_, tracer) in tracers.iter_mut() {
+            resolve_hop_names(tracer.hops_mut(), &mut names).await;
+        }
+        if live {
+            print!("\x1B[2J\x1B[H");
+            print_mtr_report(&tracers, rounds);
+        }
+    }
+
+    if !live {
+        print_mtr_report(&tracers, rounds);
+    }
+
     Ok(())
 }
+
+fn run_history(config: HistoryConfig) -> Result<()> {
+    if !config.db.exists() {
+        return Err(anyhow::anyhow!("{} does not exist", config.db.display()));
+    }
+    let runs = history::query(&config.db, &config.host, config.since, config.until)
+        .map_err(|e| anyhow::anyhow!("cannot read {}: {}", config.db.display(), e))?;
+    let range = format!(
+        "between {} and {}",
+        display_time(config.since),
+        display_time(config.until)
+    );
+    if runs.is_empty() {
+        println!("No probes of {} recorded {}", config.host, range);
+        return Ok(());
+    }
+
+    println!("HISTORY {} {}", config.host, range);
+    let mut table = stats::create_history_table(&runs);
+    style_table(&mut table);
+    print!("\n{}\n\n", table);
+    Ok(())
+}
+
+fn show_archive(args: ArchiveArgs) -> Result<()> {
+    let mut archive = Archive::open(&args.file)
+        .map_err(|e| anyhow::anyhow!("cannot read {}: {}", args.file.display(), e))?;
+    let mut rows = archive.rows(args.resolution.level())?;
+    if let Some(last) = args.last {
+        rows.drain(..rows.len().saturating_sub(last as usize));
+    }
+
+    println!(
+        "ARCHIVE {} with {} {} rows",
+        archive.label(),
+        rows.len(),
+        format!("{:?}", args.resolution).to_lowercase()
+    );
+    if args.graph {
+        print!("\n{}\n", stats::format_archive_graph(&rows));
+    } else {
+        let mut table = stats::create_archive_table(&rows);
+        style_table(&mut table);
+        print!("\n{}\n\n", table);
+    }
+    Ok(())
+}
+
+fn print_mtr_report(tracers: &[(PingTarget, Tracer)], rounds: u64) {
+    for (target, tracer) in tracers {
+        println!("MTR {} after {} rounds", target, rounds);
+        print_hop_table(tracer.hops());
+    }
+}
+
+fn print_hop_table(hops: &[Hop]) {
+    let mut table = stats::create_hop_table(hops);
+    style_table(&mut table);
+    print!("\n{}\n\n", table);
+
+    if (0..hops.len()).any(|index| is_transient_loss(hops, index)) {
+        println!(
+            "{} loss does not persist to the destination (likely ICMP rate limiting, not an outage)\n",
+            TRANSIENT_LOSS_MARKER
+        );
+    }
+}
+
+/// Fills in the reverse DNS names of hop responders, looking up each address only once.
+async fn resolve_hop_names(hops: &mut [Hop], names: &mut HashMap<IpAddr, Option<String>>) {
+    let unknown = hops
+        .iter()
+        .filter_map(|hop| hop.responder().map(|t| t.addr))
+        .filter(|addr| !names.contains_key(addr))
+        .collect::<HashSet<_>>();
+    names.extend(
+        join_all(
+            unknown
+                .into_iter()
+                .map(async |addr| (addr, lookup_name(addr).await)),
+        )
+        .await,
+    );
+
+    for hop in hops {
+        if let Some(addr) = hop.responder().map(|t| t.addr) {
+            hop.results.target.host = names.get(&addr).cloned().flatten();
+        }
+    }
+}
+
+fn trace_error(e: io::Error) -> anyhow::Error {
+    anyhow::anyhow!("trace failed (raw sockets need root or CAP_NET_RAW): {}", e)
+}
+
+fn style_table(table: &mut Table) {
+    table
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .load_preset(UTF8_BORDERS_ONLY)
+        .apply_modifier(UTF8_ROUND_CORNERS);
+}
@@ -0,0 +1,559 @@
+//! Per-target counters, RTT histograms and up gauges for `mping serve`, and the final results
+//! of a run for the node_exporter textfile collector, both in the Prometheus text exposition
+//! format.
+
+use crate::core::constants::{PERCENTAGE_FACTOR, RTT_BUCKETS};
+use crate::events::PingEvent;
+use crate::network::client::PingTarget;
+use crate::network::ping::PingResults;
+use crate::state::{HostState, HostTracker, StateThresholds};
+use crate::stats::OverallStats;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Labels every series gets from its target; user tags may not reuse them.
+const TARGET_LABELS: [&str; 4] = ["host", "addr", "probe", "port"];
+
+/// A user defined label added to every series, given as `KEY=VALUE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))?;
+        let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !key.starts_with("__");
+        if !valid {
+            return Err(format!("invalid label name '{}'", key));
+        }
+        if TARGET_LABELS.contains(&key) || key == "le" {
+            return Err(format!("label '{}' is set by mping", key));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Probe outcomes of one target, summed up since it was registered or last reset.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeTally {
+    pub sent: u64,
+    pub received: u64,
+    /// Answered probes per entry of [`RTT_BUCKETS`] plus one for slower replies, not cumulative.
+    pub buckets: [u64; RTT_BUCKETS.len() + 1],
+    pub rtt_sum: Duration,
+    pub rtt_min: Option<Duration>,
+    pub rtt_max: Option<Duration>,
+}
+
+impl ProbeTally {
+    /// Counts one probe, answered after `rtt` or lost.
+    pub fn record(&mut self, rtt: Option<Duration>) {
+        self.sent += 1;
+        let Some(rtt) = rtt else {
+            return;
+        };
+        self.received += 1;
+        self.rtt_sum += rtt;
+        self.rtt_min = Some(self.rtt_min.map_or(rtt, |min| min.min(rtt)));
+        self.rtt_max = Some(self.rtt_max.map_or(rtt, |max| max.max(rtt)));
+        let seconds = rtt.as_secs_f64();
+        let bucket = RTT_BUCKETS
+            .iter()
+            .position(|&le| seconds <= le)
+            .unwrap_or(RTT_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.sent - self.received
+    }
+
+    pub fn avg_rtt(&self) -> Option<Duration> {
+        (self.received > 0).then(|| self.rtt_sum / self.received as u32)
+    }
+}
+
+/// State of every target in the order the targets were added, looked up by
+/// [`PingTarget::key`].
+#[derive(Debug)]
+pub struct PerTarget<T> {
+    entries: Vec<T>,
+    index: HashMap<String, usize>,
+}
+
+impl<T> Default for PerTarget<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PerTarget<T> {
+    pub fn insert(&mut self, target: &PingTarget, value: T) {
+        self.index.insert(target.key(), self.entries.len());
+        self.entries.push(value);
+    }
+
+    pub fn get_mut(&mut self, target: &PingTarget) -> Option<&mut T> {
+        let &i = self.index.get(&target.key())?;
+        Some(&mut self.entries[i])
+    }
+
+    /// The state of `target`, added from `value` first if the target is new.
+    pub fn get_or_insert_with(&mut self, target: &PingTarget, value: impl FnOnce() -> T) -> &mut T {
+        let i = *self.index.entry(target.key()).or_insert_with(|| {
+            self.entries.push(value());
+            self.entries.len() - 1
+        });
+        &mut self.entries[i]
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.entries.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.entries.iter_mut()
+    }
+}
+
+#[derive(Debug)]
+struct HostMetrics {
+    /// Label set of the target, already formatted as `name="value",...`.
+    labels: String,
+    tally: ProbeTally,
+    tracker: HostTracker,
+}
+
+/// Metrics of all targets, fed from the live probe events.
+#[derive(Debug)]
+pub struct Metrics {
+    hosts: PerTarget<HostMetrics>,
+}
+
+impl Metrics {
+    /// Registers every target up front, so its series are exposed before the first probe.
+    pub fn new(targets: &[PingTarget], tags: &[Tag], thresholds: StateThresholds) -> Self {
+        let mut hosts = PerTarget::default();
+        for target in targets {
+            hosts.insert(
+                target,
+                HostMetrics {
+                    labels: target_labels(target, tags),
+                    tally: ProbeTally::default(),
+                    tracker: HostTracker::new(thresholds),
+                },
+            );
+        }
+        Self { hosts }
+    }
+
+    /// Counts one probe outcome.  Events of unregistered targets are ignored.
+    pub fn record(&mut self, event: &PingEvent) {
+        let PingEvent::Sample { target, sample, .. } = event;
+        let Some(host) = self.hosts.get_mut(target) else {
+            return;
+        };
+        host.tally.record(sample.rtt);
+        host.tracker.record(sample.at, sample.rtt.is_some());
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.counter(&mut out, "mping_probes_sent_total", "Probes sent.", |h| {
+            h.tally.sent
+        });
+        self.counter(
+            &mut out,
+            "mping_probes_received_total",
+            "Probes answered.",
+            |h| h.tally.received,
+        );
+        self.counter(&mut out, "mping_probes_lost_total", "Probes lost.", |h| {
+            h.tally.lost()
+        });
+
+        let name = "mping_rtt_seconds";
+        header(
+            &mut out,
+            name,
+            "Round-trip time of answered probes.",
+            "histogram",
+        );
+        for host in self.hosts.iter() {
+            let mut cumulative = 0;
+            for (le, count) in RTT_BUCKETS.iter().zip(host.tally.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, host.labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, host.labels, host.tally.received
+            );
+            let _ = writeln!(
+                out,
+                "{}_sum{{{}}} {}",
+                name,
+                host.labels,
+                seconds(host.tally.rtt_sum)
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{{}}} {}",
+                name, host.labels, host.tally.received
+            );
+        }
+
+        let name = "mping_up";
+        header(
+            &mut out,
+            name,
+            "1 unless the host is down after --down-after consecutive lost probes.",
+            "gauge",
+        );
+        for host in self.hosts.iter() {
+            let up = u8::from(host.tracker.state() != HostState::Down);
+            let _ = writeln!(out, "{}{{{}}} {}", name, host.labels, up);
+        }
+        out
+    }
+
+    fn counter(&self, out: &mut String, name: &str, help: &str, value: fn(&HostMetrics) -> u64) {
+        header(out, name, help, "counter");
+        for host in self.hosts.iter() {
+            let _ = writeln!(out, "{}{{{}}} {}", name, host.labels, value(host));
+        }
+    }
+}
+
+/// Value of a per-host gauge, `None` to leave out the host.
+type HostValue = fn(&PingResults) -> Option<f64>;
+
+/// Final results of a run as gauges for the node_exporter textfile collector.  RTT gauges are
+/// left out for hosts that never answered.
+pub fn textfile(results: &[PingResults], overall: &OverallStats, finished: SystemTime) -> String {
+    let mut out = String::new();
+    let hosts = results
+        .iter()
+        .map(|result| (target_labels(&result.target, &[]), result))
+        .collect::<Vec<_>>();
+    let gauges: [(&str, &str, HostValue); 7] = [
+        ("mping_probes_sent", "Probes sent.", |r| {
+            Some(r.total_count() as f64)
+        }),
+        ("mping_probes_received", "Probes answered.", |r| {
+            Some(r.num_recv as f64)
+        }),
+        ("mping_probes_lost", "Probes lost.", |r| {
+            Some(r.num_loss as f64)
+        }),
+        (
+            "mping_loss_ratio",
+            "Share of lost probes from 0 to 1.",
+            |r| Some(r.loss_rate() as f64),
+        ),
+        ("mping_rtt_min_seconds", "Fastest reply.", |r| {
+            r.min_duration.map(seconds)
+        }),
+        ("mping_rtt_avg_seconds", "Mean round-trip time.", |r| {
+            r.avg_duration().map(seconds)
+        }),
+        ("mping_rtt_max_seconds", "Slowest reply.", |r| {
+            r.max_duration.map(seconds)
+        }),
+    ];
+    for (name, help, value) in gauges {
+        header(&mut out, name, help, "gauge");
+        for (labels, result) in &hosts {
+            if let Some(value) = value(result) {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+
+    for (name, help, value) in [
+        (
+            "mping_overall_sent",
+            "Probes sent to all hosts.",
+            overall.total_sent as f64,
+        ),
+        (
+            "mping_overall_received",
+            "Probes answered by all hosts.",
+            overall.total_received as f64,
+        ),
+        (
+            "mping_overall_loss_ratio",
+            "Share of lost probes over all hosts from 0 to 1.",
+            overall.loss_percentage / PERCENTAGE_FACTOR,
+        ),
+        (
+            "mping_hosts_alive",
+            "Hosts that answered at least one probe.",
+            overall.hosts_alive as f64,
+        ),
+        (
+            "mping_last_run_timestamp_seconds",
+            "Unix time the run finished.",
+            finished
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        ),
+    ] {
+        header(&mut out, name, help, "gauge");
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    out
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into place, so the
+/// collector never reads a partial file.  The collector only reads `*.prom` files, so it skips
+/// the temporary one.
+pub fn write_atomically(path: &Path, content: &str) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, path)
+}
+
+/// `name="value"` pairs identifying a target, followed by the user tags.
+fn target_labels(target: &PingTarget, tags: &[Tag]) -> String {
+    let mut labels = vec![
+        ("host", target.host.clone().unwrap_or_default()),
+        ("addr", target.addr.to_string()),
+        ("probe", target.kind.scheme().unwrap_or("icmp").to_string()),
+    ];
+    if let Some(port) = target.kind.port() {
+        labels.push(("port", port.to_string()));
+    }
+    labels.extend(tags.iter().map(|tag| (tag.key.as_str(), tag.value.clone())));
+    labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Divides nanoseconds so values like 0.043 print without float noise.
+fn seconds(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e9
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value for the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::{PingResponse, Sample};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn target() -> PingTarget {
+        PingTarget::with_host("db1".to_string(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+    }
+
+    fn event(target: &PingTarget, rtt_ms: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: target.clone(),
+            seq: 0,
+            sample: Sample {
+                at: SystemTime::now(),
+                rtt: rtt_ms.map(Duration::from_millis),
+                ttl: None,
+            },
+            failure: None,
+        }
+    }
+
+    fn metrics(tags: &[Tag]) -> Metrics {
+        Metrics::new(&[target()], tags, StateThresholds::default())
+    }
+
+    #[test]
+    fn tally_counts_probes_and_buckets_rtts() {
+        let mut tally = ProbeTally::default();
+        tally.record(Some(Duration::from_millis(2)));
+        tally.record(None);
+        tally.record(Some(Duration::from_millis(4)));
+        tally.record(Some(Duration::from_secs(2)));
+        assert_eq!((tally.sent, tally.received, tally.lost()), (4, 3, 1));
+        assert_eq!(tally.rtt_min, Some(Duration::from_millis(2)));
+        assert_eq!(tally.rtt_max, Some(Duration::from_secs(2)));
+        assert_eq!(tally.avg_rtt(), Some(Duration::from_millis(2_006) / 3));
+        // 2 ms is in the 2.5 ms bucket, 4 ms in the 5 ms one and 2 s beyond the largest
+        assert_eq!(tally.buckets[2], 1);
+        assert_eq!(tally.buckets[3], 1);
+        assert_eq!(tally.buckets[RTT_BUCKETS.len()], 1);
+    }
+
+    #[test]
+    fn per_target_tells_apart_probe_kinds() {
+        let tls = target().with_kind(ProbeKind::Tls { port: 443 });
+        let mut hosts = PerTarget::default();
+        hosts.insert(&target(), 1);
+        *hosts.get_or_insert_with(&tls, || 10) += 1;
+        *hosts.get_or_insert_with(&target(), || 100) += 1;
+        assert_eq!(hosts.iter().copied().collect::<Vec<_>>(), vec![2, 11]);
+        assert!(hosts.get_mut(&PingTarget::new(target().addr)).is_none());
+    }
+
+    #[test]
+    fn parses_tags() {
+        assert_eq!(
+            "site=fra1".parse::<Tag>().unwrap(),
+            Tag {
+                key: "site".to_string(),
+                value: "fra1".to_string(),
+            }
+        );
+        assert_eq!("empty=".parse::<Tag>().unwrap().value, "");
+        assert!("site".parse::<Tag>().is_err());
+        assert!("1site=x".parse::<Tag>().is_err());
+        assert!("my-site=x".parse::<Tag>().is_err());
+        assert!("__name__=x".parse::<Tag>().is_err());
+        assert!("host=x".parse::<Tag>().is_err());
+    }
+
+    #[test]
+    fn targets_are_exposed_before_the_first_probe() {
+        let out = metrics(&[]).render();
+        assert!(out.contains("# TYPE mping_probes_sent_total counter\n"));
+        assert!(out.contains(
+            "mping_probes_sent_total{host=\"db1\",addr=\"10.0.0.1\",probe=\"icmp\"} 0\n"
+        ));
+        assert!(out.contains("mping_up{host=\"db1\",addr=\"10.0.0.1\",probe=\"icmp\"} 1\n"));
+    }
+
+    #[test]
+    fn counts_probes_into_cumulative_buckets() {
+        let mut metrics = metrics(&[]);
+        let target = target();
+        metrics.record(&event(&target, Some(3)));
+        metrics.record(&event(&target, Some(40)));
+        metrics.record(&event(&target, None));
+
+        let out = metrics.render();
+        let labels = "host=\"db1\",addr=\"10.0.0.1\",probe=\"icmp\"";
+        for line in [
+            format!("mping_probes_sent_total{{{}}} 3", labels),
+            format!("mping_probes_received_total{{{}}} 2", labels),
+            format!("mping_probes_lost_total{{{}}} 1", labels),
+            format!("mping_rtt_seconds_bucket{{{},le=\"0.0025\"}} 0", labels),
+            format!("mping_rtt_seconds_bucket{{{},le=\"0.005\"}} 1", labels),
+            format!("mping_rtt_seconds_bucket{{{},le=\"0.05\"}} 2", labels),
+            format!("mping_rtt_seconds_bucket{{{},le=\"+Inf\"}} 2", labels),
+            format!("mping_rtt_seconds_sum{{{}}} 0.043", labels),
+            format!("mping_rtt_seconds_count{{{}}} 2", labels),
+        ] {
+            assert!(out.contains(&format!("{}\n", line)), "missing {}", line);
+        }
+    }
+
+    #[test]
+    fn up_gauge_follows_down_after() {
+        let mut metrics = metrics(&[]);
+        let target = target();
+        for _ in 0..3 {
+            metrics.record(&event(&target, None));
+        }
+        assert!(metrics.render().contains("probe=\"icmp\"} 0\n"));
+
+        metrics.record(&event(&target, Some(1)));
+        assert!(
+            metrics
+                .render()
+                .contains("mping_up{host=\"db1\",addr=\"10.0.0.1\",probe=\"icmp\"} 1\n")
+        );
+    }
+
+    #[test]
+    fn tags_and_ports_are_labels() {
+        let tls = PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+            .with_kind(ProbeKind::Tls { port: 443 });
+        let tags = [Tag {
+            key: "site".to_string(),
+            value: "fra \"1\"".to_string(),
+        }];
+        let out = Metrics::new(&[tls], &tags, StateThresholds::default()).render();
+        assert!(out.contains(
+            "mping_up{host=\"\",addr=\"10.0.0.2\",probe=\"tls\",port=\"443\",site=\"fra \\\"1\\\"\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn textfile_has_final_results() {
+        let mut answered = PingResults::new(target());
+        answered.add_received(PingResponse::new(Duration::from_millis(10)));
+        answered.add_received(PingResponse::new(Duration::from_millis(30)));
+        answered.add_loss();
+        answered.add_loss();
+        let silent = PingResults::new(PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9))));
+        let results = [answered, silent];
+
+        let out = textfile(
+            &results,
+            &OverallStats::from_results(&results),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        );
+        let labels = "host=\"db1\",addr=\"10.0.0.1\",probe=\"icmp\"";
+        for line in [
+            "# TYPE mping_probes_sent gauge".to_string(),
+            format!("mping_probes_sent{{{}}} 4", labels),
+            format!("mping_loss_ratio{{{}}} 0.5", labels),
+            format!("mping_rtt_avg_seconds{{{}}} 0.02", labels),
+            "mping_probes_sent{host=\"\",addr=\"10.0.0.9\",probe=\"icmp\"} 0".to_string(),
+            "mping_overall_loss_ratio 0.5".to_string(),
+            "mping_hosts_alive 1".to_string(),
+            "mping_last_run_timestamp_seconds 1700000000".to_string(),
+        ] {
+            assert!(out.contains(&format!("{}\n", line)), "missing {}", line);
+        }
+        assert!(!out.contains("mping_rtt_min_seconds{host=\"\""));
+    }
+
+    #[test]
+    fn textfile_is_replaced_atomically() {
+        let path = std::env::temp_dir().join(format!("mping-{}.prom", std::process::id()));
+        write_atomically(&path, "old 1\n").unwrap();
+        write_atomically(&path, "new 1\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new 1\n");
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+        fs::remove_file(path).unwrap();
+    }
+}
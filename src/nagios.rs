@@ -0,0 +1,370 @@
+//! Nagios/Icinga plugin output: one status line with perfdata and the matching exit code.
+
+use crate::core::constants::PERCENTAGE_FACTOR;
+use crate::network::ping::PingResults;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Plugin state, ordered by severity.  UNKNOWN is reported for runtime errors only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl Status {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+            Status::Unknown => 3,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Ok => write!(f, "OK"),
+            Status::Warning => write!(f, "WARNING"),
+            Status::Critical => write!(f, "CRITICAL"),
+            Status::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+/// Round trip average and packet loss limits in `check_ping` syntax, e.g. `100,20%`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NagiosThreshold {
+    pub rta: Duration,
+    /// Packet loss in percent.
+    pub pl: f64,
+}
+
+impl NagiosThreshold {
+    /// `check_ping`'s example warning threshold.
+    pub const DEFAULT_WARNING: Self = Self {
+        rta: Duration::from_millis(100),
+        pl: 20.0,
+    };
+    /// `check_ping`'s example critical threshold.
+    pub const DEFAULT_CRITICAL: Self = Self {
+        rta: Duration::from_millis(500),
+        pl: 60.0,
+    };
+
+    /// Whether a host with the given average RTT and loss reaches this threshold.  Like
+    /// `check_ping`, reaching the limit is enough.
+    fn reached(&self, rta: Option<Duration>, pl: f64) -> bool {
+        pl >= self.pl || rta.is_some_and(|rta| rta >= self.rta)
+    }
+}
+
+impl FromStr for NagiosThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rta, pl) = s
+            .split_once(',')
+            .ok_or_else(|| format!("expected RTA,PL%, got '{}'", s))?;
+        let rta = rta
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|ms| ms.is_finite() && *ms >= 0.0)
+            .ok_or_else(|| format!("invalid round trip average '{}' in ms", rta))?;
+        let pl = pl
+            .trim()
+            .trim_end_matches('%')
+            .parse::<f64>()
+            .ok()
+            .filter(|pl| (0.0..=100.0).contains(pl))
+            .ok_or_else(|| format!("invalid packet loss '{}', expected 0-100%", pl))?;
+
+        Ok(Self {
+            rta: Duration::from_secs_f64(rta / 1000.0),
+            pl,
+        })
+    }
+}
+
+/// One host's contribution to the status line.
+#[derive(Debug, Clone)]
+struct HostCheck {
+    label: String,
+    status: Status,
+    rta: Option<Duration>,
+    pl: f64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+/// Outcome of a check over all hosts.
+#[derive(Debug, Clone)]
+pub struct NagiosReport {
+    warning: NagiosThreshold,
+    critical: NagiosThreshold,
+    hosts: Vec<HostCheck>,
+    /// Hosts given on the command line that could not be resolved.
+    unresolved: usize,
+}
+
+impl NagiosReport {
+    pub fn new(
+        results: &[PingResults],
+        warning: NagiosThreshold,
+        critical: NagiosThreshold,
+        unresolved: usize,
+    ) -> Self {
+        let hosts = results
+            .iter()
+            .map(|result| {
+                let rta = result.avg_duration();
+                let pl = if result.total_count() > 0 {
+                    result.loss_rate() as f64 * PERCENTAGE_FACTOR
+                } else {
+                    PERCENTAGE_FACTOR
+                };
+                let status = if critical.reached(rta, pl) {
+                    Status::Critical
+                } else if warning.reached(rta, pl) {
+                    Status::Warning
+                } else {
+                    Status::Ok
+                };
+                HostCheck {
+                    label: perf_label(match result.target.host {
+                        Some(_) => result.target.label(),
+                        None => result.target.addr.to_string(),
+                    }),
+                    status,
+                    rta,
+                    pl,
+                    min: result.min_duration,
+                    max: result.max_duration,
+                }
+            })
+            .collect();
+
+        Self {
+            warning,
+            critical,
+            hosts,
+            unresolved,
+        }
+    }
+
+    /// Worst state of any host; unresolved hosts are critical.
+    pub fn status(&self) -> Status {
+        let worst = self
+            .hosts
+            .iter()
+            .map(|host| host.status)
+            .max()
+            .unwrap_or(Status::Ok);
+        if self.unresolved > 0 {
+            worst.max(Status::Critical)
+        } else {
+            worst
+        }
+    }
+
+    fn summary(&self) -> String {
+        let total = self.hosts.len() + self.unresolved;
+        let ok = self
+            .hosts
+            .iter()
+            .filter(|host| host.status == Status::Ok)
+            .count();
+        let mut summary = format!("{}/{} hosts OK", ok, total);
+        if self.unresolved > 0 {
+            summary.push_str(&format!(", {} not resolved", self.unresolved));
+        }
+        for host in self.hosts.iter().filter(|host| host.status != Status::Ok) {
+            summary.push_str(&format!(
+                ", {} {}: loss {:.0}%, RTA {}",
+                host.label.trim_matches('\''),
+                host.status,
+                host.pl,
+                host.rta
+                    .map_or("-".to_string(), |rta| format!("{:.2} ms", millis(rta)))
+            ));
+        }
+        summary
+    }
+
+    fn perfdata(&self) -> String {
+        let (w, c) = (self.warning, self.critical);
+        self.hosts
+            .iter()
+            .map(|host| {
+                let label = |metric: &str| match host.label.strip_suffix('\'') {
+                    Some(quoted) => format!("{}_{}'", quoted, metric),
+                    None => format!("{}_{}", host.label, metric),
+                };
+                format!(
+                    "{}={};{:.3};{:.3};0 {}={:.0}%;{};{};0;100 {}={};;;0 {}={};;;0",
+                    label("rta"),
+                    perf_value(host.rta),
+                    millis(w.rta),
+                    millis(c.rta),
+                    label("pl"),
+                    host.pl,
+                    w.pl,
+                    c.pl,
+                    label("rtmin"),
+                    perf_value(host.min),
+                    label("rtmax"),
+                    perf_value(host.max),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for NagiosReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PING {} - {}|{}",
+            self.status(),
+            self.summary(),
+            self.perfdata()
+        )
+    }
+}
+
+/// Perfdata labels may not contain `=` or `'`, and must be quoted if they contain spaces.
+fn perf_label(name: String) -> String {
+    let name = name.replace(['=', '\''], "_");
+    if name.contains(' ') {
+        format!("'{}'", name)
+    } else {
+        name
+    }
+}
+
+/// A time in milliseconds, or `U` without a unit for a value that could not be measured.
+fn perf_value(duration: Option<Duration>) -> String {
+    duration.map_or("U".to_string(), |d| format!("{:.3}ms", millis(d)))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::PingTarget;
+    use crate::network::ping::PingResponse;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn results(host: &str, rtts_ms: &[Option<u64>]) -> PingResults {
+        let target = PingTarget::with_host(host.to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mut results = PingResults::new(target);
+        for rtt in rtts_ms {
+            match rtt {
+                Some(ms) => results.add_received(PingResponse::new(Duration::from_millis(*ms))),
+                None => results.add_loss(),
+            }
+        }
+        results
+    }
+
+    fn report(results: &[PingResults], unresolved: usize) -> NagiosReport {
+        NagiosReport::new(
+            results,
+            NagiosThreshold::DEFAULT_WARNING,
+            NagiosThreshold::DEFAULT_CRITICAL,
+            unresolved,
+        )
+    }
+
+    #[test]
+    fn parses_check_ping_thresholds() {
+        assert_eq!(
+            "100.5,20%".parse::<NagiosThreshold>().unwrap(),
+            NagiosThreshold {
+                rta: Duration::from_micros(100_500),
+                pl: 20.0,
+            }
+        );
+        assert_eq!("50,5".parse::<NagiosThreshold>().unwrap().pl, 5.0);
+        assert!("100".parse::<NagiosThreshold>().is_err());
+        assert!("100,120%".parse::<NagiosThreshold>().is_err());
+        assert!("-1,20%".parse::<NagiosThreshold>().is_err());
+    }
+
+    #[test]
+    fn all_hosts_ok() {
+        let report = report(
+            &[
+                results("db1", &[Some(10), Some(20)]),
+                results("db2", &[Some(5)]),
+            ],
+            0,
+        );
+        assert_eq!(report.status(), Status::Ok);
+        assert_eq!(
+            report.to_string(),
+            "PING OK - 2/2 hosts OK|db1_rta=15.000ms;100.000;500.000;0 db1_pl=0%;20;60;0;100 \
+             db1_rtmin=10.000ms;;;0 db1_rtmax=20.000ms;;;0 db2_rta=5.000ms;100.000;500.000;0 \
+             db2_pl=0%;20;60;0;100 db2_rtmin=5.000ms;;;0 db2_rtmax=5.000ms;;;0"
+        );
+    }
+
+    #[test]
+    fn worst_host_decides_status() {
+        let report = report(
+            &[
+                results("db1", &[Some(10)]),
+                results("db2", &[Some(150), Some(150)]),
+            ],
+            0,
+        );
+        assert_eq!(report.status(), Status::Warning);
+        assert_eq!(report.status().exit_code(), 1);
+        assert!(
+            report
+                .to_string()
+                .starts_with("PING WARNING - 1/2 hosts OK, db2 WARNING: loss 0%, RTA 150.00 ms|")
+        );
+    }
+
+    #[test]
+    fn unreachable_host_is_critical_with_unknown_rta() {
+        let report = report(&[results("db1", &[None, None])], 0);
+        assert_eq!(report.status(), Status::Critical);
+        let line = report.to_string();
+        assert!(line.contains("db1 CRITICAL: loss 100%, RTA -"));
+        assert!(line.contains("db1_rta=U;100.000;500.000;0"));
+        assert!(line.contains("db1_pl=100%;20;60;0;100"));
+    }
+
+    #[test]
+    fn unresolved_hosts_are_critical() {
+        let report = report(&[results("db1", &[Some(10)])], 1);
+        assert_eq!(report.status(), Status::Critical);
+        assert!(
+            report
+                .to_string()
+                .starts_with("PING CRITICAL - 1/2 hosts OK, 1 not resolved|")
+        );
+    }
+
+    #[test]
+    fn perf_labels_are_quoted_when_needed() {
+        assert_eq!(perf_label("db1".to_string()), "db1");
+        assert_eq!(perf_label("a=b".to_string()), "a_b");
+        assert_eq!(perf_label("my host".to_string()), "'my host'");
+
+        let report = report(&[results("my host", &[Some(10)])], 0);
+        assert!(report.to_string().contains("'my host_rta'=10.000ms"));
+    }
+}
@@ -25,23 +25,109 @@ impl PingClients {
     }
 }
 
-#[derive(Debug)]
+/// How a target is probed.  Plain hosts are pinged via ICMP, `tls://host:port` targets are
+/// timed by opening a TCP connection and completing a TLS handshake and `ntp://host` targets
+/// are queried via SNTP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProbeKind {
+    #[default]
+    Icmp,
+    Tls {
+        port: u16,
+    },
+    Ntp {
+        port: u16,
+    },
+}
+
+impl ProbeKind {
+    /// Returns the URL scheme prefix used for this kind of probe, if any.
+    pub fn scheme(&self) -> Option<&'static str> {
+        match self {
+            ProbeKind::Icmp => None,
+            ProbeKind::Tls { .. } => Some("tls"),
+            ProbeKind::Ntp { .. } => Some("ntp"),
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            ProbeKind::Icmp => None,
+            ProbeKind::Tls { port } | ProbeKind::Ntp { port } => Some(*port),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PingTarget {
     pub host: Option<String>,
     pub addr: IpAddr,
+    pub kind: ProbeKind,
 }
 
 impl PingTarget {
     pub fn new(addr: IpAddr) -> Self {
-        Self { host: None, addr }
+        Self {
+            host: None,
+            addr,
+            kind: ProbeKind::Icmp,
+        }
     }
 
     pub fn with_host(host: String, addr: IpAddr) -> Self {
         Self {
             host: Some(host),
             addr,
+            kind: ProbeKind::Icmp,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: ProbeKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Label shown in the host column, e.g. `example.com` or `tls://example.com:443`.
+    pub fn label(&self) -> String {
+        self.label_for(self.host.as_deref().unwrap_or("-"))
+    }
+
+    /// Like [`label`](Self::label), but with the address standing in for a missing host name,
+    /// e.g. `10.0.0.1` or `ntp://10.0.0.1:123`, for outputs that must identify the host on
+    /// their own.
+    pub fn host_label(&self) -> String {
+        match (&self.host, self.addr) {
+            (Some(host), _) => self.label_for(host),
+            (None, IpAddr::V6(addr)) if self.kind.scheme().is_some() => {
+                self.label_for(&format!("[{}]", addr))
+            }
+            (None, addr) => self.label_for(&addr.to_string()),
         }
     }
+
+    fn label_for(&self, host: &str) -> String {
+        match (self.kind.scheme(), self.kind.port()) {
+            (Some(scheme), Some(port)) => format!("{}://{}:{}", scheme, host, port),
+            _ => host.to_string(),
+        }
+    }
+
+    /// The host name, or the address without one, with the probe kind and port appended for
+    /// TLS and NTP targets, e.g. `api.example.com_tls_443`.  Outputs that name things after the
+    /// target escape it as they need.
+    pub fn name(&self) -> String {
+        let host = self.host.clone().unwrap_or_else(|| self.addr.to_string());
+        match (self.kind.scheme(), self.kind.port()) {
+            (Some(scheme), Some(port)) => format!("{}_{}_{}", host, scheme, port),
+            _ => host,
+        }
+    }
+
+    /// Identifies the target among those of a run, for looking up per-target state.  The label
+    /// tells apart probes of different kinds against the same address.
+    pub fn key(&self) -> String {
+        format!("{} {}", self.label(), self.addr)
+    }
 }
 
 impl std::fmt::Display for PingTarget {
@@ -87,4 +173,30 @@ mod tests {
         let target = PingTarget::with_host("localhost".to_string(), addr);
         assert_eq!(format!("{}", target), "localhost (::1)");
     }
+
+    #[test]
+    fn ping_target_defaults_to_icmp() {
+        let target = PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(target.kind, ProbeKind::Icmp);
+        assert_eq!(target.label(), "-");
+    }
+
+    #[test]
+    fn ping_target_label_includes_tls_scheme_and_port() {
+        let target =
+            PingTarget::with_host("example.com".to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_kind(ProbeKind::Tls { port: 8443 });
+        assert_eq!(target.label(), "tls://example.com:8443");
+    }
+
+    #[test]
+    fn ping_target_host_label_falls_back_to_address() {
+        let target = PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(target.host_label(), "10.0.0.1");
+        let ntp = target.with_kind(ProbeKind::Ntp { port: 123 });
+        assert_eq!(ntp.host_label(), "ntp://10.0.0.1:123");
+        let tls = PingTarget::new(IpAddr::V6(Ipv6Addr::LOCALHOST))
+            .with_kind(ProbeKind::Tls { port: 443 });
+        assert_eq!(tls.host_label(), "tls://[::1]:443");
+    }
 }
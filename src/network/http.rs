@@ -0,0 +1,360 @@
+//! Minimal HTTP/1.1 client for posting to webhooks and write endpoints, and server for exposing
+//! metrics.
+
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+/// An `http://` or `https://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, rest) = if let Some(rest) = s.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("expected an http:// or https:// URL, got '{}'", s));
+        };
+
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("unterminated IPv6 address in '{}'", s))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", s));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("invalid port '{}' in '{}'", port, s))?,
+            None => default_port,
+        };
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]:{}{}", scheme, self.host, self.port, self.path)
+        } else {
+            write!(f, "{}://{}:{}{}", scheme, self.host, self.port, self.path)
+        }
+    }
+}
+
+/// Posts `body` as JSON to `url` and returns the response status code.  The response body is
+/// not read.
+pub async fn post_json(url: &HttpUrl, body: &str) -> io::Result<u16> {
+    post(url, "application/json", &[], body).await
+}
+
+/// Posts `body` with the given content type and extra headers to `url` and returns the response
+/// status code.  The response body is not read.
+pub async fn post(
+    url: &HttpUrl,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> io::Result<u16> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let request = request(url, content_type, headers, body);
+    if !url.tls {
+        return send(stream, &request).await;
+    }
+
+    let server_name = ServerName::try_from(url.host.clone())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let stream = connector().connect(server_name, stream).await?;
+    send(stream, &request).await
+}
+
+fn request(url: &HttpUrl, content_type: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let host = if url.host.contains(':') {
+        format!("[{}]", url.host)
+    } else {
+        url.host.clone()
+    };
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect::<String>();
+    format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: mping/{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        url.path,
+        host,
+        url.port,
+        env!("CARGO_PKG_VERSION"),
+        content_type,
+        body.len(),
+        headers,
+        body
+    )
+}
+
+async fn send<S>(stream: S, request: &str) -> io::Result<u16>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    stream.get_mut().write_all(request.as_bytes()).await?;
+    stream.get_mut().flush().await?;
+
+    let mut status_line = String::new();
+    stream.read_line(&mut status_line).await?;
+    parse_status(&status_line).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid HTTP status line '{}'", status_line.trim_end()),
+        )
+    })
+}
+
+/// Status code from a line like `HTTP/1.1 204 No Content`.
+fn parse_status(line: &str) -> Option<u16> {
+    let mut parts = line.split_whitespace();
+    parts
+        .next()
+        .filter(|version| version.starts_with("HTTP/"))?;
+    parts.next()?.parse().ok()
+}
+
+/// Answers `GET path` with the text returned by `body`, one request per connection.  Other
+/// paths get 404 and other methods 405.  Runs until the task is dropped.
+pub async fn serve<F>(
+    listener: TcpListener,
+    path: &'static str,
+    content_type: &'static str,
+    body: F,
+) where
+    F: Fn() -> String + Clone + Send + 'static,
+{
+    loop {
+        // Errors like a connection reset before it was accepted only affect that connection
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let body = body.clone();
+        tokio::spawn(async move {
+            let _ = respond(stream, path, content_type, body).await;
+        });
+    }
+}
+
+async fn respond<S>(
+    stream: S,
+    path: &str,
+    content_type: &str,
+    body: impl Fn() -> String,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    // The headers are of no interest, but must be read before the client expects a response
+    loop {
+        let mut header = String::new();
+        if stream.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) if target.split('?').next() == Some(path) => {
+            ("200 OK", content_type, body())
+        }
+        (Some("GET"), _) => ("404 Not Found", "text/plain", format!("Try {}\n", path)),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    stream.get_mut().shutdown().await
+}
+
+/// TLS client verifying servers against the Mozilla roots, shared with other clients.
+pub(crate) fn connector() -> TlsConnector {
+    let roots = rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring provider supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn parses_urls() {
+        let url = "https://hooks.slack.com/services/T0/B0/x"
+            .parse::<HttpUrl>()
+            .unwrap();
+        assert_eq!(
+            url,
+            HttpUrl {
+                tls: true,
+                host: "hooks.slack.com".to_string(),
+                port: 443,
+                path: "/services/T0/B0/x".to_string(),
+            }
+        );
+
+        let url = "http://127.0.0.1:8080".parse::<HttpUrl>().unwrap();
+        assert_eq!((url.port, url.path.as_str()), (8080, "/"));
+
+        let url = "http://[::1]:9000/hook?key=a=b".parse::<HttpUrl>().unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.path, "/hook?key=a=b");
+
+        let url = "http://example.com?token=1".parse::<HttpUrl>().unwrap();
+        assert_eq!(url.path, "/?token=1");
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!("ftp://example.com".parse::<HttpUrl>().is_err());
+        assert!("http://:80/".parse::<HttpUrl>().is_err());
+        assert!("http://example.com:http/".parse::<HttpUrl>().is_err());
+        assert!("http://[::1/".parse::<HttpUrl>().is_err());
+    }
+
+    #[test]
+    fn parses_status_line() {
+        assert_eq!(parse_status("HTTP/1.1 204 No Content\r\n"), Some(204));
+        assert_eq!(parse_status("HTTP/1.0 500\r\n"), Some(500));
+        assert_eq!(parse_status("SSH-2.0-OpenSSH\r\n"), None);
+        assert_eq!(parse_status(""), None);
+    }
+
+    #[tokio::test]
+    async fn post_json_sends_request_and_reads_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            // The client closes its side only after reading the response, so read until the body
+            // is complete
+            while !request.ends_with(b"{\"ok\":true}") {
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = format!("http://127.0.0.1:{}/hook", port).parse().unwrap();
+        let status = post_json(&url, "{\"ok\":true}").await.unwrap();
+        let request = server.await.unwrap();
+
+        assert_eq!(status, 202);
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.contains("Content-Length: 11\r\n"));
+    }
+
+    #[test]
+    fn request_carries_extra_headers() {
+        let url = "http://localhost:8086/api/v2/write?bucket=net"
+            .parse()
+            .unwrap();
+        let request = request(
+            &url,
+            "text/plain",
+            &[("Authorization", "Token secret")],
+            "m v=1i",
+        );
+        assert!(request.starts_with("POST /api/v2/write?bucket=net HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Token secret\r\n"));
+        assert!(request.ends_with("Connection: close\r\n\r\nm v=1i"));
+    }
+
+    async fn get(port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serve_answers_only_its_path() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve(listener, "/metrics", "text/plain", || {
+            "up 1\n".to_string()
+        }));
+
+        let response = get(port, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\nup 1\n"));
+
+        let response = get(port, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get(port, "POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        server.abort();
+    }
+}
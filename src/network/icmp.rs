@@ -0,0 +1,380 @@
+//! Raw ICMP echo probes for the cases surge-ping cannot handle.
+//!
+//! surge-ping matches replies to pingers by the replying address, so ICMP errors sent by routers
+//! along the path ("time exceeded", "fragmentation needed") never reach a `Pinger`.  This module
+//! sends echo requests on its own raw socket and matches replies and errors by identifier and
+//! sequence number instead, which requires root or `CAP_NET_RAW`.
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV4_UNREACHABLE: u8 = 3;
+const ICMPV4_FRAGMENTATION_NEEDED: u8 = 4;
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_UNREACHABLE: u8 = 1;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const ICMP_HEADER_LEN: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ReplyKind {
+    EchoReply,
+    TimeExceeded,
+    Unreachable,
+    /// "Fragmentation needed" (ICMPv4) or "packet too big" (ICMPv6).  `mtu` is the next-hop MTU
+    /// reported by the router, or 0 if it did not report one.
+    TooBig {
+        mtu: u32,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct IcmpReply {
+    pub kind: ReplyKind,
+    pub ident: u16,
+    pub seq: u16,
+}
+
+/// Size of the IP and ICMP headers in front of the echo payload.
+pub(crate) fn header_overhead(destination: IpAddr) -> usize {
+    ICMP_HEADER_LEN
+        + match destination {
+            IpAddr::V4(_) => IPV4_HEADER_LEN,
+            IpAddr::V6(_) => IPV6_HEADER_LEN,
+        }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds an echo request with `payload_size` zero bytes of payload.  The kernel fills in the
+/// ICMPv6 checksum for raw sockets.
+pub(crate) fn echo_request(
+    destination: IpAddr,
+    ident: u16,
+    seq: u16,
+    payload_size: usize,
+) -> Vec<u8> {
+    let mut packet = vec![0; ICMP_HEADER_LEN + payload_size];
+    packet[0] = match destination {
+        IpAddr::V4(_) => ICMPV4_ECHO_REQUEST,
+        IpAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+    };
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    if destination.is_ipv4() {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    packet
+}
+
+/// Reads identifier and sequence number from an echo header.
+fn echo_ids(icmp: &[u8]) -> Option<(u16, u16)> {
+    let ident = u16::from_be_bytes(icmp.get(4..6)?.try_into().ok()?);
+    let seq = u16::from_be_bytes(icmp.get(6..8)?.try_into().ok()?);
+    Some((ident, seq))
+}
+
+/// Parses a packet received on a raw ICMPv4 socket, which includes the IPv4 header.
+fn parse_v4(buf: &[u8]) -> Option<IcmpReply> {
+    let header_len = (*buf.first()? & 0x0f) as usize * 4;
+    let icmp = buf.get(header_len..)?;
+    let kind = match (*icmp.first()?, *icmp.get(1)?) {
+        (ICMPV4_ECHO_REPLY, _) => {
+            let (ident, seq) = echo_ids(icmp)?;
+            return Some(IcmpReply {
+                kind: ReplyKind::EchoReply,
+                ident,
+                seq,
+            });
+        }
+        (ICMPV4_TIME_EXCEEDED, _) => ReplyKind::TimeExceeded,
+        (ICMPV4_UNREACHABLE, ICMPV4_FRAGMENTATION_NEEDED) => ReplyKind::TooBig {
+            mtu: u16::from_be_bytes(icmp.get(6..8)?.try_into().ok()?) as u32,
+        },
+        (ICMPV4_UNREACHABLE, _) => ReplyKind::Unreachable,
+        _ => return None,
+    };
+
+    // Error messages quote our original IPv4 header followed by the echo request header
+    let quoted = icmp.get(ICMP_HEADER_LEN..)?;
+    let quoted_header_len = (*quoted.first()? & 0x0f) as usize * 4;
+    let request = quoted.get(quoted_header_len..)?;
+    if *request.first()? != ICMPV4_ECHO_REQUEST {
+        return None;
+    }
+    let (ident, seq) = echo_ids(request)?;
+    Some(IcmpReply { kind, ident, seq })
+}
+
+/// Parses a packet received on a raw ICMPv6 socket, which starts at the ICMPv6 header.
+fn parse_v6(icmp: &[u8]) -> Option<IcmpReply> {
+    let kind = match *icmp.first()? {
+        ICMPV6_ECHO_REPLY => {
+            let (ident, seq) = echo_ids(icmp)?;
+            return Some(IcmpReply {
+                kind: ReplyKind::EchoReply,
+                ident,
+                seq,
+            });
+        }
+        ICMPV6_TIME_EXCEEDED => ReplyKind::TimeExceeded,
+        ICMPV6_UNREACHABLE => ReplyKind::Unreachable,
+        ICMPV6_PACKET_TOO_BIG => ReplyKind::TooBig {
+            mtu: u32::from_be_bytes(icmp.get(4..8)?.try_into().ok()?),
+        },
+        _ => return None,
+    };
+
+    let request = icmp.get(ICMP_HEADER_LEN + IPV6_HEADER_LEN..)?;
+    if *request.first()? != ICMPV6_ECHO_REQUEST {
+        return None;
+    }
+    let (ident, seq) = echo_ids(request)?;
+    Some(IcmpReply { kind, ident, seq })
+}
+
+/// Raw ICMP socket sending to a single destination.
+pub(crate) struct IcmpSocket {
+    socket: UdpSocket,
+    destination: IpAddr,
+}
+
+impl IcmpSocket {
+    pub fn new(destination: IpAddr) -> io::Result<Self> {
+        let (domain, protocol) = match destination {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        let socket = Socket::new(domain, Type::RAW, Some(protocol))?;
+        socket.set_nonblocking(true)?;
+        // Same approach as surge-ping: tokio's UdpSocket only needs a datagram-style fd
+        let socket = UdpSocket::from_std(std::net::UdpSocket::from(socket))?;
+        Ok(Self {
+            socket,
+            destination,
+        })
+    }
+
+    pub fn set_ttl(&self, ttl: u8) -> io::Result<()> {
+        let sock = SockRef::from(&self.socket);
+        match self.destination {
+            IpAddr::V4(_) => sock.set_ttl(ttl as u32),
+            IpAddr::V6(_) => sock.set_unicast_hops_v6(ttl as u32),
+        }
+    }
+
+    /// Sets the don't-fragment flag on outgoing packets.  The kernel's cached path MTU is
+    /// ignored so that oversized probes still leave the host and routers get to reject them;
+    /// sending more than the local interface MTU fails with `EMSGSIZE`.
+    #[cfg(target_os = "linux")]
+    pub fn set_dont_fragment(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let (level, name, value) = match self.destination {
+            IpAddr::V4(_) => (
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            ),
+            IpAddr::V6(_) => (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            ),
+        };
+        // SAFETY: the fd is owned by `self.socket` and `value` outlives the call
+        let rc = unsafe {
+            libc::setsockopt(
+                self.socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if rc == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_dont_fragment(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "setting the don't-fragment flag is only supported on Linux",
+        ))
+    }
+
+    pub async fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.socket
+            .send_to(packet, SocketAddr::new(self.destination, 0))
+            .await?;
+        Ok(())
+    }
+
+    /// Receives the next ICMP packet and returns it if it answers one of our echo requests,
+    /// together with the address it came from.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<(Option<IcmpReply>, IpAddr)> {
+        let (len, from) = self.socket.recv_from(buf).await?;
+        let reply = match from.ip() {
+            IpAddr::V4(_) => parse_v4(&buf[..len]),
+            IpAddr::V6(_) => parse_v6(&buf[..len]),
+        };
+        Ok((reply, from.ip()))
+    }
+}
+
+/// Whether a send failed because the packet exceeds the local interface MTU.
+pub(crate) fn is_message_too_long(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn ipv4_header(len: usize) -> Vec<u8> {
+        let mut header = vec![0; len];
+        header[0] = 0x40 | (len / 4) as u8;
+        header
+    }
+
+    #[test]
+    fn checksum_of_valid_packet_is_zero() {
+        let packet = echo_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 0x1234, 7, 56);
+        assert_eq!(checksum(&packet), 0);
+    }
+
+    #[test]
+    fn checksum_handles_odd_length() {
+        assert_eq!(checksum(&[0xff]), !0xff00);
+    }
+
+    #[test]
+    fn echo_request_sets_type_ident_and_seq() {
+        let packet = echo_request(IpAddr::V6(Ipv6Addr::LOCALHOST), 0xabcd, 42, 56);
+        assert_eq!(packet[0], ICMPV6_ECHO_REQUEST);
+        assert_eq!(echo_ids(&packet), Some((0xabcd, 42)));
+        assert_eq!(packet.len(), ICMP_HEADER_LEN + 56);
+    }
+
+    #[test]
+    fn header_overhead_includes_ip_and_icmp_headers() {
+        assert_eq!(header_overhead(IpAddr::V4(Ipv4Addr::LOCALHOST)), 28);
+        assert_eq!(header_overhead(IpAddr::V6(Ipv6Addr::LOCALHOST)), 48);
+    }
+
+    #[test]
+    fn parse_v4_echo_reply() {
+        let mut buf = ipv4_header(20);
+        let mut reply = echo_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 9, 3, 56);
+        reply[0] = ICMPV4_ECHO_REPLY;
+        buf.extend(reply);
+
+        assert_eq!(
+            parse_v4(&buf),
+            Some(IcmpReply {
+                kind: ReplyKind::EchoReply,
+                ident: 9,
+                seq: 3
+            })
+        );
+    }
+
+    #[test]
+    fn parse_v4_time_exceeded_quotes_original_request() {
+        let mut buf = ipv4_header(20);
+        buf.extend([ICMPV4_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0]);
+        buf.extend(ipv4_header(24));
+        buf.extend(&echo_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 9, 5, 56)[..8]);
+
+        assert_eq!(
+            parse_v4(&buf),
+            Some(IcmpReply {
+                kind: ReplyKind::TimeExceeded,
+                ident: 9,
+                seq: 5
+            })
+        );
+    }
+
+    #[test]
+    fn parse_v4_fragmentation_needed_reports_next_hop_mtu() {
+        let mut buf = ipv4_header(20);
+        buf.extend([ICMPV4_UNREACHABLE, ICMPV4_FRAGMENTATION_NEEDED, 0, 0, 0, 0]);
+        buf.extend(1400u16.to_be_bytes());
+        buf.extend(ipv4_header(20));
+        buf.extend(&echo_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 9, 6, 56)[..8]);
+
+        assert_eq!(
+            parse_v4(&buf),
+            Some(IcmpReply {
+                kind: ReplyKind::TooBig { mtu: 1400 },
+                ident: 9,
+                seq: 6
+            })
+        );
+    }
+
+    #[test]
+    fn parse_v4_ignores_echo_requests_and_truncated_packets() {
+        let mut buf = ipv4_header(20);
+        buf.extend(echo_request(IpAddr::V4(Ipv4Addr::LOCALHOST), 9, 3, 56));
+        assert_eq!(parse_v4(&buf), None);
+        assert_eq!(parse_v4(&buf[..22]), None);
+    }
+
+    #[test]
+    fn parse_v6_unreachable_quotes_original_request() {
+        let mut buf = vec![ICMPV6_UNREACHABLE, 4, 0, 0, 0, 0, 0, 0];
+        buf.extend([0; IPV6_HEADER_LEN]);
+        buf.extend(&echo_request(IpAddr::V6(Ipv6Addr::LOCALHOST), 77, 8, 56)[..8]);
+
+        assert_eq!(
+            parse_v6(&buf),
+            Some(IcmpReply {
+                kind: ReplyKind::Unreachable,
+                ident: 77,
+                seq: 8
+            })
+        );
+    }
+
+    #[test]
+    fn parse_v6_packet_too_big_reports_mtu() {
+        let mut buf = vec![ICMPV6_PACKET_TOO_BIG, 0, 0, 0];
+        buf.extend(1280u32.to_be_bytes());
+        buf.extend([0; IPV6_HEADER_LEN]);
+        buf.extend(&echo_request(IpAddr::V6(Ipv6Addr::LOCALHOST), 77, 9, 56)[..8]);
+
+        assert_eq!(
+            parse_v6(&buf),
+            Some(IcmpReply {
+                kind: ReplyKind::TooBig { mtu: 1280 },
+                ident: 77,
+                seq: 9
+            })
+        );
+    }
+}
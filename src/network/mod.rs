@@ -1,3 +1,11 @@
 pub mod client;
+pub mod http;
+pub(crate) mod icmp;
+pub mod ntp;
 pub mod ping;
+pub mod pmtu;
+pub mod reachability;
 pub mod resolver;
+pub mod sweep;
+pub mod tls;
+pub mod trace;
@@ -0,0 +1,260 @@
+use crate::core::constants::{DEFAULT_NTP_PORT, LOSS_TIMEOUT};
+use crate::core::error::ProbeError;
+use crate::network::client::PingTarget;
+use crate::network::ping::{PingResponse, PingResults, Probe, Schedule, run_probes};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+const NTP_PACKET_SIZE: usize = 48;
+/// LI = 0 (no warning), VN = 4, Mode = 3 (client)
+const NTP_CLIENT_HEADER: u8 = 0x23;
+const NTP_MODE_SERVER: u8 = 4;
+/// Seconds between the NTP era (1900-01-01) and the UNIX epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// An NTP timestamp: seconds since 1900 in the upper 32 bits, fraction in the lower 32 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NtpTimestamp(u64);
+
+impl NtpTimestamp {
+    fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+        let fraction = (since_epoch.subsec_nanos() as u64) * (1 << 32) / 1_000_000_000;
+        Self((seconds << 32) | fraction)
+    }
+
+    fn read(buf: &[u8]) -> Self {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&buf[..8]);
+        Self(u64::from_be_bytes(bytes))
+    }
+
+    /// Microseconds since the NTP era, rounded to the nearest microsecond.
+    fn as_micros(&self) -> i64 {
+        let seconds = (self.0 >> 32) as i64;
+        let fraction = ((self.0 & 0xffff_ffff) * 1_000_000 + (1 << 31)) >> 32;
+        seconds * 1_000_000 + fraction as i64
+    }
+}
+
+/// Builds a client request carrying `transmit` as its transmit timestamp.
+fn request(transmit: NtpTimestamp) -> [u8; NTP_PACKET_SIZE] {
+    let mut packet = [0; NTP_PACKET_SIZE];
+    packet[0] = NTP_CLIENT_HEADER;
+    packet[40..48].copy_from_slice(&transmit.0.to_be_bytes());
+    packet
+}
+
+/// Server timestamps carried by a reply.
+#[derive(Debug)]
+struct NtpReply {
+    receive: NtpTimestamp,
+    transmit: NtpTimestamp,
+}
+
+fn parse_reply(buf: &[u8], origin: NtpTimestamp) -> Result<NtpReply, ProbeError> {
+    if buf.len() < NTP_PACKET_SIZE {
+        return Err(ProbeError::Ntp(format!(
+            "short packet of {} bytes",
+            buf.len()
+        )));
+    }
+    if buf[0] & 0x07 != NTP_MODE_SERVER {
+        return Err(ProbeError::Ntp(format!(
+            "unexpected mode {}",
+            buf[0] & 0x07
+        )));
+    }
+    if buf[1] == 0 {
+        return Err(ProbeError::Ntp("kiss-of-death (stratum 0)".to_string()));
+    }
+    if NtpTimestamp::read(&buf[24..32]) != origin {
+        return Err(ProbeError::Ntp("origin timestamp mismatch".to_string()));
+    }
+
+    Ok(NtpReply {
+        receive: NtpTimestamp::read(&buf[32..40]),
+        transmit: NtpTimestamp::read(&buf[40..48]),
+    })
+}
+
+/// Computes round-trip delay and clock offset from the four SNTP timestamps (RFC 4330).
+/// `elapsed` is the locally measured time between sending and receiving, which is less prone to
+/// wall clock adjustments than t4 - t1.  Offset is in microseconds.
+fn delay_and_offset(
+    t1: NtpTimestamp,
+    reply: &NtpReply,
+    t4: NtpTimestamp,
+    elapsed: Duration,
+) -> (Duration, i64) {
+    let (t1, t2, t3, t4) = (
+        t1.as_micros(),
+        reply.receive.as_micros(),
+        reply.transmit.as_micros(),
+        t4.as_micros(),
+    );
+    let server_time = Duration::from_micros((t3 - t2).max(0) as u64);
+    let offset = ((t2 - t1) + (t3 - t4)) / 2;
+
+    (elapsed.saturating_sub(server_time), offset)
+}
+
+struct NtpProbe {
+    addr: SocketAddr,
+}
+
+impl Probe for NtpProbe {
+    async fn probe(&mut self, seq: u16) -> Result<PingResponse, ProbeError> {
+        time::timeout(Duration::from_secs(LOSS_TIMEOUT as u64), self.query())
+            .await
+            .map_err(|_| ProbeError::Timeout { seq })?
+    }
+}
+
+impl NtpProbe {
+    async fn query(&self) -> Result<PingResponse, ProbeError> {
+        let local: IpAddr = match self.addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        socket.connect(self.addr).await?;
+
+        let t1 = NtpTimestamp::from_system_time(SystemTime::now());
+        let start = Instant::now();
+        socket.send(&request(t1)).await?;
+
+        let mut buf = [0; 512];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            let elapsed = start.elapsed();
+            let t4 = NtpTimestamp::from_system_time(SystemTime::now());
+            // Ignore stray datagrams that do not answer this request
+            let Ok(reply) = parse_reply(&buf[..len], t1) else {
+                continue;
+            };
+
+            let (delay, offset) = delay_and_offset(t1, &reply, t4, elapsed);
+            return Ok(PingResponse::new(delay).with_offset(offset));
+        }
+    }
+}
+
+/// Sends one SNTP request per scheduled slot and records round-trip delay and clock offset per
+/// reply.
+pub async fn probe(target: PingTarget, schedule: Schedule) -> PingResults {
+    let port = target.kind.port().unwrap_or(DEFAULT_NTP_PORT);
+    let mut probe = NtpProbe {
+        addr: SocketAddr::new(target.addr, port),
+    };
+
+    run_probes(target, schedule, &mut probe).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+
+    /// Answers every request like a server whose clock runs `skew` ahead of ours.
+    async fn spawn_ntp_server(skew: Duration) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0; NTP_PACKET_SIZE];
+            while let Ok((_, peer)) = socket.recv_from(&mut buf).await {
+                let now = NtpTimestamp::from_system_time(SystemTime::now() + skew);
+                let mut reply = [0; NTP_PACKET_SIZE];
+                reply[0] = 0x24; // VN = 4, Mode = 4 (server)
+                reply[1] = 2;
+                reply[24..32].copy_from_slice(&buf[40..48]);
+                reply[32..40].copy_from_slice(&now.0.to_be_bytes());
+                reply[40..48].copy_from_slice(&now.0.to_be_bytes());
+                let _ = socket.send_to(&reply, peer).await;
+            }
+        });
+        port
+    }
+
+    fn reply_for(origin: NtpTimestamp) -> [u8; NTP_PACKET_SIZE] {
+        let mut reply = [0; NTP_PACKET_SIZE];
+        reply[0] = 0x24;
+        reply[1] = 1;
+        reply[24..32].copy_from_slice(&origin.0.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn timestamp_round_trips_unix_epoch() {
+        let ts = NtpTimestamp::from_system_time(UNIX_EPOCH);
+        assert_eq!(ts.0 >> 32, NTP_UNIX_OFFSET);
+        assert_eq!(ts.0 & 0xffff_ffff, 0);
+    }
+
+    #[test]
+    fn timestamp_fraction_has_microsecond_precision() {
+        let ts = NtpTimestamp::from_system_time(UNIX_EPOCH + Duration::from_micros(500_000));
+        assert_eq!(ts.as_micros(), NTP_UNIX_OFFSET as i64 * 1_000_000 + 500_000);
+    }
+
+    #[test]
+    fn request_sets_client_mode_and_transmit_timestamp() {
+        let ts = NtpTimestamp(0x0102_0304_0506_0708);
+        let packet = request(ts);
+        assert_eq!(packet[0], NTP_CLIENT_HEADER);
+        assert_eq!(NtpTimestamp::read(&packet[40..48]), ts);
+    }
+
+    #[test]
+    fn parse_reply_rejects_short_packet() {
+        assert!(parse_reply(&[0; 20], NtpTimestamp(1)).is_err());
+    }
+
+    #[test]
+    fn parse_reply_rejects_origin_mismatch() {
+        let reply = reply_for(NtpTimestamp(1));
+        assert!(parse_reply(&reply, NtpTimestamp(2)).is_err());
+    }
+
+    #[test]
+    fn parse_reply_rejects_kiss_of_death() {
+        let mut reply = reply_for(NtpTimestamp(1));
+        reply[1] = 0;
+        assert!(parse_reply(&reply, NtpTimestamp(1)).is_err());
+    }
+
+    #[test]
+    fn delay_and_offset_follow_rfc_4330() {
+        let base = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let t1 = NtpTimestamp::from_system_time(base);
+        let reply = NtpReply {
+            // Server is 1 s ahead, request takes 10 ms each way, 2 ms processing
+            receive: NtpTimestamp::from_system_time(base + Duration::from_millis(1010)),
+            transmit: NtpTimestamp::from_system_time(base + Duration::from_millis(1012)),
+        };
+        let t4 = NtpTimestamp::from_system_time(base + Duration::from_millis(22));
+
+        let (delay, offset) = delay_and_offset(t1, &reply, t4, Duration::from_millis(22));
+
+        assert_eq!(delay, Duration::from_millis(20));
+        assert_eq!(offset, 1_000_000);
+    }
+
+    #[tokio::test]
+    async fn probe_local_server_records_delay_and_offset() {
+        let port = spawn_ntp_server(Duration::from_secs(2)).await;
+        let target =
+            PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST)).with_kind(ProbeKind::Ntp { port });
+
+        let results = probe(target, Schedule::new(3, Duration::from_millis(100))).await;
+
+        assert_eq!(results.num_recv, 3);
+        assert_eq!(results.num_loss, 0);
+        let offset = results.avg_offset().unwrap();
+        assert!((offset - 2_000_000).abs() < 100_000, "offset {}", offset);
+        assert!(results.min_offset <= results.max_offset);
+    }
+}
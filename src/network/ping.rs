@@ -1,14 +1,24 @@
-use crate::core::constants::LOSS_TIMEOUT;
+use crate::core::constants::{LOSS_TIMEOUT, SAMPLE_HISTORY};
+use crate::core::error::{ProbeError, ProbeFailure};
+use crate::events::{EventSender, PingEvent};
 use crate::network::client::PingTarget;
+use crate::network::pmtu::PathMtu;
+use crate::network::tls::CertificateInfo;
+use crate::state::{HostTracker, StateThresholds};
+use futures::future::join_all;
 use rand::random;
-use std::time::Duration;
-use surge_ping::{Client, IcmpPacket, PingIdentifier, PingSequence};
-use tokio::time;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use surge_ping::{Client, IcmpPacket, PingIdentifier, PingSequence, Pinger};
+use tokio::sync::watch;
+use tokio::time::{self, Interval};
 
 #[derive(Debug)]
 pub struct PingResults {
     pub target: PingTarget,
-    pub responses: Vec<PingResponse>,
+    /// The most recent replies, at most `history` of them.
+    pub responses: VecDeque<PingResponse>,
     pub min_duration: Option<Duration>,
     pub max_duration: Option<Duration>,
     avg_duration: Option<Duration>,
@@ -16,13 +26,31 @@ pub struct PingResults {
     recv_rate: f32,
     pub num_loss: u32,
     loss_rate: f32,
+    pub certificate: Option<CertificateInfo>,
+    pub min_offset: Option<i64>,
+    pub max_offset: Option<i64>,
+    avg_offset: Option<i64>,
+    num_offsets: u32,
+    pub path_mtu: Option<PathMtu>,
+    /// The most recent bursts, at most `history` of them.
+    pub bursts: VecDeque<BurstStats>,
+    num_bursts: u32,
+    worst_burst: Option<BurstStats>,
+    spread_total: Duration,
+    num_spreads: u32,
+    /// The most recent probe outcomes in the order they were recorded, at most `history` of
+    /// them.  Older ones only count towards the running statistics.
+    pub samples: VecDeque<Sample>,
+    history: usize,
+    last_sample: Option<Sample>,
+    tracker: HostTracker,
 }
 
 impl PingResults {
     pub fn new(target: PingTarget) -> Self {
         Self {
             target,
-            responses: Vec::new(),
+            responses: VecDeque::new(),
             min_duration: None,
             max_duration: None,
             avg_duration: None,
@@ -30,9 +58,55 @@ impl PingResults {
             recv_rate: 0.0,
             num_loss: 0,
             loss_rate: 0.0,
+            certificate: None,
+            min_offset: None,
+            max_offset: None,
+            avg_offset: None,
+            num_offsets: 0,
+            path_mtu: None,
+            bursts: VecDeque::new(),
+            num_bursts: 0,
+            worst_burst: None,
+            spread_total: Duration::ZERO,
+            num_spreads: 0,
+            samples: VecDeque::new(),
+            history: SAMPLE_HISTORY,
+            last_sample: None,
+            tracker: HostTracker::new(StateThresholds::default()),
         }
     }
 
+    /// Keeps at most `history` samples, replies and bursts, so long runs use constant memory.
+    pub fn with_history(mut self, history: usize) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Derives the host state with `thresholds` instead of the defaults.
+    pub fn with_thresholds(mut self, thresholds: StateThresholds) -> Self {
+        self.tracker = HostTracker::new(thresholds);
+        self
+    }
+
+    /// State, outages and availability over every probe of the run.
+    pub fn tracker(&self) -> &HostTracker {
+        &self.tracker
+    }
+
+    /// The outcome of the latest probe, kept even if no history is.
+    pub fn last_sample(&self) -> Option<Sample> {
+        self.last_sample
+    }
+
+    /// Sequence number of the oldest probe still in `samples`.
+    pub fn first_seq(&self) -> u64 {
+        (self.total_count() as usize - self.samples.len()) as u64
+    }
+
+    pub fn num_bursts(&self) -> u32 {
+        self.num_bursts
+    }
+
     pub fn recv_rate(&self) -> f32 {
         self.recv_rate
     }
@@ -45,18 +119,100 @@ impl PingResults {
         self.avg_duration
     }
 
+    /// Mean clock offset in microseconds, for probes that measure one (NTP).
+    pub fn avg_offset(&self) -> Option<i64> {
+        self.avg_offset
+    }
+
     pub fn add_received(&mut self, response: PingResponse) {
+        self.push_sample(Sample {
+            at: SystemTime::now(),
+            rtt: Some(response.duration),
+            ttl: response.ttl,
+        });
         self.num_recv += 1;
         self.update_rates();
         self.update_time_stats(response.duration);
-        self.responses.push(response);
+        if let Some(offset) = response.offset {
+            self.update_offset_stats(offset);
+        }
+        push_bounded(&mut self.responses, response, self.history);
     }
 
     pub fn add_loss(&mut self) {
+        self.push_sample(Sample {
+            at: SystemTime::now(),
+            rtt: None,
+            ttl: None,
+        });
         self.num_loss += 1;
         self.update_rates();
     }
 
+    fn push_sample(&mut self, sample: Sample) {
+        self.tracker.record(sample.at, sample.rtt.is_some());
+        self.last_sample = Some(sample);
+        push_bounded(&mut self.samples, sample, self.history);
+    }
+
+    /// Records the outcomes of one burst of back-to-back probes, `None` for each lost probe,
+    /// and returns their samples.  Every probe also counts towards the overall statistics.
+    pub fn add_burst(&mut self, outcomes: Vec<Option<PingResponse>>) -> Vec<Sample> {
+        let sent = outcomes.len() as u32;
+        let mut lost = 0;
+        let mut fastest: Option<Duration> = None;
+        let mut slowest: Option<Duration> = None;
+        let mut samples = Vec::new();
+
+        for outcome in outcomes {
+            match outcome {
+                Some(response) => {
+                    fastest = Some(fastest.map_or(response.duration, |d| d.min(response.duration)));
+                    slowest = Some(slowest.map_or(response.duration, |d| d.max(response.duration)));
+                    self.add_received(response);
+                }
+                None => {
+                    lost += 1;
+                    self.add_loss();
+                }
+            }
+            samples.extend(self.last_sample);
+        }
+
+        let burst = BurstStats {
+            sent,
+            lost,
+            spread: slowest.zip(fastest).map(|(max, min)| max - min),
+        };
+        self.num_bursts += 1;
+        if let Some(spread) = burst.spread {
+            self.spread_total += spread;
+            self.num_spreads += 1;
+        }
+        if self
+            .worst_burst
+            .as_ref()
+            .is_none_or(|worst| burst.lost > worst.lost)
+        {
+            self.worst_burst = Some(burst.clone());
+        }
+        push_bounded(&mut self.bursts, burst, self.history);
+        samples
+    }
+
+    /// The burst with the most lost probes, the earliest one on ties.
+    pub fn worst_burst(&self) -> Option<&BurstStats> {
+        self.worst_burst.as_ref()
+    }
+
+    /// Mean difference between the slowest and fastest reply within a burst.
+    pub fn avg_burst_spread(&self) -> Option<Duration> {
+        if self.num_spreads == 0 {
+            return None;
+        }
+        Some(self.spread_total / self.num_spreads)
+    }
+
     pub fn total_count(&self) -> u32 {
         self.num_recv + self.num_loss
     }
@@ -81,39 +237,308 @@ impl PingResults {
             self.avg_duration = Some(total_time / self.num_recv);
         }
     }
+
+    fn update_offset_stats(&mut self, offset: i64) {
+        self.num_offsets += 1;
+        self.min_offset = Some(self.min_offset.map_or(offset, |min| min.min(offset)));
+        self.max_offset = Some(self.max_offset.map_or(offset, |max| max.max(offset)));
+
+        let n = self.num_offsets as i64;
+        self.avg_offset = Some(match self.avg_offset {
+            Some(current_avg) => (current_avg * (n - 1) + offset) / n,
+            None => offset,
+        });
+    }
+}
+
+/// Appends `item` and drops the oldest items beyond `limit`.
+fn push_bounded<T>(items: &mut VecDeque<T>, item: T, limit: usize) {
+    if limit == 0 {
+        return;
+    }
+    if items.len() == limit {
+        items.pop_front();
+    }
+    items.push_back(item);
 }
 
 #[derive(Debug)]
 pub struct PingResponse {
     pub duration: Duration,
+    /// Clock offset of the target relative to us in microseconds, for probes that measure one.
+    pub offset: Option<i64>,
+    /// TTL of the reply, for probes that see the IP header.
+    pub ttl: Option<u8>,
+}
+
+impl PingResponse {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            offset: None,
+            ttl: None,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Option<u8>) -> Self {
+        self.ttl = ttl;
+        self
+    }
 }
 
-pub async fn ping(client: Client, target: PingTarget, count: u16, delay: Duration) -> PingResults {
-    let payload = [0; 56];
-    let mut pinger = client.pinger(target.addr, PingIdentifier(random())).await;
-    pinger.timeout(Duration::from_secs(LOSS_TIMEOUT as u64));
-    let mut interval = time::interval(delay);
+/// Outcome of a single probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// When the reply arrived or the probe was given up as lost.
+    pub at: SystemTime,
+    /// Round-trip time, `None` if the probe was lost.
+    pub rtt: Option<Duration>,
+    /// TTL of the reply, if known.
+    pub ttl: Option<u8>,
+}
 
-    let mut results: PingResults = PingResults::new(target);
+/// Loss and RTT spread of one burst of back-to-back probes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BurstStats {
+    pub sent: u32,
+    pub lost: u32,
+    /// Slowest minus fastest reply, `None` if every probe was lost.
+    pub spread: Option<Duration>,
+}
 
-    for index in 0..count {
-        interval.tick().await;
-        match pinger.ping(PingSequence(index), &payload).await {
-            Ok((IcmpPacket::V4(_), duration)) => {
-                let response = PingResponse { duration };
+/// A measurement that can be repeated by [`run_probes`], e.g. an ICMP echo or a TLS handshake.
+pub(crate) trait Probe {
+    /// Sends probe number `seq` and returns the measured response.
+    async fn probe(&mut self, seq: u16) -> Result<PingResponse, ProbeError>;
+}
 
-                results.add_received(response);
-            }
-            Ok((IcmpPacket::V6(_), duration)) => {
-                let response = PingResponse { duration };
+pub(crate) struct IcmpProbe {
+    pinger: Pinger,
+    payload: Vec<u8>,
+}
+
+impl IcmpProbe {
+    pub(crate) async fn new(client: &Client, addr: IpAddr, payload_size: usize) -> Self {
+        let mut pinger = client.pinger(addr, PingIdentifier(random())).await;
+        pinger.timeout(Duration::from_secs(LOSS_TIMEOUT as u64));
+        Self {
+            pinger,
+            payload: vec![0; payload_size],
+        }
+    }
+
+    pub(crate) fn set_payload_size(&mut self, payload_size: usize) {
+        self.payload.resize(payload_size, 0);
+    }
+}
+
+impl Probe for IcmpProbe {
+    async fn probe(&mut self, seq: u16) -> Result<PingResponse, ProbeError> {
+        let (packet, duration) = self.pinger.ping(PingSequence(seq), &self.payload).await?;
+        // ICMPv6 sockets do not pass on the hop limit of the reply
+        let ttl = match packet {
+            IcmpPacket::V4(packet) => packet.get_ttl(),
+            IcmpPacket::V6(_) => None,
+        };
+        Ok(PingResponse::new(duration).with_ttl(ttl))
+    }
+}
+
+/// How many probes to send and how far apart, and where to report each outcome while the run is
+/// in progress.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// `None` keeps probing until stopped.
+    pub count: Option<u16>,
+    pub delay: Duration,
+    events: Option<EventSender>,
+    stop: Option<watch::Receiver<bool>>,
+    history: usize,
+    thresholds: StateThresholds,
+}
+
+impl Schedule {
+    pub fn new(count: u16, delay: Duration) -> Self {
+        Self {
+            count: Some(count),
+            delay,
+            events: None,
+            stop: None,
+            history: SAMPLE_HISTORY,
+            thresholds: StateThresholds::default(),
+        }
+    }
+
+    /// Probes until the stop signal is raised.
+    pub fn continuous(delay: Duration) -> Self {
+        Self {
+            count: None,
+            ..Self::new(0, delay)
+        }
+    }
+
+    /// Reports every probe outcome as a [`PingEvent::Sample`].
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Ends the run early once `stop` becomes `true`.
+    pub fn with_stop(mut self, stop: watch::Receiver<bool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Keeps at most `history` probe outcomes per target, see [`PingResults::with_history`].
+    pub fn with_history(mut self, history: usize) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Derives the host state of the results with `thresholds`.
+    pub fn with_thresholds(mut self, thresholds: StateThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    fn results(&self, target: PingTarget) -> PingResults {
+        PingResults::new(target)
+            .with_history(self.history)
+            .with_thresholds(self.thresholds)
+    }
+
+    /// Waits for the slot of probe number `index`.  Returns `false` once all probes were sent or
+    /// the run was stopped.
+    async fn next(&mut self, interval: &mut Interval, index: u64) -> bool {
+        if self.count.is_some_and(|count| index >= count as u64) {
+            return false;
+        }
+        let Some(stop) = self.stop.as_mut() else {
+            interval.tick().await;
+            return true;
+        };
+        tokio::select! {
+            biased;
+            Ok(_) = stop.wait_for(|&stopped| stopped) => false,
+            _ = interval.tick() => true,
+        }
+    }
+
+    /// Reports the latest `samples` of `results`, each with the error that made it a loss.
+    fn emit(
+        &self,
+        results: &PingResults,
+        samples: Vec<Sample>,
+        failures: Vec<Option<ProbeFailure>>,
+    ) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let first = results.total_count() as u64 - samples.len() as u64;
+        for ((seq, sample), failure) in (first..).zip(samples).zip(failures) {
+            // The receiver only goes away when nobody is interested in events any more
+            let _ = events.send(PingEvent::Sample {
+                target: results.target.clone(),
+                seq,
+                sample,
+                failure,
+            });
+        }
+    }
+}
+
+/// Sends echo requests carrying `payload_size` bytes of payload.
+pub async fn ping(
+    client: Client,
+    target: PingTarget,
+    schedule: Schedule,
+    payload_size: usize,
+) -> PingResults {
+    let mut probe = IcmpProbe::new(&client, target.addr, payload_size).await;
+
+    run_probes(target, schedule, &mut probe).await
+}
+
+/// Sends bursts of `burst_size` back-to-back echo requests, one burst per interval.
+pub async fn ping_burst(
+    client: Client,
+    target: PingTarget,
+    schedule: Schedule,
+    payload_size: usize,
+    burst_size: u16,
+) -> PingResults {
+    // A pinger waits for one reply at a time, so each probe in flight needs its own
+    let mut probes = Vec::new();
+    for _ in 0..burst_size {
+        probes.push(IcmpProbe::new(&client, target.addr, payload_size).await);
+    }
+
+    run_bursts(target, schedule, &mut probes).await
+}
+
+/// Runs one burst per scheduled slot.  Each burst fires all `probes` at once.
+pub(crate) async fn run_bursts<P: Probe>(
+    target: PingTarget,
+    mut schedule: Schedule,
+    probes: &mut [P],
+) -> PingResults {
+    let mut interval = time::interval(schedule.delay);
+    let mut results = schedule.results(target);
+    let burst_size = probes.len() as u16;
+    let mut index = 0;
+
+    while schedule.next(&mut interval, index).await {
+        let first_seq = (index as u16).wrapping_mul(burst_size);
+        let outcomes = join_all(
+            probes
+                .iter_mut()
+                .zip(0..)
+                .map(|(probe, offset)| probe.probe(first_seq.wrapping_add(offset))),
+        )
+        .await;
+
+        let failures = outcomes
+            .iter()
+            .map(|outcome| outcome.as_ref().err().map(ProbeFailure::from))
+            .collect();
+        let samples = results.add_burst(outcomes.into_iter().map(Result::ok).collect());
+        schedule.emit(&results, samples, failures);
+        index += 1;
+    }
+    results
+}
 
+/// Runs one probe per scheduled slot and collects the outcomes.  Errors are not printed but
+/// passed on with the schedule's events.
+pub(crate) async fn run_probes<P: Probe>(
+    target: PingTarget,
+    mut schedule: Schedule,
+    probe: &mut P,
+) -> PingResults {
+    let mut interval = time::interval(schedule.delay);
+    let mut results = schedule.results(target);
+    let mut index = 0;
+
+    while schedule.next(&mut interval, index).await {
+        // Sequence numbers wrap around in continuous runs
+        let failure = match probe.probe(index as u16).await {
+            Ok(response) => {
                 results.add_received(response);
+                None
             }
             Err(e) => {
-                println!("{} ping error: {}", pinger.host, e);
                 results.add_loss();
+                Some(ProbeFailure::from(&e))
             }
         };
+        let sample = results.last_sample().expect("just recorded");
+        schedule.emit(&results, vec![sample], vec![failure]);
+        index += 1;
     }
     results
 }
@@ -121,7 +546,7 @@ pub async fn ping(client: Client, target: PingTarget, count: u16, delay: Duratio
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::net::Ipv4Addr;
 
     fn make_target() -> PingTarget {
         PingTarget::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)))
@@ -143,9 +568,7 @@ mod tests {
     #[test]
     fn add_received_updates_recv_count() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
         assert_eq!(results.num_recv, 1);
         assert_eq!(results.num_loss, 0);
         assert_eq!(results.total_count(), 1);
@@ -163,13 +586,9 @@ mod tests {
     #[test]
     fn rates_are_correct_for_mixed_results() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
         results.add_loss();
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(20)));
 
         assert_eq!(results.num_recv, 2);
         assert_eq!(results.num_loss, 1);
@@ -181,9 +600,7 @@ mod tests {
     #[test]
     fn time_stats_single_response() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(15),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(15)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(15)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(15)));
@@ -193,15 +610,9 @@ mod tests {
     #[test]
     fn time_stats_multiple_responses() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(30),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+        results.add_received(PingResponse::new(Duration::from_millis(20)));
+        results.add_received(PingResponse::new(Duration::from_millis(30)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(10)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(30)));
@@ -211,15 +622,9 @@ mod tests {
     #[test]
     fn time_stats_updates_min_and_max() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(50),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(100),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(50)));
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+        results.add_received(PingResponse::new(Duration::from_millis(100)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(10)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(100)));
@@ -247,12 +652,8 @@ mod tests {
     #[test]
     fn all_received_results_in_zero_percent_loss() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+        results.add_received(PingResponse::new(Duration::from_millis(20)));
 
         assert_eq!(results.loss_rate(), 0.0);
         assert_eq!(results.recv_rate(), 1.0);
@@ -261,9 +662,7 @@ mod tests {
     #[test]
     fn time_stats_with_zero_duration() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::ZERO,
-        });
+        results.add_received(PingResponse::new(Duration::ZERO));
 
         assert_eq!(results.min_duration, Some(Duration::ZERO));
         assert_eq!(results.max_duration, Some(Duration::ZERO));
@@ -273,12 +672,8 @@ mod tests {
     #[test]
     fn time_stats_with_sub_millisecond_durations() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_micros(100),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_micros(200),
-        });
+        results.add_received(PingResponse::new(Duration::from_micros(100)));
+        results.add_received(PingResponse::new(Duration::from_micros(200)));
 
         assert_eq!(results.min_duration, Some(Duration::from_micros(100)));
         assert_eq!(results.max_duration, Some(Duration::from_micros(200)));
@@ -288,12 +683,8 @@ mod tests {
     #[test]
     fn time_stats_with_large_durations() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_secs(5),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_secs(10),
-        });
+        results.add_received(PingResponse::new(Duration::from_secs(5)));
+        results.add_received(PingResponse::new(Duration::from_secs(10)));
 
         assert_eq!(results.min_duration, Some(Duration::from_secs(5)));
         assert_eq!(results.max_duration, Some(Duration::from_secs(10)));
@@ -305,15 +696,9 @@ mod tests {
     #[test]
     fn time_stats_with_identical_durations() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(42),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(42),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(42),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(42)));
+        results.add_received(PingResponse::new(Duration::from_millis(42)));
+        results.add_received(PingResponse::new(Duration::from_millis(42)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(42)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(42)));
@@ -323,15 +708,9 @@ mod tests {
     #[test]
     fn time_stats_with_decreasing_durations() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(100),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(50),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(100)));
+        results.add_received(PingResponse::new(Duration::from_millis(50)));
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(10)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(100)));
@@ -342,14 +721,10 @@ mod tests {
     #[test]
     fn time_stats_interleaved_loss_does_not_affect_durations() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
         results.add_loss();
         results.add_loss();
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(20)));
 
         assert_eq!(results.min_duration, Some(Duration::from_millis(10)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(20)));
@@ -361,12 +736,8 @@ mod tests {
     #[test]
     fn responses_vector_tracks_all_received() {
         let mut results = PingResults::new(make_target());
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+        results.add_received(PingResponse::new(Duration::from_millis(20)));
 
         assert_eq!(results.responses.len(), 2);
         assert_eq!(results.responses[0].duration, Duration::from_millis(10));
@@ -382,13 +753,34 @@ mod tests {
         assert!(results.responses.is_empty());
     }
 
+    #[test]
+    fn offset_stats_absent_without_offsets() {
+        let mut results = PingResults::new(make_target());
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+
+        assert!(results.min_offset.is_none());
+        assert!(results.max_offset.is_none());
+        assert!(results.avg_offset().is_none());
+    }
+
+    #[test]
+    fn offset_stats_track_signed_offsets() {
+        let mut results = PingResults::new(make_target());
+        results.add_received(PingResponse::new(Duration::from_millis(10)).with_offset(-300));
+        results.add_loss();
+        results.add_received(PingResponse::new(Duration::from_millis(12)).with_offset(100));
+        results.add_received(PingResponse::new(Duration::from_millis(11)).with_offset(500));
+
+        assert_eq!(results.min_offset, Some(-300));
+        assert_eq!(results.max_offset, Some(500));
+        assert_eq!(results.avg_offset(), Some(100));
+    }
+
     #[test]
     fn avg_calculation_does_not_overflow_with_many_packets() {
         let mut results = PingResults::new(make_target());
         for _ in 0..1000 {
-            results.add_received(PingResponse {
-                duration: Duration::from_millis(1),
-            });
+            results.add_received(PingResponse::new(Duration::from_millis(1)));
         }
 
         assert_eq!(results.num_recv, 1000);
@@ -396,4 +788,259 @@ mod tests {
         assert_eq!(results.min_duration, Some(Duration::from_millis(1)));
         assert_eq!(results.max_duration, Some(Duration::from_millis(1)));
     }
+
+    #[test]
+    fn samples_record_every_outcome_in_order() {
+        let mut results = PingResults::new(make_target());
+        results.add_received(PingResponse::new(Duration::from_millis(10)));
+        results.add_loss();
+
+        let rtts = results.samples.iter().map(|s| s.rtt).collect::<Vec<_>>();
+        assert_eq!(rtts, vec![Some(Duration::from_millis(10)), None]);
+        assert!(results.samples[0].at <= results.samples[1].at);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_samples_and_running_statistics() {
+        let mut results = PingResults::new(make_target())
+            .with_history(3)
+            .with_thresholds(StateThresholds {
+                degraded_after: 1,
+                down_after: 2,
+            });
+        results.add_loss();
+        results.add_loss();
+        for ms in 1..=4 {
+            results.add_received(PingResponse::new(Duration::from_millis(ms)));
+        }
+
+        let rtts = results.samples.iter().map(|s| s.rtt).collect::<Vec<_>>();
+        assert_eq!(
+            rtts,
+            vec![
+                Some(Duration::from_millis(2)),
+                Some(Duration::from_millis(3)),
+                Some(Duration::from_millis(4))
+            ]
+        );
+        assert_eq!(results.responses.len(), 3);
+        assert_eq!(results.first_seq(), 3);
+        assert_eq!(results.total_count(), 6);
+        assert_eq!(results.min_duration, Some(Duration::from_millis(1)));
+        // The outage of the dropped samples is still known
+        assert_eq!(results.tracker().outages().len(), 1);
+
+        let mut results = PingResults::new(make_target()).with_history(0);
+        results.add_burst(vec![
+            None,
+            Some(PingResponse::new(Duration::from_millis(1))),
+        ]);
+        assert!(results.samples.is_empty() && results.responses.is_empty());
+        assert!(results.bursts.is_empty());
+        assert_eq!(results.worst_burst().unwrap().lost, 1);
+        assert_eq!(
+            results.last_sample().unwrap().rtt,
+            Some(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    fn add_burst_records_loss_and_spread() {
+        let mut results = PingResults::new(make_target());
+        results.add_burst(vec![
+            Some(PingResponse::new(Duration::from_millis(10))),
+            None,
+            Some(PingResponse::new(Duration::from_millis(16))),
+        ]);
+
+        assert_eq!(results.num_recv, 2);
+        assert_eq!(results.num_loss, 1);
+        assert_eq!(
+            results.bursts,
+            vec![BurstStats {
+                sent: 3,
+                lost: 1,
+                spread: Some(Duration::from_millis(6)),
+            }]
+        );
+    }
+
+    #[test]
+    fn fully_lost_burst_has_no_spread() {
+        let mut results = PingResults::new(make_target());
+        results.add_burst(vec![None, None]);
+        assert_eq!(results.bursts[0].spread, None);
+        assert_eq!(results.avg_burst_spread(), None);
+    }
+
+    #[test]
+    fn worst_burst_and_average_spread() {
+        let mut results = PingResults::new(make_target());
+        let reply = |ms| Some(PingResponse::new(Duration::from_millis(ms)));
+        results.add_burst(vec![reply(10), reply(12)]);
+        results.add_burst(vec![reply(10), None]);
+        results.add_burst(vec![reply(10), reply(16)]);
+
+        assert_eq!(results.worst_burst().unwrap().lost, 1);
+        // A burst with a single reply has no spread
+        assert_eq!(
+            results.avg_burst_spread(),
+            Some(Duration::from_millis(8) / 3)
+        );
+    }
+
+    /// Answers every probe after `seq` milliseconds, except those listed in `lost`.
+    struct FakeProbe {
+        lost: Vec<u16>,
+    }
+
+    impl Probe for FakeProbe {
+        async fn probe(&mut self, seq: u16) -> Result<PingResponse, ProbeError> {
+            if self.lost.contains(&seq) {
+                return Err(ProbeError::Timeout { seq });
+            }
+            Ok(PingResponse::new(Duration::from_millis(seq as u64)))
+        }
+    }
+
+    #[tokio::test]
+    async fn run_bursts_numbers_probes_consecutively() {
+        let mut probes = vec![
+            FakeProbe { lost: vec![] },
+            FakeProbe { lost: vec![4] },
+            FakeProbe { lost: vec![] },
+        ];
+
+        let schedule = Schedule::new(2, Duration::from_millis(1));
+        let results = run_bursts(make_target(), schedule, &mut probes).await;
+
+        assert_eq!(results.total_count(), 6);
+        assert_eq!(results.num_loss, 1);
+        assert_eq!(results.bursts.len(), 2);
+        assert_eq!(results.bursts[0].spread, Some(Duration::from_millis(2)));
+        assert_eq!(results.bursts[1].lost, 1);
+        assert_eq!(results.max_duration, Some(Duration::from_millis(5)));
+    }
+
+    #[tokio::test]
+    async fn run_bursts_reports_failures_with_position() {
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let schedule = Schedule::new(2, Duration::from_millis(1)).with_events(events);
+        let mut probes = vec![FakeProbe { lost: vec![] }, FakeProbe { lost: vec![3] }];
+
+        run_bursts(make_target(), schedule, &mut probes).await;
+
+        let mut failures = Vec::new();
+        while let Ok(PingEvent::Sample { seq, failure, .. }) = received.try_recv() {
+            failures.push((seq, failure.map(|f| f.kind)));
+        }
+        assert_eq!(
+            failures,
+            vec![(0, None), (1, None), (2, None), (3, Some("timeout"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_probes_reports_every_sample() {
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let schedule = Schedule::new(3, Duration::from_millis(1)).with_events(events);
+        let mut probe = FakeProbe { lost: vec![1] };
+
+        let results = run_probes(make_target(), schedule, &mut probe).await;
+
+        let mut rtts = Vec::new();
+        while let Ok(PingEvent::Sample { sample, .. }) = received.try_recv() {
+            rtts.push(sample.rtt);
+        }
+        assert_eq!(
+            rtts,
+            vec![Some(Duration::ZERO), None, Some(Duration::from_millis(2))]
+        );
+        assert_eq!(results.total_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn probes_without_history_still_report_every_sample() {
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let schedule = Schedule::new(3, Duration::from_millis(1))
+            .with_events(events)
+            .with_history(0);
+        let mut probes = vec![FakeProbe { lost: vec![] }, FakeProbe { lost: vec![3] }];
+
+        let results = run_bursts(make_target(), schedule, &mut probes).await;
+
+        let mut seqs = Vec::new();
+        while let Ok(PingEvent::Sample { seq, sample, .. }) = received.try_recv() {
+            seqs.push((seq, sample.rtt.is_some()));
+        }
+        assert_eq!(
+            seqs,
+            vec![
+                (0, true),
+                (1, true),
+                (2, true),
+                (3, false),
+                (4, true),
+                (5, true)
+            ]
+        );
+        assert!(results.samples.is_empty() && results.responses.is_empty());
+        assert_eq!(results.total_count(), 6);
+    }
+
+    #[tokio::test]
+    async fn continuous_schedule_runs_until_stopped() {
+        let (stop, stopped) = watch::channel(false);
+        let schedule = Schedule::continuous(Duration::from_millis(10)).with_stop(stopped);
+        let mut probe = FakeProbe { lost: vec![] };
+
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(55)).await;
+            stop.send(true).unwrap();
+        });
+        let results = run_probes(make_target(), schedule, &mut probe).await;
+
+        assert!(results.total_count() >= 3, "sent {}", results.total_count());
+    }
+
+    #[test]
+    fn long_continuous_runs_keep_memory_flat() {
+        fn feed(results: &mut PingResults, probes: u32) {
+            for i in 0..probes {
+                if i % 7 == 0 {
+                    results.add_loss();
+                } else {
+                    results
+                        .add_received(PingResponse::new(Duration::from_millis(u64::from(i % 5))));
+                }
+                if i % 50 == 0 {
+                    results.add_burst(vec![
+                        None,
+                        Some(PingResponse::new(Duration::from_millis(1))),
+                    ]);
+                }
+            }
+        }
+
+        let mut results = PingResults::new(make_target()).with_history(16);
+        feed(&mut results, 1_000);
+        let capacities = |r: &PingResults| {
+            (
+                r.samples.capacity(),
+                r.responses.capacity(),
+                r.bursts.capacity(),
+            )
+        };
+        let warmed_up = capacities(&results);
+        feed(&mut results, 100_000);
+
+        assert_eq!(capacities(&results), warmed_up);
+        assert_eq!(results.samples.len(), 16);
+        assert_eq!(results.responses.len(), 16);
+        assert_eq!(results.bursts.len(), 16);
+        assert_eq!(results.num_bursts(), 2_020);
+        assert_eq!(results.first_seq(), u64::from(results.total_count()) - 16);
+        // 1 in 7 single probes and one probe of every burst were lost
+        assert_eq!(results.num_loss, 143 + 14_286 + 2_020);
+    }
 }
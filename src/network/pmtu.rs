@@ -0,0 +1,266 @@
+//! Path MTU discovery with don't-fragment echo requests of varying size.
+
+use crate::core::constants::{LOSS_TIMEOUT, PMTU_CEILING};
+use crate::network::client::PingTarget;
+use crate::network::icmp::{
+    IcmpSocket, ReplyKind, echo_request, header_overhead, is_message_too_long,
+};
+use crate::network::ping::{PingResponse, PingResults};
+use rand::random;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Result of path MTU discovery for one target.
+#[derive(Debug, Clone, Default)]
+pub struct PathMtu {
+    /// Largest packet, including IP and ICMP headers, that was answered by the target.
+    pub largest: Option<usize>,
+    /// Distinct sources of "fragmentation needed" errors seen during the search.
+    pub too_big: Vec<TooBig>,
+}
+
+/// A "fragmentation needed" / "packet too big" error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooBig {
+    /// Router that sent the error, or `None` if the packet exceeded the local interface MTU.
+    pub from: Option<IpAddr>,
+    /// Next-hop MTU reported with the error, if any.
+    pub mtu: Option<u32>,
+}
+
+impl PathMtu {
+    fn record_too_big(&mut self, too_big: TooBig) {
+        if !self.too_big.iter().any(|seen| seen.from == too_big.from) {
+            self.too_big.push(too_big);
+        }
+    }
+}
+
+/// Binary search for the largest payload size that gets through.  The first size tried is the
+/// largest, so the common case of an unrestricted path takes a single step.
+#[derive(Debug)]
+struct MtuSearch {
+    largest_ok: Option<usize>,
+    smallest_bad: usize,
+    hint: Option<usize>,
+}
+
+impl MtuSearch {
+    fn new(max_payload: usize) -> Self {
+        Self {
+            largest_ok: None,
+            smallest_bad: max_payload + 1,
+            hint: Some(max_payload),
+        }
+    }
+
+    /// Next payload size to try, or `None` once the search has converged.
+    fn next(&mut self) -> Option<usize> {
+        let low = self.largest_ok.map_or(0, |ok| ok + 1);
+        if low >= self.smallest_bad {
+            return None;
+        }
+        let hint = self
+            .hint
+            .take()
+            .filter(|h| (low..self.smallest_bad).contains(h));
+        Some(hint.unwrap_or((low + self.smallest_bad) / 2))
+    }
+
+    fn passed(&mut self, size: usize) {
+        self.largest_ok = self.largest_ok.max(Some(size));
+    }
+
+    /// Records a size that did not get through.  `hint` is a payload size derived from the MTU a
+    /// router reported, which is tried next if it is still in range.
+    fn failed(&mut self, size: usize, hint: Option<usize>) {
+        self.smallest_bad = self.smallest_bad.min(size);
+        self.hint = hint;
+    }
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Reply(Duration),
+    TooBig(TooBig),
+    Lost,
+}
+
+struct PmtuProbe {
+    socket: IcmpSocket,
+    destination: IpAddr,
+    ident: u16,
+    seq: u16,
+}
+
+impl PmtuProbe {
+    async fn send(&mut self, payload_size: usize) -> io::Result<Outcome> {
+        self.seq = self.seq.wrapping_add(1);
+        let packet = echo_request(self.destination, self.ident, self.seq, payload_size);
+        let start = Instant::now();
+        match self.socket.send(&packet).await {
+            Err(e) if is_message_too_long(&e) => {
+                return Ok(Outcome::TooBig(TooBig {
+                    from: None,
+                    mtu: None,
+                }));
+            }
+            result => result?,
+        }
+
+        let mut buf = [0; 2048];
+        let deadline = start + Duration::from_secs(LOSS_TIMEOUT as u64);
+        loop {
+            let Ok(received) = time::timeout_at(deadline, self.socket.recv(&mut buf)).await else {
+                return Ok(Outcome::Lost);
+            };
+            let (Some(reply), from) = received? else {
+                continue;
+            };
+            if reply.ident != self.ident || reply.seq != self.seq {
+                continue;
+            }
+            return Ok(match reply.kind {
+                ReplyKind::EchoReply => Outcome::Reply(start.elapsed()),
+                ReplyKind::TooBig { mtu } => Outcome::TooBig(TooBig {
+                    from: Some(from),
+                    mtu: (mtu > 0).then_some(mtu),
+                }),
+                ReplyKind::TimeExceeded | ReplyKind::Unreachable => Outcome::Lost,
+            });
+        }
+    }
+}
+
+/// Searches for the largest don't-fragment echo request that `target` answers, up to
+/// [`PMTU_CEILING`] bytes.  Each size is tried up to `count` times, `delay` apart, unless a
+/// router rejects it first.  Every probe is recorded in the returned results.
+pub async fn discover(target: PingTarget, count: u16, delay: Duration) -> io::Result<PingResults> {
+    let destination = target.addr;
+    let overhead = header_overhead(destination);
+    let socket = IcmpSocket::new(destination)?;
+    socket.set_dont_fragment()?;
+    let mut probe = PmtuProbe {
+        socket,
+        destination,
+        ident: random(),
+        seq: 0,
+    };
+
+    let mut results = PingResults::new(target);
+    let mut path_mtu = PathMtu::default();
+    let mut search = MtuSearch::new(PMTU_CEILING - overhead);
+    let mut interval = time::interval(delay);
+
+    while let Some(size) = search.next() {
+        let mut passed = false;
+        let mut hint = None;
+        for _ in 0..count {
+            interval.tick().await;
+            match probe.send(size).await? {
+                Outcome::Reply(rtt) => {
+                    results.add_received(PingResponse::new(rtt));
+                    passed = true;
+                    break;
+                }
+                Outcome::TooBig(too_big) => {
+                    results.add_loss();
+                    hint = too_big
+                        .mtu
+                        .and_then(|mtu| (mtu as usize).checked_sub(overhead));
+                    path_mtu.record_too_big(too_big);
+                    break;
+                }
+                Outcome::Lost => results.add_loss(),
+            }
+        }
+
+        if passed {
+            search.passed(size);
+        } else {
+            search.failed(size, hint);
+        }
+    }
+
+    path_mtu.largest = search.largest_ok.map(|size| size + overhead);
+    results.path_mtu = Some(path_mtu);
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// Runs the search against a path that passes payloads up to `limit`.
+    fn converge(max_payload: usize, limit: Option<usize>) -> (Option<usize>, usize) {
+        let mut search = MtuSearch::new(max_payload);
+        let mut steps = 0;
+        while let Some(size) = search.next() {
+            steps += 1;
+            if limit.is_some_and(|limit| size <= limit) {
+                search.passed(size);
+            } else {
+                search.failed(size, None);
+            }
+        }
+        (search.largest_ok, steps)
+    }
+
+    #[test]
+    fn search_takes_one_step_when_largest_size_passes() {
+        assert_eq!(converge(1472, Some(1472)), (Some(1472), 1));
+    }
+
+    #[test]
+    fn search_finds_limit_by_bisection() {
+        let (largest, steps) = converge(1472, Some(1372));
+        assert_eq!(largest, Some(1372));
+        assert!(steps <= 12, "took {} steps", steps);
+    }
+
+    #[test]
+    fn search_reports_nothing_when_all_sizes_fail() {
+        assert_eq!(converge(1472, None).0, None);
+    }
+
+    #[test]
+    fn search_tries_reported_mtu_next() {
+        let mut search = MtuSearch::new(1472);
+        assert_eq!(search.next(), Some(1472));
+        search.failed(1472, Some(1372));
+        assert_eq!(search.next(), Some(1372));
+        search.passed(1372);
+        // Bisection between the confirmed and the rejected size continues from here
+        assert_eq!(search.next(), Some(1422));
+    }
+
+    #[test]
+    fn search_ignores_hint_outside_range() {
+        let mut search = MtuSearch::new(1472);
+        search.next();
+        search.failed(1472, Some(9000));
+        assert_eq!(search.next(), Some(736));
+    }
+
+    #[test]
+    fn path_mtu_keeps_one_entry_per_source() {
+        let router = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let mut path_mtu = PathMtu::default();
+        path_mtu.record_too_big(TooBig {
+            from: router,
+            mtu: Some(1400),
+        });
+        path_mtu.record_too_big(TooBig {
+            from: router,
+            mtu: Some(1400),
+        });
+        path_mtu.record_too_big(TooBig {
+            from: None,
+            mtu: None,
+        });
+        assert_eq!(path_mtu.too_big.len(), 2);
+    }
+}
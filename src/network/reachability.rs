@@ -0,0 +1,239 @@
+use crate::core::constants::LOSS_TIMEOUT;
+use crate::network::client::PingTarget;
+use futures::future::join_all;
+use rand::random;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use surge_ping::{Client, PingIdentifier, PingSequence};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+
+/// One column of the reachability matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Icmp,
+    Tcp(u16),
+    Udp(u16),
+}
+
+impl Check {
+    /// ICMP followed by the given TCP and UDP ports, in that order.
+    pub fn from_ports(tcp_ports: &[u16], udp_ports: &[u16]) -> Vec<Check> {
+        std::iter::once(Check::Icmp)
+            .chain(tcp_ports.iter().map(|&port| Check::Tcp(port)))
+            .chain(udp_ports.iter().map(|&port| Check::Udp(port)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Icmp => write!(f, "ICMP"),
+            Check::Tcp(port) => write!(f, "tcp/{}", port),
+            Check::Udp(port) => write!(f, "udp/{}", port),
+        }
+    }
+}
+
+/// Outcome of a single check.  A UDP service that silently drops our empty datagram cannot be
+/// told apart from a firewall dropping it, so both are reported as filtered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    Reachable(Duration),
+    Refused(Duration),
+    Filtered,
+}
+
+impl Reachability {
+    fn is_conclusive(&self) -> bool {
+        !matches!(self, Reachability::Filtered)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReachabilityResults {
+    pub target: PingTarget,
+    pub outcomes: Vec<(Check, Reachability)>,
+}
+
+/// Runs every check against `target`.  Each check is attempted up to `count` times, `delay`
+/// apart, and stops at the first reply or refusal.
+pub async fn check(
+    client: Client,
+    target: PingTarget,
+    checks: Vec<Check>,
+    count: u16,
+    delay: Duration,
+) -> ReachabilityResults {
+    let client = &client;
+    let addr = target.addr;
+    let outcomes =
+        join_all(checks.into_iter().map(|check| async move {
+            (check, run_check(client, addr, check, count, delay).await)
+        }))
+        .await;
+
+    ReachabilityResults { target, outcomes }
+}
+
+async fn run_check(
+    client: &Client,
+    addr: IpAddr,
+    check: Check,
+    count: u16,
+    delay: Duration,
+) -> Reachability {
+    let mut interval = time::interval(delay);
+    for seq in 0..count {
+        interval.tick().await;
+        let outcome = match check {
+            Check::Icmp => check_icmp(client, addr, seq).await,
+            Check::Tcp(port) => check_tcp(SocketAddr::new(addr, port)).await,
+            Check::Udp(port) => check_udp(SocketAddr::new(addr, port)).await,
+        };
+        if outcome.is_conclusive() {
+            return outcome;
+        }
+    }
+    Reachability::Filtered
+}
+
+fn timeout() -> Duration {
+    Duration::from_secs(LOSS_TIMEOUT as u64)
+}
+
+async fn check_icmp(client: &Client, addr: IpAddr, seq: u16) -> Reachability {
+    let mut pinger = client.pinger(addr, PingIdentifier(random())).await;
+    pinger.timeout(timeout());
+    match pinger.ping(PingSequence(seq), &[0; 56]).await {
+        Ok((_, duration)) => Reachability::Reachable(duration),
+        Err(_) => Reachability::Filtered,
+    }
+}
+
+async fn check_tcp(addr: SocketAddr) -> Reachability {
+    let start = Instant::now();
+    match time::timeout(timeout(), TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Reachability::Reachable(start.elapsed()),
+        Ok(Err(e)) => classify(&e, start.elapsed()),
+        Err(_) => Reachability::Filtered,
+    }
+}
+
+async fn check_udp(addr: SocketAddr) -> Reachability {
+    let start = Instant::now();
+    let exchange = async {
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        // A connected socket reports ICMP port unreachable as ECONNREFUSED
+        socket.connect(addr).await?;
+        socket.send(&[]).await?;
+        let mut buf = [0; 512];
+        socket.recv(&mut buf).await
+    };
+
+    match time::timeout(timeout(), exchange).await {
+        Ok(Ok(_)) => Reachability::Reachable(start.elapsed()),
+        Ok(Err(e)) => classify(&e, start.elapsed()),
+        Err(_) => Reachability::Filtered,
+    }
+}
+
+fn classify(e: &io::Error, elapsed: Duration) -> Reachability {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => Reachability::Refused(elapsed),
+        _ => Reachability::Filtered,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn localhost(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    async fn unused_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn from_ports_puts_icmp_first() {
+        let checks = Check::from_ports(&[22, 443], &[53]);
+        assert_eq!(
+            checks,
+            vec![Check::Icmp, Check::Tcp(22), Check::Tcp(443), Check::Udp(53)]
+        );
+    }
+
+    #[test]
+    fn check_display_names_protocol_and_port() {
+        assert_eq!(Check::Icmp.to_string(), "ICMP");
+        assert_eq!(Check::Tcp(22).to_string(), "tcp/22");
+        assert_eq!(Check::Udp(53).to_string(), "udp/53");
+    }
+
+    #[test]
+    fn classify_refused_and_other_errors() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let unreachable = io::Error::from(io::ErrorKind::HostUnreachable);
+        let elapsed = Duration::from_millis(1);
+        assert_eq!(classify(&refused, elapsed), Reachability::Refused(elapsed));
+        assert_eq!(classify(&unreachable, elapsed), Reachability::Filtered);
+    }
+
+    #[tokio::test]
+    async fn tcp_listening_port_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(matches!(
+            check_tcp(localhost(port)).await,
+            Reachability::Reachable(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn tcp_closed_port_is_refused() {
+        let port = unused_port().await;
+        assert!(matches!(
+            check_tcp(localhost(port)).await,
+            Reachability::Refused(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn udp_echo_port_is_reachable() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0; 16];
+            while let Ok((len, peer)) = server.recv_from(&mut buf).await {
+                let _ = server.send_to(&buf[..len], peer).await;
+            }
+        });
+        assert!(matches!(
+            check_udp(localhost(port)).await,
+            Reachability::Reachable(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn udp_closed_port_is_refused() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        drop(socket);
+        assert!(matches!(
+            check_udp(localhost(port)).await,
+            Reachability::Refused(_)
+        ));
+    }
+}
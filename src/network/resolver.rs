@@ -1,39 +1,106 @@
-use crate::core::config::PingConfig;
-use crate::network::client::PingTarget;
+use crate::core::constants::{DEFAULT_NTP_PORT, DEFAULT_TLS_PORT};
+use crate::network::client::{PingTarget, ProbeKind};
 use anyhow::anyhow;
 use std::net::IpAddr;
 use tokio::net::lookup_host;
 
-pub async fn resolve_targets(ping_config: &PingConfig) -> Vec<PingTarget> {
+/// Resolves target specs as given on the command line.  Specs that cannot be parsed or resolved
+/// are reported and skipped.
+pub async fn resolve_targets(specs: &[String]) -> Vec<PingTarget> {
     let mut targets = Vec::new();
 
-    for host in ping_config.hosts.iter() {
-        let target: PingTarget;
-        if let Some(ping_target) = try_parse_ip_target(host) {
-            target = reverse_resolve_ip(ping_target.addr).await.unwrap();
-        } else {
-            target = match resolve_hostname(host).await {
-                Ok(target) => target,
-                Err(e) => {
-                    eprintln!("{} resolve error: {}", host, e);
-                    // Skip this host
-                    continue;
-                }
-            };
-        }
-        targets.push(target);
+    for spec in specs {
+        let (kind, host) = match parse_target_spec(spec) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("{} parse error: {}", spec, e);
+                continue;
+            }
+        };
+        let target = match resolve_target(host).await {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("{} resolve error: {}", host, e);
+                // Skip this host
+                continue;
+            }
+        };
+        targets.push(target.with_kind(kind));
     }
     targets
 }
 
+/// Resolves a bare host name or IP address.  IP addresses are reverse resolved where possible.
+pub async fn resolve_target(host: &str) -> anyhow::Result<PingTarget> {
+    if let Some(ping_target) = try_parse_ip_target(host) {
+        return Ok(reverse_resolve_ip(ping_target.addr)
+            .await
+            .unwrap_or(ping_target));
+    }
+    resolve_hostname(host).await
+}
+
+/// Splits a target spec such as `tls://example.com:443` into its probe kind and bare host.
+/// Specs without a scheme are ICMP targets.
+pub fn parse_target_spec(spec: &str) -> anyhow::Result<(ProbeKind, &str)> {
+    let Some((scheme, rest)) = spec.split_once("://") else {
+        return Ok((ProbeKind::Icmp, spec));
+    };
+
+    match scheme {
+        "tls" => {
+            let (host, port) = split_host_port(rest)?;
+            Ok((
+                ProbeKind::Tls {
+                    port: port.unwrap_or(DEFAULT_TLS_PORT),
+                },
+                host,
+            ))
+        }
+        "ntp" => {
+            let (host, port) = split_host_port(rest)?;
+            Ok((
+                ProbeKind::Ntp {
+                    port: port.unwrap_or(DEFAULT_NTP_PORT),
+                },
+                host,
+            ))
+        }
+        _ => Err(anyhow!("unsupported probe type '{}'", scheme)),
+    }
+}
+
+/// Splits `host:port`, `[v6]:port`, `[v6]` or `host` into the host and an optional port.
+fn split_host_port(s: &str) -> anyhow::Result<(&str, Option<u16>)> {
+    if let Some(rest) = s.strip_prefix('[') {
+        let (host, tail) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow!("missing ']' in '{}'", s))?;
+        return match tail.strip_prefix(':') {
+            Some(port) => Ok((host, Some(parse_port(port)?))),
+            None if tail.is_empty() => Ok((host, None)),
+            None => Err(anyhow!("unexpected '{}' after address", tail)),
+        };
+    }
+
+    match s.split_once(':') {
+        // More than one colon without brackets is a bare IPv6 address
+        Some((_, port)) if port.contains(':') => Ok((s, None)),
+        Some((host, port)) => Ok((host, Some(parse_port(port)?))),
+        None => Ok((s, None)),
+    }
+}
+
+fn parse_port(port: &str) -> anyhow::Result<u16> {
+    port.parse::<u16>()
+        .map_err(|_| anyhow!("invalid port '{}'", port))
+}
+
 /// Returns `Some(PingTarget)` if `host` is an IP address, `None` otherwise.
 fn try_parse_ip_target(host: &str) -> Option<PingTarget> {
     // If this is an IP address, we can skip the DNS lookup
     if let Ok(ip) = host.parse::<IpAddr>() {
-        return Some(PingTarget {
-            host: None,
-            addr: ip,
-        });
+        return Some(PingTarget::new(ip));
     }
     None
 }
@@ -48,25 +115,26 @@ pub async fn resolve_hostname(hostname: &str) -> anyhow::Result<PingTarget> {
     //  we might want to prefer IPv6 over IPv4 per default.
     addresses
         .next()
-        .map(|addr| PingTarget {
-            host: Some(hostname.to_string()),
-            addr: addr.ip(),
-        })
+        .map(|addr| PingTarget::with_host(hostname.to_string(), addr.ip()))
         .ok_or_else(|| anyhow!("{}: no address found", hostname))
 }
 
 pub async fn reverse_resolve_ip(addr: IpAddr) -> anyhow::Result<PingTarget> {
-    let ip_addr = addr.to_string();
-    // FIXME: lookup_host does only IP -> hostname, not the other way around.
-    let mut host_names = lookup_host(format!("{}:53", ip_addr)).await?;
+    lookup_name(addr)
+        .await
+        .map(|hostname| PingTarget::with_host(hostname, addr))
+        .ok_or_else(|| anyhow!("{}: no hostname found", addr))
+}
 
-    host_names
-        .next()
-        .map(|hostname| PingTarget {
-            host: Some(hostname.to_string()),
-            addr: addr,
-        })
-        .ok_or_else(|| anyhow!("{}: no hostname found", ip_addr))
+/// Reverse DNS lookup of `addr`.  Returns `None` if there is no name other than the address itself.
+pub async fn lookup_name(addr: IpAddr) -> Option<String> {
+    let hostname = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&addr))
+        .await
+        .ok()?
+        .ok()?;
+
+    // getnameinfo falls back to the numeric address when there is no PTR record
+    (hostname != addr.to_string()).then_some(hostname)
 }
 
 #[cfg(test)]
@@ -103,4 +171,49 @@ mod tests {
         let result = try_parse_ip_target("not-an-ip");
         assert!(result.is_none());
     }
+
+    #[test]
+    fn parse_spec_without_scheme_is_icmp() {
+        let (kind, host) = parse_target_spec("example.com").unwrap();
+        assert_eq!(kind, ProbeKind::Icmp);
+        assert_eq!(host, "example.com");
+    }
+
+    #[test]
+    fn parse_tls_spec_with_port() {
+        let (kind, host) = parse_target_spec("tls://example.com:8443").unwrap();
+        assert_eq!(kind, ProbeKind::Tls { port: 8443 });
+        assert_eq!(host, "example.com");
+    }
+
+    #[test]
+    fn parse_tls_spec_defaults_to_443() {
+        let (kind, host) = parse_target_spec("tls://example.com").unwrap();
+        assert_eq!(kind, ProbeKind::Tls { port: 443 });
+        assert_eq!(host, "example.com");
+    }
+
+    #[test]
+    fn parse_tls_spec_with_bracketed_ipv6() {
+        let (kind, host) = parse_target_spec("tls://[::1]:8443").unwrap();
+        assert_eq!(kind, ProbeKind::Tls { port: 8443 });
+        assert_eq!(host, "::1");
+    }
+
+    #[test]
+    fn parse_ntp_spec_defaults_to_123() {
+        let (kind, host) = parse_target_spec("ntp://pool.ntp.org").unwrap();
+        assert_eq!(kind, ProbeKind::Ntp { port: 123 });
+        assert_eq!(host, "pool.ntp.org");
+    }
+
+    #[test]
+    fn parse_tls_spec_invalid_port_returns_error() {
+        assert!(parse_target_spec("tls://example.com:https").is_err());
+    }
+
+    #[test]
+    fn parse_unknown_scheme_returns_error() {
+        assert!(parse_target_spec("gopher://example.com").is_err());
+    }
 }
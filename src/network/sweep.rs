@@ -0,0 +1,152 @@
+//! Payload size sweeps for estimating serialization delay and bottleneck bandwidth.
+
+use crate::network::client::PingTarget;
+use crate::network::ping::{IcmpProbe, PingResults, Probe};
+use std::time::Duration;
+use surge_ping::Client;
+use tokio::time;
+
+/// Per payload size statistics of a sweep against one target.
+#[derive(Debug)]
+pub struct SweepResults {
+    pub target: PingTarget,
+    /// Payload size in bytes and the statistics of the probes that carried it, smallest first.
+    pub buckets: Vec<(usize, PingResults)>,
+}
+
+/// How much the round-trip time grows with every additional payload byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerializationEstimate {
+    pub secs_per_byte: f64,
+}
+
+impl SerializationEstimate {
+    /// Bandwidth of the bottleneck link in bits per second.  Echo request and reply both carry
+    /// the payload, so every byte is serialized twice per round trip.
+    pub fn bits_per_second(&self) -> f64 {
+        2.0 * 8.0 / self.secs_per_byte
+    }
+}
+
+impl SweepResults {
+    /// Least squares fit of the minimum RTT per bucket against payload size.  The minimum is
+    /// used because it is the sample least affected by queueing.  Returns `None` with fewer than
+    /// two answered sizes or if RTT does not grow with size.
+    pub fn estimate(&self) -> Option<SerializationEstimate> {
+        let points = self
+            .buckets
+            .iter()
+            .filter_map(|(size, results)| {
+                results
+                    .min_duration
+                    .map(|min| (*size as f64, min.as_secs_f64()))
+            })
+            .collect::<Vec<_>>();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let covariance = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>();
+        let variance = points
+            .iter()
+            .map(|(x, _)| (x - mean_x).powi(2))
+            .sum::<f64>();
+        if variance == 0.0 {
+            return None;
+        }
+
+        let secs_per_byte = covariance / variance;
+        (secs_per_byte > 0.0).then_some(SerializationEstimate { secs_per_byte })
+    }
+}
+
+/// Sends `count` echo requests to `target`, cycling the payload through `sizes`.
+pub async fn sweep(
+    client: Client,
+    target: PingTarget,
+    sizes: Vec<usize>,
+    count: u16,
+    delay: Duration,
+) -> SweepResults {
+    let mut probe = IcmpProbe::new(&client, target.addr, 0).await;
+    let mut buckets = sizes
+        .into_iter()
+        .map(|size| (size, PingResults::new(target.clone())))
+        .collect::<Vec<_>>();
+    let num_buckets = buckets.len();
+    let mut interval = time::interval(delay);
+
+    if num_buckets > 0 {
+        for seq in 0..count {
+            interval.tick().await;
+            let (size, results) = &mut buckets[seq as usize % num_buckets];
+            probe.set_payload_size(*size);
+            // Failed probes count as lost, like in the other probe loops
+            match probe.probe(seq).await {
+                Ok(response) => results.add_received(response),
+                Err(_) => results.add_loss(),
+            }
+        }
+    }
+
+    SweepResults { target, buckets }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::ping::PingResponse;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn sweep_results(points: &[(usize, Option<u64>)]) -> SweepResults {
+        let target = PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let buckets = points
+            .iter()
+            .map(|&(size, rtt_micros)| {
+                let mut results = PingResults::new(target.clone());
+                match rtt_micros {
+                    Some(micros) => {
+                        results.add_received(PingResponse::new(Duration::from_micros(micros)))
+                    }
+                    None => results.add_loss(),
+                }
+                (size, results)
+            })
+            .collect();
+        SweepResults { target, buckets }
+    }
+
+    #[test]
+    fn estimate_recovers_linear_slope() {
+        // 1 μs per byte plus a 1 ms base delay
+        let results = sweep_results(&[(100, Some(1_100)), (600, Some(1_600)), (1100, Some(2_100))]);
+        let estimate = results.estimate().unwrap();
+        assert!((estimate.secs_per_byte - 1e-6).abs() < 1e-9);
+        // 16 bits per μs of slope is 16 Mbit/s
+        assert!((estimate.bits_per_second() - 16e6).abs() < 1e3);
+    }
+
+    #[test]
+    fn estimate_skips_unanswered_sizes() {
+        let results = sweep_results(&[(100, Some(1_100)), (600, None), (1100, Some(2_100))]);
+        assert!(results.estimate().is_some());
+    }
+
+    #[test]
+    fn estimate_needs_two_answered_sizes() {
+        let results = sweep_results(&[(100, Some(1_100)), (600, None)]);
+        assert!(results.estimate().is_none());
+    }
+
+    #[test]
+    fn estimate_rejects_non_increasing_rtt() {
+        let results = sweep_results(&[(100, Some(2_000)), (1100, Some(1_000))]);
+        assert!(results.estimate().is_none());
+    }
+}
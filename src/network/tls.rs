@@ -0,0 +1,262 @@
+use crate::core::constants::{DEFAULT_TLS_PORT, LOSS_TIMEOUT, SECONDS_IN_DAY};
+use crate::core::error::ProbeError;
+use crate::network::client::PingTarget;
+use crate::network::ping::{PingResponse, PingResults, Probe, Schedule, run_probes};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::TlsConnector;
+
+/// Details of the leaf certificate presented by a TLS target.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub not_after: SystemTime,
+}
+
+impl CertificateInfo {
+    /// Whole days left until the certificate expires, negative once it has expired.
+    pub fn days_to_expiry(&self) -> i64 {
+        days_between(SystemTime::now(), self.not_after)
+    }
+}
+
+fn days_between(from: SystemTime, to: SystemTime) -> i64 {
+    let seconds = match to.duration_since(from) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    seconds.div_euclid(SECONDS_IN_DAY)
+}
+
+struct TlsProbe {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+    addr: SocketAddr,
+    certificate: Option<CertificateInfo>,
+}
+
+impl Probe for TlsProbe {
+    async fn probe(&mut self, seq: u16) -> Result<PingResponse, ProbeError> {
+        let handshake = self.handshake();
+        let (duration, certificate) =
+            time::timeout(Duration::from_secs(LOSS_TIMEOUT as u64), handshake)
+                .await
+                .map_err(|_| ProbeError::Timeout { seq })??;
+        if certificate.is_some() {
+            self.certificate = certificate;
+        }
+        Ok(PingResponse::new(duration))
+    }
+}
+
+impl TlsProbe {
+    async fn handshake(&self) -> Result<(Duration, Option<CertificateInfo>), ProbeError> {
+        let start = Instant::now();
+        let stream = TcpStream::connect(self.addr).await?;
+        let tls = self
+            .connector
+            .connect(self.server_name.clone(), stream)
+            .await
+            .map_err(|e| ProbeError::Tls(e.to_string()))?;
+        let duration = start.elapsed();
+
+        let certificate = tls
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|der| parse_certificate(der));
+
+        Ok((duration, certificate))
+    }
+}
+
+/// Times TCP connect plus TLS handshake once per scheduled slot.  The certificate of the last
+/// successful handshake is kept in the results.
+pub async fn probe(target: PingTarget, schedule: Schedule) -> PingResults {
+    let port = target.kind.port().unwrap_or(DEFAULT_TLS_PORT);
+    let mut probe = TlsProbe {
+        connector: connector(),
+        server_name: server_name(&target),
+        addr: SocketAddr::new(target.addr, port),
+        certificate: None,
+    };
+
+    let mut results = run_probes(target, schedule, &mut probe).await;
+    results.certificate = probe.certificate;
+    results
+}
+
+/// SNI name for the handshake; falls back to the IP address if the host is not a valid DNS name.
+fn server_name(target: &PingTarget) -> ServerName<'static> {
+    target
+        .host
+        .as_deref()
+        .and_then(|host| ServerName::try_from(host.to_string()).ok())
+        .unwrap_or_else(|| ServerName::IpAddress(target.addr.into()))
+}
+
+fn parse_certificate(der: &CertificateDer<'_>) -> Option<CertificateInfo> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der.as_ref()).ok()?;
+    let not_after = u64::try_from(cert.validity().not_after.timestamp()).ok()?;
+
+    Some(CertificateInfo {
+        subject: cert.subject().to_string(),
+        not_after: UNIX_EPOCH + Duration::from_secs(not_after),
+    })
+}
+
+fn connector() -> TlsConnector {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// We measure handshakes and report on certificates rather than trust them, so expired and
+/// self-signed certificates must not abort the handshake.  Handshake signatures are still checked.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use rustls::ServerConfig;
+    use rustls::pki_types::PrivateKeyDer;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Starts a TLS server with a self-signed certificate for `localhost` and returns its port.
+    async fn spawn_self_signed_server() -> u16 {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(cert.signing_key.serialize_der().into());
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.cert.der().clone()], key)
+                .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = acceptor.accept(stream).await;
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn days_between_counts_whole_days() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let later = now + Duration::from_secs(3 * 86_400 + 100);
+        assert_eq!(days_between(now, later), 3);
+    }
+
+    #[test]
+    fn days_between_is_negative_when_expired() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let earlier = now - Duration::from_secs(3600);
+        assert_eq!(days_between(now, earlier), -1);
+    }
+
+    #[test]
+    fn server_name_falls_back_to_ip() {
+        let target =
+            PingTarget::with_host("127.0.0.1:53".to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(matches!(server_name(&target), ServerName::IpAddress(_)));
+    }
+
+    #[tokio::test]
+    async fn probe_self_signed_server_records_handshakes_and_certificate() {
+        let port = spawn_self_signed_server().await;
+        let target =
+            PingTarget::with_host("localhost".to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .with_kind(ProbeKind::Tls { port });
+
+        let results = probe(target, Schedule::new(2, Duration::from_millis(100))).await;
+
+        assert_eq!(results.num_recv, 2);
+        assert_eq!(results.num_loss, 0);
+        let cert = results.certificate.unwrap();
+        assert!(cert.subject.contains("rcgen"));
+        assert!(cert.days_to_expiry() > 0);
+    }
+
+    #[tokio::test]
+    async fn probe_closed_port_counts_as_loss() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let target =
+            PingTarget::new(IpAddr::V4(Ipv4Addr::LOCALHOST)).with_kind(ProbeKind::Tls { port });
+
+        let results = probe(target, Schedule::new(1, Duration::from_millis(100))).await;
+
+        assert_eq!(results.num_recv, 0);
+        assert_eq!(results.num_loss, 1);
+        assert!(results.certificate.is_none());
+    }
+}
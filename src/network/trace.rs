@@ -0,0 +1,233 @@
+//! Traceroute using ICMP echo requests with increasing TTL.
+//!
+//! "Time exceeded" messages from routers along the path never reach a surge-ping `Pinger`, so
+//! tracing uses the raw socket from [`crate::network::icmp`], which requires root or
+//! `CAP_NET_RAW`.
+
+use crate::core::constants::{DEFAULT_PAYLOAD_SIZE, LOSS_TIMEOUT};
+use crate::network::client::PingTarget;
+use crate::network::icmp::{IcmpSocket, ReplyKind, echo_request};
+use crate::network::ping::{PingResponse, PingResults};
+use rand::random;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Statistics for one TTL.  `results.target` is the router that answered, or the unspecified
+/// address while no reply has been seen.
+#[derive(Debug)]
+pub struct Hop {
+    pub ttl: u8,
+    pub results: PingResults,
+}
+
+impl Hop {
+    fn new(ttl: u8, destination: IpAddr) -> Self {
+        let unspecified = match destination {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        Self {
+            ttl,
+            results: PingResults::new(PingTarget::new(unspecified)),
+        }
+    }
+
+    /// The router that answered for this TTL, if any did.
+    pub fn responder(&self) -> Option<&PingTarget> {
+        let target = &self.results.target;
+        (!target.addr.is_unspecified()).then_some(target)
+    }
+
+    fn record_reply(&mut self, from: IpAddr, rtt: Duration) {
+        if self.results.target.addr.is_unspecified() {
+            self.results.target = PingTarget::new(from);
+        }
+        self.results.add_received(PingResponse::new(rtt));
+    }
+}
+
+/// Probes every hop of the path to one destination, one round at a time.
+pub struct Tracer {
+    socket: IcmpSocket,
+    destination: IpAddr,
+    ident: u16,
+    seq: u16,
+    hops: Vec<Hop>,
+    /// TTL at which the destination answered; no probes are sent beyond it.
+    last_ttl: u8,
+}
+
+impl Tracer {
+    pub fn new(target: &PingTarget, max_hops: u8) -> io::Result<Self> {
+        let destination = target.addr;
+        Ok(Self {
+            socket: IcmpSocket::new(destination)?,
+            destination,
+            ident: random(),
+            seq: 0,
+            hops: (1..=max_hops)
+                .map(|ttl| Hop::new(ttl, destination))
+                .collect(),
+            last_ttl: max_hops,
+        })
+    }
+
+    /// Hops up to and including the destination, or up to the maximum TTL if it never answered.
+    pub fn hops(&self) -> &[Hop] {
+        &self.hops[..self.last_ttl as usize]
+    }
+
+    pub fn hops_mut(&mut self) -> &mut [Hop] {
+        &mut self.hops[..self.last_ttl as usize]
+    }
+
+    pub fn into_hops(mut self) -> Vec<Hop> {
+        self.hops.truncate(self.last_ttl as usize);
+        self.hops
+    }
+
+    /// Sends one probe per TTL and waits up to the loss timeout for the replies.
+    pub async fn round(&mut self) -> io::Result<()> {
+        let mut pending: HashMap<u16, (u8, Instant)> = HashMap::new();
+        for ttl in 1..=self.last_ttl {
+            self.seq = self.seq.wrapping_add(1);
+            let packet = echo_request(self.destination, self.ident, self.seq, DEFAULT_PAYLOAD_SIZE);
+            self.socket.set_ttl(ttl)?;
+            self.socket.send(&packet).await?;
+            pending.insert(self.seq, (ttl, Instant::now()));
+        }
+
+        let mut buf = [0; 2048];
+        let deadline = Instant::now() + Duration::from_secs(LOSS_TIMEOUT as u64);
+        while !pending.is_empty() {
+            let Ok(received) = time::timeout_at(deadline, self.socket.recv(&mut buf)).await else {
+                break;
+            };
+            let (Some(reply), from) = received? else {
+                continue;
+            };
+            if reply.ident != self.ident {
+                continue;
+            }
+            let Some((ttl, sent)) = pending.remove(&reply.seq) else {
+                continue;
+            };
+
+            self.hops[ttl as usize - 1].record_reply(from, sent.elapsed());
+            if from == self.destination && reply.kind != ReplyKind::TimeExceeded {
+                self.last_ttl = self.last_ttl.min(ttl);
+            }
+        }
+
+        for (ttl, _) in pending.into_values() {
+            if ttl <= self.last_ttl {
+                self.hops[ttl as usize - 1].results.add_loss();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Probes every TTL from 1 to `max_hops` once per round for `count` rounds and returns the hops
+/// up to and including the destination.
+pub async fn trace(
+    target: &PingTarget,
+    max_hops: u8,
+    count: u16,
+    delay: Duration,
+) -> io::Result<Vec<Hop>> {
+    let mut tracer = Tracer::new(target, max_hops)?;
+    let mut interval = time::interval(delay);
+
+    for _ in 0..count {
+        interval.tick().await;
+        tracer.round().await?;
+    }
+
+    Ok(tracer.into_hops())
+}
+
+/// Whether the loss seen at `hops[index]` does not carry through to the destination.  Routers
+/// often rate limit the ICMP messages they generate themselves while forwarding traffic just
+/// fine, so such loss is usually not an outage.
+pub fn is_transient_loss(hops: &[Hop], index: usize) -> bool {
+    let (Some(hop), Some(destination)) = (hops.get(index), hops.last()) else {
+        return false;
+    };
+    index + 1 < hops.len()
+        && hop.results.num_loss > 0
+        && destination.results.loss_rate() < hop.results.loss_rate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hop_without_reply_has_no_responder() {
+        let mut hop = Hop::new(3, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        hop.results.add_loss();
+        assert!(hop.responder().is_none());
+        assert_eq!(hop.results.num_loss, 1);
+    }
+
+    #[test]
+    fn hop_records_first_responder() {
+        let router = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let mut hop = Hop::new(1, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        hop.record_reply(router, Duration::from_millis(2));
+        hop.record_reply(
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            Duration::from_millis(4),
+        );
+
+        assert_eq!(hop.responder().unwrap().addr, router);
+        assert_eq!(hop.results.num_recv, 2);
+        assert_eq!(hop.results.avg_duration(), Some(Duration::from_millis(3)));
+    }
+
+    fn hop_with_loss(ttl: u8, received: u32, lost: u32) -> Hop {
+        let mut hop = Hop::new(ttl, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        for _ in 0..received {
+            hop.record_reply(
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, ttl)),
+                Duration::from_millis(1),
+            );
+        }
+        for _ in 0..lost {
+            hop.results.add_loss();
+        }
+        hop
+    }
+
+    #[test]
+    fn loss_not_reaching_destination_is_transient() {
+        let hops = vec![
+            hop_with_loss(1, 10, 0),
+            hop_with_loss(2, 6, 4),
+            hop_with_loss(3, 10, 0),
+        ];
+        assert!(!is_transient_loss(&hops, 0));
+        assert!(is_transient_loss(&hops, 1));
+        assert!(!is_transient_loss(&hops, 2));
+    }
+
+    #[test]
+    fn loss_persisting_to_destination_is_not_transient() {
+        let hops = vec![
+            hop_with_loss(1, 10, 0),
+            hop_with_loss(2, 6, 4),
+            hop_with_loss(3, 6, 4),
+        ];
+        assert!(!is_transient_loss(&hops, 1));
+        assert!(!is_transient_loss(&hops, 2));
+    }
+
+    #[test]
+    fn transient_loss_out_of_range_is_false() {
+        assert!(!is_transient_loss(&[], 0));
+    }
+}
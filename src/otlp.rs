@@ -0,0 +1,448 @@
+//! OpenTelemetry metrics export over OTLP/HTTP with the JSON encoding: per-target RTT
+//! histograms, probe counters and up and availability gauges, pushed to a collector.
+
+use crate::core::constants::{OTLP_EXPORT_INTERVAL, OTLP_TIMEOUT, RTT_BUCKETS};
+use crate::events::{EventReceiver, PingEvent};
+use crate::metrics::{PerTarget, ProbeTally, Tag};
+use crate::network::client::PingTarget;
+use crate::network::http::{HttpUrl, post};
+use crate::state::{HostState, HostTracker, StateThresholds};
+use serde_json::{Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time;
+
+#[derive(Debug)]
+struct HostSeries {
+    target: PingTarget,
+    tally: ProbeTally,
+    tracker: HostTracker,
+}
+
+/// Cumulative metrics of all targets since the exporter was created.
+#[derive(Debug)]
+pub struct OtlpExporter {
+    url: HttpUrl,
+    /// Extra request headers, e.g. for authentication.
+    headers: Vec<(String, String)>,
+    resource: Vec<Tag>,
+    started: SystemTime,
+    hosts: PerTarget<HostSeries>,
+}
+
+impl OtlpExporter {
+    /// Registers every target up front, so its series are exported before the first probe.
+    /// `resource` becomes resource attributes next to `service.name`, `service.version` and
+    /// `host.name`, identifying the probing agent.
+    pub fn new(
+        url: HttpUrl,
+        targets: &[PingTarget],
+        resource: Vec<Tag>,
+        thresholds: StateThresholds,
+    ) -> Self {
+        let mut exporter = Self {
+            url,
+            headers: Vec::new(),
+            resource,
+            started: SystemTime::now(),
+            hosts: PerTarget::default(),
+        };
+        for target in targets {
+            exporter.hosts.insert(
+                target,
+                HostSeries {
+                    target: target.clone(),
+                    tally: ProbeTally::default(),
+                    tracker: HostTracker::new(thresholds),
+                },
+            );
+        }
+        exporter
+    }
+
+    /// Adds headers given as `key=value,key=value`, the format of `OTEL_EXPORTER_OTLP_HEADERS`.
+    /// Malformed entries are skipped.
+    pub fn with_headers(mut self, headers: &str) -> Self {
+        self.headers.extend(headers.split(',').filter_map(|header| {
+            let (key, value) = header.split_once('=')?;
+            let key = key.trim();
+            (!key.is_empty()).then(|| (key.to_string(), value.trim().to_string()))
+        }));
+        self
+    }
+
+    /// Counts one probe outcome.  Events of unregistered targets are ignored.
+    pub fn record(&mut self, event: &PingEvent) {
+        let PingEvent::Sample { target, sample, .. } = event;
+        let Some(host) = self.hosts.get_mut(target) else {
+            return;
+        };
+        host.tally.record(sample.rtt);
+        host.tracker.record(sample.at, sample.rtt.is_some());
+    }
+
+    /// An `ExportMetricsServiceRequest` with the state at `now`.  64 bit integers are strings,
+    /// as the OTLP JSON encoding requires.
+    pub fn request(&self, now: SystemTime) -> Value {
+        let start = nanos(self.started);
+        let now = nanos(now);
+        let point = |host: &HostSeries| {
+            json!({
+                "attributes": target_attributes(&host.target),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+            })
+        };
+        let counter = |value: fn(&HostSeries) -> u64| {
+            json!({
+                "aggregationTemporality": CUMULATIVE,
+                "isMonotonic": true,
+                "dataPoints": self.hosts.iter().map(|host| {
+                    with(point(host), json!({ "asInt": value(host).to_string() }))
+                }).collect::<Vec<_>>(),
+            })
+        };
+
+        let histogram = self
+            .hosts
+            .iter()
+            .map(|host| {
+                let mut fields = json!({
+                    "count": host.tally.received.to_string(),
+                    "sum": host.tally.rtt_sum.as_nanos() as f64 / 1e9,
+                    "bucketCounts": host.tally.buckets.iter().map(u64::to_string).collect::<Vec<_>>(),
+                    "explicitBounds": RTT_BUCKETS,
+                });
+                if let (Some(min), Some(max)) = (host.tally.rtt_min, host.tally.rtt_max) {
+                    fields["min"] = json!(min.as_secs_f64());
+                    fields["max"] = json!(max.as_secs_f64());
+                }
+                with(point(host), fields)
+            })
+            .collect::<Vec<_>>();
+        let up = self
+            .hosts
+            .iter()
+            .map(|host| {
+                let up = host.tracker.state() != HostState::Down;
+                with(point(host), json!({ "asInt": u8::from(up).to_string() }))
+            })
+            .collect::<Vec<_>>();
+        let availability = self
+            .hosts
+            .iter()
+            .filter_map(|host| {
+                let availability = host.tracker.availability()?;
+                Some(with(point(host), json!({ "asDouble": availability })))
+            })
+            .collect::<Vec<_>>();
+
+        let mut resource = vec![
+            attribute("service.name", json!({ "stringValue": "mping" })),
+            attribute(
+                "service.version",
+                json!({ "stringValue": env!("CARGO_PKG_VERSION") }),
+            ),
+        ];
+        if let Ok(hostname) = dns_lookup::get_hostname() {
+            resource.push(attribute("host.name", json!({ "stringValue": hostname })));
+        }
+        resource.extend(
+            self.resource
+                .iter()
+                .map(|tag| attribute(&tag.key, json!({ "stringValue": tag.value }))),
+        );
+
+        json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": resource },
+                "scopeMetrics": [{
+                    "scope": { "name": "mping", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": [
+                        {
+                            "name": "mping.probes.sent",
+                            "description": "Probes sent.",
+                            "unit": "{probe}",
+                            "sum": counter(|host| host.tally.sent),
+                        },
+                        {
+                            "name": "mping.probes.lost",
+                            "description": "Probes lost.",
+                            "unit": "{probe}",
+                            "sum": counter(|host| host.tally.lost()),
+                        },
+                        {
+                            "name": "mping.rtt",
+                            "description": "Round-trip time of answered probes.",
+                            "unit": "s",
+                            "histogram": {
+                                "aggregationTemporality": CUMULATIVE,
+                                "dataPoints": histogram,
+                            },
+                        },
+                        {
+                            "name": "mping.up",
+                            "description": "1 unless the host is down after --down-after consecutive lost probes.",
+                            "unit": "1",
+                            "gauge": { "dataPoints": up },
+                        },
+                        {
+                            "name": "mping.availability",
+                            "description": "Share of the observed time the host was not down.",
+                            "unit": "1",
+                            "gauge": { "dataPoints": availability },
+                        },
+                    ],
+                }],
+            }],
+        })
+    }
+
+    async fn export(&self) {
+        let body = self.request(SystemTime::now()).to_string();
+        let headers = self
+            .headers
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let request = post(&self.url, "application/json", &headers, &body);
+        let error = match time::timeout(OTLP_TIMEOUT, request).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => return,
+            Ok(Ok(status)) => format!("HTTP status {}", status),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timed out".to_string(),
+        };
+        eprintln!("OTLP export to {} failed: {}", self.url, error);
+    }
+}
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`.
+const CUMULATIVE: u8 = 2;
+
+/// Records every probe event and exports the metrics periodically and once more when the event
+/// channel is closed.  A failed export is not retried; the next one carries the same totals.
+pub async fn run(mut events: EventReceiver, mut exporter: OtlpExporter) {
+    let mut ticker = time::interval(OTLP_EXPORT_INTERVAL);
+    // The first tick completes immediately
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                exporter.record(&event);
+            }
+            _ = ticker.tick() => exporter.export().await,
+        }
+    }
+    exporter.export().await;
+}
+
+fn target_attributes(target: &PingTarget) -> Vec<Value> {
+    let mut attributes = Vec::new();
+    if let Some(host) = &target.host {
+        attributes.push(attribute("host", json!({ "stringValue": host })));
+    }
+    attributes.push(attribute(
+        "addr",
+        json!({ "stringValue": target.addr.to_string() }),
+    ));
+    attributes.push(attribute(
+        "probe",
+        json!({ "stringValue": target.kind.scheme().unwrap_or("icmp") }),
+    ));
+    if let Some(port) = target.kind.port() {
+        attributes.push(attribute("port", json!({ "intValue": port.to_string() })));
+    }
+    attributes
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+/// `point` with the fields of `value` added.
+fn with(mut point: Value, value: Value) -> Value {
+    if let (Some(point), Value::Object(fields)) = (point.as_object_mut(), value) {
+        point.extend(fields);
+    }
+    point
+}
+
+fn nanos(at: SystemTime) -> String {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::Sample;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn targets() -> Vec<PingTarget> {
+        vec![
+            PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+                .with_kind(ProbeKind::Tls { port: 443 }),
+        ]
+    }
+
+    fn event(target: &PingTarget, secs: u64, rtt_us: Option<u64>) -> PingEvent {
+        PingEvent::Sample {
+            target: target.clone(),
+            seq: 0,
+            sample: Sample {
+                at: UNIX_EPOCH + Duration::from_secs(secs),
+                rtt: rtt_us.map(Duration::from_micros),
+                ttl: None,
+            },
+            failure: None,
+        }
+    }
+
+    fn exporter(url: &str) -> OtlpExporter {
+        let tags = vec!["site=fra1".parse().unwrap()];
+        OtlpExporter::new(
+            url.parse().unwrap(),
+            &targets(),
+            tags,
+            StateThresholds::default(),
+        )
+    }
+
+    fn metric<'a>(request: &'a Value, name: &str) -> &'a Value {
+        request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|metric| metric["name"] == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn request_carries_cumulative_metrics_per_target() {
+        let mut exporter = exporter("http://localhost:4318/v1/metrics");
+        let targets = targets();
+        exporter.record(&event(&targets[0], 1, Some(800)));
+        exporter.record(&event(&targets[0], 2, Some(3_000)));
+        exporter.record(&event(&targets[0], 3, None));
+        exporter.record(&event(&targets[1], 1, Some(2_000_000)));
+        let request = exporter.request(UNIX_EPOCH + Duration::from_secs(10));
+
+        let resource = &request["resourceMetrics"][0]["resource"]["attributes"];
+        assert_eq!(
+            resource[0],
+            attribute("service.name", json!({ "stringValue": "mping" }))
+        );
+        assert!(
+            resource
+                .as_array()
+                .unwrap()
+                .contains(&attribute("site", json!({ "stringValue": "fra1" })))
+        );
+
+        let sent = &metric(&request, "mping.probes.sent")["sum"];
+        assert_eq!(sent["aggregationTemporality"], 2);
+        assert_eq!(sent["dataPoints"][0]["asInt"], "3");
+        assert_eq!(sent["dataPoints"][0]["timeUnixNano"], "10000000000");
+        assert_eq!(
+            metric(&request, "mping.probes.lost")["sum"]["dataPoints"][0]["asInt"],
+            "1"
+        );
+
+        let points = &metric(&request, "mping.rtt")["histogram"]["dataPoints"];
+        assert_eq!(points[0]["count"], "2");
+        assert_eq!(points[0]["sum"], 0.0038);
+        assert_eq!(points[0]["min"], 0.0008);
+        assert_eq!(
+            points[0]["bucketCounts"],
+            json!(["0", "1", "0", "1", "0", "0", "0", "0", "0", "0", "0", "0"])
+        );
+        // Slower than the largest bound
+        assert_eq!(points[1]["bucketCounts"][RTT_BUCKETS.len()], "1");
+        assert_eq!(
+            points[1]["attributes"],
+            json!([
+                attribute("addr", json!({ "stringValue": "10.0.0.2" })),
+                attribute("probe", json!({ "stringValue": "tls" })),
+                attribute("port", json!({ "intValue": "443" })),
+            ])
+        );
+
+        let up = &metric(&request, "mping.up")["gauge"]["dataPoints"];
+        assert_eq!(up[0]["asInt"], "1");
+        let availability = &metric(&request, "mping.availability")["gauge"]["dataPoints"];
+        assert_eq!(availability[0]["asDouble"], 1.0);
+        // A single probe observes no time yet
+        assert_eq!(availability.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parses_header_list() {
+        let exporter = exporter("http://localhost:4318/v1/metrics")
+            .with_headers("Authorization=Bearer abc, x-scope = ops,broken");
+        assert_eq!(
+            exporter.headers,
+            vec![
+                ("Authorization".to_string(), "Bearer abc".to_string()),
+                ("x-scope".to_string(), "ops".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_to_receiver_when_events_end() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            loop {
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .and_then(|length| length.parse::<usize>().ok())
+                        .unwrap();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = format!("http://127.0.0.1:{}/v1/metrics", port);
+        let exporter = exporter(&url).with_headers("api-key=secret");
+        let (events, received) = mpsc::unbounded_channel();
+        events.send(event(&targets()[0], 1, Some(1_000))).unwrap();
+        drop(events);
+        run(received, exporter).await;
+
+        let request = receiver.await.unwrap();
+        assert!(request.starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.contains("api-key: secret\r\n"));
+        let body: Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(
+            metric(&body, "mping.probes.sent")["sum"]["dataPoints"][0]["asInt"],
+            "1"
+        );
+    }
+}
@@ -0,0 +1,484 @@
+//! Machine-readable report of a finished run for `--format json`, and the event lines streamed
+//! with `--format ndjson`.
+//!
+//! The field names and types are a stable interface.  Additions that do not change existing
+//! fields keep the schema version; anything else bumps [`SCHEMA_VERSION`].
+
+use crate::core::error::ProbeFailure;
+use crate::display::rfc3339;
+use crate::events::{PingEvent, RecentStats, StateChange};
+use crate::network::client::PingTarget;
+use crate::network::ping::PingResults;
+use crate::state::HostState;
+use crate::stats::OverallStats;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub mping_version: &'static str,
+    /// RFC 3339 UTC timestamp of when the report was created.
+    pub generated_at: String,
+    pub hosts: Vec<HostReport>,
+    pub overall: OverallReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostReport {
+    /// Host name as given or reverse resolved, `null` if unknown.
+    pub host: Option<String>,
+    pub addr: IpAddr,
+    /// `icmp`, `tls` or `ntp`.
+    pub probe: &'static str,
+    /// Port of TLS and NTP probes, `null` for ICMP.
+    pub port: Option<u16>,
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    /// Share of lost probes from 0.0 to 1.0.
+    pub loss_rate: f64,
+    /// `null` if no probe was answered.
+    pub rtt: Option<RttReport>,
+    /// `up`, `degraded` or `down` at the end of the run.
+    pub state: String,
+    /// Share of the observed time the host was not down, `null` if too short to tell.
+    pub availability: Option<f64>,
+    pub outages: Vec<OutageReport>,
+    /// Leaf certificate of TLS probes.
+    pub certificate: Option<CertificateReport>,
+    /// Clock offset measured by NTP probes.
+    pub clock_offset: Option<OffsetReport>,
+    /// Burst statistics with `--burst`.
+    pub bursts: Option<BurstReport>,
+    /// Probe outcomes in the order they were sent, only the most recent ones of long runs.
+    pub packets: Vec<PacketReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RttReport {
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutageReport {
+    pub start: String,
+    /// `null` while the outage is still ongoing at the end of the run.
+    pub end: Option<String>,
+    pub duration_s: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CertificateReport {
+    pub subject: String,
+    pub not_after: String,
+    pub days_to_expiry: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OffsetReport {
+    pub min_us: i64,
+    pub avg_us: i64,
+    pub max_us: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BurstReport {
+    pub count: usize,
+    /// Probes per burst.
+    pub size: u32,
+    /// Most probes lost in a single burst.
+    pub worst_lost: u32,
+    /// Mean spread between the fastest and slowest reply of a burst.
+    pub avg_spread_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketReport {
+    /// Position of the probe in the run, starting at 0.
+    pub seq: u64,
+    pub timestamp: String,
+    /// `null` for a lost probe.
+    pub rtt_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverallReport {
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    pub loss_percent: f64,
+    pub hosts_alive: usize,
+}
+
+/// One line of `--format ndjson`, tagged by `type`.
+#[derive(Debug, Serialize)]
+pub struct EventLine {
+    pub schema_version: u32,
+    /// Host name as given or reverse resolved, `null` if unknown.
+    pub host: Option<String>,
+    pub addr: IpAddr,
+    /// `icmp`, `tls` or `ntp`.
+    pub probe: &'static str,
+    /// Port of TLS and NTP probes, `null` for ICMP.
+    pub port: Option<u16>,
+    /// RFC 3339 UTC timestamp of the probe or state change.
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub event: EventDetails,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventDetails {
+    /// A probe was answered or lost.
+    Probe {
+        /// Position of the probe in the run of the host, starting at 0.
+        seq: u64,
+        /// `null` for a lost probe.
+        rtt_ms: Option<f64>,
+        /// Why the probe was lost, `null` if it was answered.
+        error: Option<ErrorReport>,
+    },
+    /// The host changed state.
+    State {
+        /// `up`, `degraded` or `down`.
+        state: String,
+        /// Left out for the first known state of a host, which no change led to.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        previous_state: Option<String>,
+        /// Time since the first lost probe for changes into or out of `down`.
+        outage_s: Option<f64>,
+        /// Loss rate over the most recent probes from 0.0 to 1.0.
+        recent_loss_rate: f64,
+        recent_rtt_ms: Option<f64>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    /// `timeout`, `io`, `icmp`, `tls` or `ntp`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl EventLine {
+    fn new(target: &PingTarget, at: SystemTime, event: EventDetails) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            host: target.host.clone(),
+            addr: target.addr,
+            probe: target.kind.scheme().unwrap_or("icmp"),
+            port: target.kind.port(),
+            timestamp: rfc3339(at),
+            event,
+        }
+    }
+
+    pub fn probe(event: &PingEvent) -> Self {
+        let PingEvent::Sample {
+            target,
+            seq,
+            sample,
+            failure,
+        } = event;
+        Self::new(
+            target,
+            sample.at,
+            EventDetails::Probe {
+                seq: *seq,
+                rtt_ms: sample.rtt.map(millis),
+                error: failure
+                    .as_ref()
+                    .map(|ProbeFailure { kind, message }| ErrorReport {
+                        kind,
+                        message: message.clone(),
+                    }),
+            },
+        )
+    }
+
+    pub fn state(change: &StateChange) -> Self {
+        Self::new(
+            &change.target,
+            change.transition.at,
+            EventDetails::State {
+                state: change.transition.to.to_string(),
+                previous_state: Some(change.transition.from.to_string()),
+                outage_s: change.outage.map(|outage| outage.as_secs_f64()),
+                recent_loss_rate: change.recent_loss,
+                recent_rtt_ms: change.recent_rtt.map(millis),
+            },
+        )
+    }
+
+    /// The first known state of `target`, which MQTT subscribers need before any change.
+    pub fn initial_state(
+        target: &PingTarget,
+        at: SystemTime,
+        state: HostState,
+        recent: RecentStats,
+    ) -> Self {
+        Self::new(
+            target,
+            at,
+            EventDetails::State {
+                state: state.to_string(),
+                previous_state: None,
+                outage_s: None,
+                recent_loss_rate: recent.loss,
+                recent_rtt_ms: recent.rtt.map(millis),
+            },
+        )
+    }
+}
+
+impl Report {
+    pub fn new(results: &[PingResults], overall: &OverallStats) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            mping_version: env!("CARGO_PKG_VERSION"),
+            generated_at: rfc3339(SystemTime::now()),
+            hosts: results.iter().map(HostReport::new).collect(),
+            overall: OverallReport {
+                sent: overall.total_sent,
+                received: overall.total_received,
+                lost: overall.total_lost,
+                loss_percent: overall.loss_percentage,
+                hosts_alive: overall.hosts_alive,
+            },
+        }
+    }
+}
+
+impl RttReport {
+    /// `None` if no probe was answered.
+    pub fn new(result: &PingResults) -> Option<Self> {
+        Some(Self {
+            min_ms: millis(result.min_duration?),
+            avg_ms: millis(result.avg_duration()?),
+            max_ms: millis(result.max_duration?),
+        })
+    }
+}
+
+impl HostReport {
+    fn new(result: &PingResults) -> Self {
+        let PingTarget { host, addr, kind } = &result.target;
+        let tracker = result.tracker();
+        let end = tracker.last_sample().unwrap_or_else(SystemTime::now);
+
+        Self {
+            host: host.clone(),
+            addr: *addr,
+            probe: kind.scheme().unwrap_or("icmp"),
+            port: kind.port(),
+            sent: result.total_count(),
+            received: result.num_recv,
+            lost: result.num_loss,
+            loss_rate: result.loss_rate() as f64,
+            rtt: RttReport::new(result),
+            state: tracker.state().to_string(),
+            availability: tracker.availability(),
+            outages: tracker
+                .outages()
+                .iter()
+                .map(|outage| OutageReport {
+                    start: rfc3339(outage.start),
+                    end: outage.end.map(rfc3339),
+                    duration_s: outage.duration(end).as_secs_f64(),
+                })
+                .collect(),
+            certificate: result
+                .certificate
+                .as_ref()
+                .map(|certificate| CertificateReport {
+                    subject: certificate.subject.clone(),
+                    not_after: rfc3339(certificate.not_after),
+                    days_to_expiry: certificate.days_to_expiry(),
+                }),
+            clock_offset: match (result.min_offset, result.avg_offset(), result.max_offset) {
+                (Some(min_us), Some(avg_us), Some(max_us)) => Some(OffsetReport {
+                    min_us,
+                    avg_us,
+                    max_us,
+                }),
+                _ => None,
+            },
+            bursts: result.worst_burst().map(|worst| BurstReport {
+                count: result.bursts.len(),
+                size: worst.sent,
+                worst_lost: worst.lost,
+                avg_spread_ms: result.avg_burst_spread().map(millis),
+            }),
+            packets: (result.first_seq()..)
+                .zip(&result.samples)
+                .map(|(seq, sample)| PacketReport {
+                    seq,
+                    timestamp: rfc3339(sample.at),
+                    rtt_ms: sample.rtt.map(millis),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Divides instead of multiplying seconds so values like 0.0778 do not print as
+/// 0.07779900000000001.
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use crate::network::ping::{PingResponse, Sample};
+    use crate::state::{HostState, Transition};
+    use serde_json::Value;
+    use std::net::Ipv4Addr;
+    use std::time::UNIX_EPOCH;
+
+    fn report() -> Value {
+        let mut icmp = PingResults::new(PingTarget::with_host(
+            "gw.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        icmp.add_received(PingResponse::new(Duration::from_micros(1_500)));
+        icmp.add_loss();
+        icmp.add_received(PingResponse::new(Duration::from_micros(2_500)));
+
+        let ntp = PingResults::new(
+            PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+                .with_kind(ProbeKind::Ntp { port: 123 }),
+        );
+
+        let results = [icmp, ntp];
+        let report = Report::new(&results, &OverallStats::from_results(&results));
+        serde_json::to_value(report).unwrap()
+    }
+
+    #[test]
+    fn report_has_versioned_envelope() {
+        let report = report();
+        assert_eq!(report["schema_version"], SCHEMA_VERSION);
+        assert_eq!(report["mping_version"], env!("CARGO_PKG_VERSION"));
+        assert!(report["generated_at"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(report["overall"]["sent"], 3);
+        assert_eq!(report["overall"]["received"], 2);
+        assert_eq!(report["overall"]["hosts_alive"], 1);
+    }
+
+    #[test]
+    fn host_fields_are_stable() {
+        let report = report();
+        let host = report["hosts"][0].as_object().unwrap();
+        let mut keys = host.keys().map(String::as_str).collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "addr",
+                "availability",
+                "bursts",
+                "certificate",
+                "clock_offset",
+                "host",
+                "loss_rate",
+                "lost",
+                "outages",
+                "packets",
+                "port",
+                "probe",
+                "received",
+                "rtt",
+                "sent",
+                "state",
+            ]
+        );
+    }
+
+    #[test]
+    fn host_values() {
+        let report = report();
+        let host = &report["hosts"][0];
+        assert_eq!(host["host"], "gw.example.com");
+        assert_eq!(host["addr"], "10.0.0.1");
+        assert_eq!(host["probe"], "icmp");
+        assert_eq!(host["port"], Value::Null);
+        assert_eq!(host["rtt"]["min_ms"], 1.5);
+        assert_eq!(host["rtt"]["avg_ms"], 2.0);
+        assert_eq!(host["rtt"]["max_ms"], 2.5);
+        assert_eq!(host["state"], "up");
+        assert_eq!(host["packets"].as_array().unwrap().len(), 3);
+        assert_eq!(host["packets"][1]["seq"], 1);
+        assert_eq!(host["packets"][1]["rtt_ms"], Value::Null);
+        assert_eq!(host["certificate"], Value::Null);
+
+        let ntp = &report["hosts"][1];
+        assert_eq!(ntp["host"], Value::Null);
+        assert_eq!(ntp["probe"], "ntp");
+        assert_eq!(ntp["port"], 123);
+        assert_eq!(ntp["rtt"], Value::Null);
+    }
+
+    #[test]
+    fn probe_line() {
+        let event = PingEvent::Sample {
+            target: PingTarget::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+                .with_kind(ProbeKind::Tls { port: 443 }),
+            seq: 7,
+            sample: Sample {
+                at: UNIX_EPOCH,
+                rtt: None,
+                ttl: None,
+            },
+            failure: Some(ProbeFailure {
+                kind: "timeout",
+                message: "Request timeout for seq 7".to_string(),
+            }),
+        };
+        assert_eq!(
+            serde_json::to_string(&EventLine::probe(&event)).unwrap(),
+            format!(
+                "{{\"schema_version\":{},\"host\":null,\"addr\":\"10.0.0.1\",\"probe\":\"tls\",\
+                 \"port\":443,\"timestamp\":\"1970-01-01T00:00:00.000Z\",\"type\":\"probe\",\
+                 \"seq\":7,\"rtt_ms\":null,\"error\":{{\"kind\":\"timeout\",\
+                 \"message\":\"Request timeout for seq 7\"}}}}",
+                SCHEMA_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn state_line() {
+        let change = StateChange {
+            target: PingTarget::with_host(
+                "gw.example.com".to_string(),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            transition: Transition {
+                from: HostState::Down,
+                to: HostState::Up,
+                at: UNIX_EPOCH + Duration::from_secs(60),
+            },
+            outage: Some(Duration::from_secs(42)),
+            recent_loss: 0.5,
+            recent_rtt: Some(Duration::from_micros(1_500)),
+        };
+        let line = serde_json::to_value(EventLine::state(&change)).unwrap();
+        assert_eq!(line["type"], "state");
+        assert_eq!(line["host"], "gw.example.com");
+        assert_eq!(line["timestamp"], "1970-01-01T00:01:00.000Z");
+        assert_eq!(line["state"], "up");
+        assert_eq!(line["previous_state"], "down");
+        assert_eq!(line["outage_s"], 42.0);
+        assert_eq!(line["recent_loss_rate"], 0.5);
+        assert_eq!(line["recent_rtt_ms"], 1.5);
+    }
+}
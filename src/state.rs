@@ -0,0 +1,260 @@
+//! Host up/degraded/down tracking and outage windows.
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HostState {
+    #[default]
+    Up,
+    Degraded,
+    Down,
+}
+
+impl fmt::Display for HostState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostState::Up => write!(f, "up"),
+            HostState::Degraded => write!(f, "degraded"),
+            HostState::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// Number of consecutive lost probes after which a host counts as degraded or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateThresholds {
+    pub degraded_after: u32,
+    pub down_after: u32,
+}
+
+impl Default for StateThresholds {
+    fn default() -> Self {
+        Self {
+            degraded_after: 1,
+            down_after: 3,
+        }
+    }
+}
+
+/// A period during which the host was down.  It starts with the first of the consecutive lost
+/// probes and ends with the next reply, or is still ongoing if `end` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outage {
+    pub start: SystemTime,
+    pub end: Option<SystemTime>,
+}
+
+impl Outage {
+    /// Length of the outage; an ongoing outage is measured up to `now`.
+    pub fn duration(&self, now: SystemTime) -> Duration {
+        self.end
+            .unwrap_or(now)
+            .duration_since(self.start)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: HostState,
+    pub to: HostState,
+    pub at: SystemTime,
+}
+
+/// Derives the host state from probe outcomes as they come in.  Any reply brings the host back
+/// up.
+#[derive(Debug, Clone)]
+pub struct HostTracker {
+    thresholds: StateThresholds,
+    state: HostState,
+    consecutive_losses: u32,
+    first_loss: Option<SystemTime>,
+    outages: Vec<Outage>,
+    first_sample: Option<SystemTime>,
+    last_sample: Option<SystemTime>,
+}
+
+impl HostTracker {
+    pub fn new(thresholds: StateThresholds) -> Self {
+        Self {
+            thresholds,
+            state: HostState::Up,
+            consecutive_losses: 0,
+            first_loss: None,
+            outages: Vec::new(),
+            first_sample: None,
+            last_sample: None,
+        }
+    }
+
+    /// Records one probe outcome and returns the state change it caused, if any.
+    pub fn record(&mut self, at: SystemTime, received: bool) -> Option<Transition> {
+        self.first_sample.get_or_insert(at);
+        self.last_sample = Some(at);
+
+        let next = if received {
+            self.consecutive_losses = 0;
+            self.first_loss = None;
+            HostState::Up
+        } else {
+            self.consecutive_losses += 1;
+            let first_loss = *self.first_loss.get_or_insert(at);
+            if self.consecutive_losses >= self.thresholds.down_after {
+                if self.state != HostState::Down {
+                    self.outages.push(Outage {
+                        start: first_loss,
+                        end: None,
+                    });
+                }
+                HostState::Down
+            } else if self.consecutive_losses >= self.thresholds.degraded_after {
+                HostState::Degraded
+            } else {
+                self.state
+            }
+        };
+
+        if next == self.state {
+            return None;
+        }
+        if self.state == HostState::Down
+            && let Some(outage) = self.outages.last_mut()
+        {
+            outage.end = Some(at);
+        }
+
+        let transition = Transition {
+            from: self.state,
+            to: next,
+            at,
+        };
+        self.state = next;
+        Some(transition)
+    }
+
+    pub fn state(&self) -> HostState {
+        self.state
+    }
+
+    pub fn outages(&self) -> &[Outage] {
+        &self.outages
+    }
+
+    /// Share of the observed time, from the first to the last probe, that the host was not down.
+    /// `None` until some time has been observed.
+    pub fn availability(&self) -> Option<f64> {
+        let (first, last) = (self.first_sample?, self.last_sample?);
+        let observed = last.duration_since(first).ok()?;
+        if observed.is_zero() {
+            return None;
+        }
+
+        let down = self
+            .outages
+            .iter()
+            .map(|outage| outage.duration(last))
+            .sum::<Duration>();
+        Some(1.0 - (down.as_secs_f64() / observed.as_secs_f64()).min(1.0))
+    }
+
+    /// End of the observed time, used to measure an ongoing outage.
+    pub fn last_sample(&self) -> Option<SystemTime> {
+        self.last_sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// Records one probe per second, `true` for a reply.
+    fn track(outcomes: &[bool]) -> HostTracker {
+        let mut tracker = HostTracker::new(StateThresholds::default());
+        for (secs, &received) in outcomes.iter().enumerate() {
+            tracker.record(at(secs as u64), received);
+        }
+        tracker
+    }
+
+    #[test]
+    fn single_loss_degrades_and_reply_recovers() {
+        let mut tracker = HostTracker::new(StateThresholds::default());
+        assert_eq!(tracker.record(at(0), true), None);
+        let degraded = tracker.record(at(1), false).unwrap();
+        assert_eq!(
+            (degraded.from, degraded.to),
+            (HostState::Up, HostState::Degraded)
+        );
+        let up = tracker.record(at(2), true).unwrap();
+        assert_eq!((up.from, up.to), (HostState::Degraded, HostState::Up));
+        assert!(tracker.outages().is_empty());
+    }
+
+    #[test]
+    fn consecutive_losses_open_outage_at_first_loss() {
+        let tracker = track(&[true, false, false, false, false, true, true]);
+        assert_eq!(
+            tracker.outages(),
+            &[Outage {
+                start: at(1),
+                end: Some(at(5)),
+            }]
+        );
+        assert_eq!(tracker.state(), HostState::Up);
+    }
+
+    #[test]
+    fn ongoing_outage_has_no_end() {
+        let tracker = track(&[true, false, false, false]);
+        assert_eq!(tracker.state(), HostState::Down);
+        let outage = tracker.outages()[0];
+        assert_eq!(outage.end, None);
+        assert_eq!(
+            outage.duration(tracker.last_sample().unwrap()),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn down_is_reported_once_per_outage() {
+        let mut tracker = HostTracker::new(StateThresholds::default());
+        let transitions = (0..6)
+            .filter_map(|secs| tracker.record(at(secs), false))
+            .map(|t| t.to)
+            .collect::<Vec<_>>();
+        assert_eq!(transitions, vec![HostState::Degraded, HostState::Down]);
+        assert_eq!(tracker.outages().len(), 1);
+    }
+
+    #[test]
+    fn thresholds_are_configurable() {
+        let thresholds = StateThresholds {
+            degraded_after: 2,
+            down_after: 2,
+        };
+        let mut tracker = HostTracker::new(thresholds);
+        assert_eq!(tracker.record(at(0), false), None);
+        assert_eq!(tracker.record(at(1), false).unwrap().to, HostState::Down);
+    }
+
+    #[test]
+    fn availability_excludes_outage_time() {
+        // Down from 2 s to 6 s of a 10 s run
+        let tracker = track(&[
+            true, true, false, false, false, false, true, true, true, true, true,
+        ]);
+        assert_eq!(tracker.availability(), Some(0.6));
+    }
+
+    #[test]
+    fn availability_needs_observed_time() {
+        assert_eq!(track(&[]).availability(), None);
+        assert_eq!(track(&[true]).availability(), None);
+    }
+}
@@ -1,7 +1,15 @@
+use crate::archive::Row;
 use crate::core::constants::PERCENTAGE_FACTOR;
-use crate::display::DurationExt;
+use crate::display::{DurationExt, display_bandwidth, display_offset, display_time};
+use crate::history::RunStats;
 use crate::network::ping::PingResults;
-use comfy_table::Table;
+use crate::network::pmtu::PathMtu;
+use crate::network::reachability::{Reachability, ReachabilityResults};
+use crate::network::sweep::SweepResults;
+use crate::network::trace::{Hop, is_transient_loss};
+use crate::state::Outage;
+use comfy_table::{Cell, Color, Table};
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Default)]
 pub struct OverallStats {
@@ -9,6 +17,8 @@ pub struct OverallStats {
     pub total_received: u32,
     pub total_lost: u32,
     pub loss_percentage: f64,
+    /// Hosts that answered at least one probe.
+    pub hosts_alive: usize,
 }
 
 impl OverallStats {
@@ -16,6 +26,7 @@ impl OverallStats {
         let total_sent = results.iter().map(|r| r.total_count()).sum();
         let total_received = results.iter().map(|r| r.num_recv).sum();
         let total_lost = results.iter().map(|r| r.num_loss).sum();
+        let hosts_alive = results.iter().filter(|r| r.num_recv > 0).count();
 
         Self {
             total_sent,
@@ -26,38 +37,433 @@ impl OverallStats {
             } else {
                 0.0
             },
+            hosts_alive,
         }
     }
 }
 
 pub fn create_results_table(results: &[PingResults]) -> Table {
     let mut table = Table::new();
+    let show_expiry = results.iter().any(|r| r.certificate.is_some());
+    let show_offset = results.iter().any(|r| r.avg_offset().is_some());
+    let show_pmtu = results.iter().any(|r| r.path_mtu.is_some());
+    let show_bursts = results.iter().any(|r| r.num_bursts() > 0);
+
+    let mut header = vec!["Host", "Addr", "Sent", "Recv", "Loss", "Min", "Max", "Avg"];
+    if show_offset {
+        header.push("Offset");
+    }
+    if show_expiry {
+        header.push("Expiry");
+    }
+    if show_pmtu {
+        header.push("PMTU");
+    }
+    if show_bursts {
+        header.push("Worst burst");
+        header.push("Spread");
+    }
+    table.set_header(header);
 
     for result in results {
-        table.add_row(vec![
-            result.target.host.as_deref().unwrap_or("-"),
-            &result.target.addr.to_string(),
-            &result.total_count().to_string(),
-            &result.num_recv.to_string(),
-            &format!("{:.1}%", result.loss_rate() * PERCENTAGE_FACTOR as f32),
-            &result
+        let mut row = vec![
+            result.target.label(),
+            result.target.addr.to_string(),
+            result.total_count().to_string(),
+            result.num_recv.to_string(),
+            format!("{:.1}%", result.loss_rate() * PERCENTAGE_FACTOR as f32),
+            result
                 .min_duration
                 .map(|d| d.display())
                 .unwrap_or_else(|| "N/A".to_string()),
-            &result
+            result
                 .max_duration
                 .map(|d| d.display())
                 .unwrap_or_else(|| "N/A".to_string()),
-            &result
+            result
                 .avg_duration()
                 .map(|d| d.display())
                 .unwrap_or_else(|| "N/A".to_string()),
+        ];
+        if show_offset {
+            row.push(
+                result
+                    .avg_offset()
+                    .map(display_offset)
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+        }
+        if show_expiry {
+            row.push(
+                result
+                    .certificate
+                    .as_ref()
+                    .map(|c| format!("{} d", c.days_to_expiry()))
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+        }
+        if show_pmtu {
+            row.push(
+                result
+                    .path_mtu
+                    .as_ref()
+                    .map(format_path_mtu)
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+        }
+        if show_bursts {
+            row.push(
+                result
+                    .worst_burst()
+                    .map(|burst| format!("{}/{} lost", burst.lost, burst.sent))
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+            row.push(
+                result
+                    .avg_burst_spread()
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+            );
+        }
+        table.add_row(row);
+    }
+
+    table
+}
+
+/// Builds the host × protocol/port matrix.  All rows are expected to share the same checks.
+pub fn create_matrix_table(results: &[ReachabilityResults]) -> Table {
+    let mut table = Table::new();
+
+    let mut header = vec!["Host".to_string(), "Addr".to_string()];
+    if let Some(first) = results.first() {
+        header.extend(first.outcomes.iter().map(|(check, _)| check.to_string()));
+    }
+    table.set_header(header);
+
+    for result in results {
+        let mut row = vec![result.target.label(), result.target.addr.to_string()];
+        row.extend(
+            result
+                .outcomes
+                .iter()
+                .map(|(_, outcome)| format_reachability(outcome)),
+        );
+        table.add_row(row);
+    }
+
+    table
+}
+
+/// Builds the per-host availability table with the final state and every outage window.
+pub fn create_availability_table(results: &[PingResults]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec!["Host", "Addr", "State", "Availability", "Outages"]);
+
+    for result in results {
+        let tracker = result.tracker();
+        let now = tracker.last_sample().unwrap_or_else(SystemTime::now);
+        let outages = if tracker.outages().is_empty() {
+            "none".to_string()
+        } else {
+            tracker
+                .outages()
+                .iter()
+                .map(|outage| format_outage(outage, now))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        table.add_row(vec![
+            result.target.label(),
+            result.target.addr.to_string(),
+            tracker.state().to_string(),
+            tracker
+                .availability()
+                .map(|a| format!("{:.2}%", a * PERCENTAGE_FACTOR))
+                .unwrap_or_else(|| "N/A".to_string()),
+            outages,
         ]);
     }
 
     table
 }
 
+/// Formats an outage as `start – end (duration)`, with `ongoing` in place of a missing end.
+fn format_outage(outage: &Outage, now: SystemTime) -> String {
+    format!(
+        "{} – {} ({})",
+        display_time(outage.start),
+        outage
+            .end
+            .map(display_time)
+            .unwrap_or_else(|| "ongoing".to_string()),
+        outage.duration(now).display()
+    )
+}
+
+/// Builds the history table with one row per run and target, and a final row over all of them
+/// if there is more than one.
+pub fn create_history_table(runs: &[RunStats]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Run", "Started", "Host", "Addr", "Sent", "Recv", "Loss", "Min", "Max", "Avg",
+    ]);
+
+    for run in runs {
+        table.add_row(history_row(
+            run.run_id.to_string(),
+            display_time(run.started),
+            run,
+        ));
+    }
+    if runs.len() > 1
+        && let Some(total) = RunStats::total(runs)
+    {
+        table.add_row(history_row("All".to_string(), String::new(), &total));
+    }
+
+    table
+}
+
+fn history_row(run_id: String, started: String, run: &RunStats) -> Vec<String> {
+    let rtt = |d: Option<Duration>| d.map(|d| d.display()).unwrap_or_else(|| "N/A".to_string());
+    vec![
+        run_id,
+        started,
+        run.target.clone(),
+        run.addr.clone(),
+        run.sent.to_string(),
+        run.received.to_string(),
+        format!("{:.1}%", run.loss_rate() * PERCENTAGE_FACTOR),
+        rtt(run.min),
+        rtt(run.max),
+        rtt(run.avg),
+    ]
+}
+
+/// Builds the table of round-robin archive rows, oldest first.
+pub fn create_archive_table(rows: &[Row]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Start", "Sent", "Lost", "Loss", "Min", "Median", "Max",
+    ]);
+
+    let rtt = |d: Option<Duration>| d.map(|d| d.display()).unwrap_or_else(|| "N/A".to_string());
+    for row in rows {
+        table.add_row(vec![
+            display_time(row.start),
+            row.sent.to_string(),
+            row.lost.to_string(),
+            format!("{:.1}%", row.loss_rate() * PERCENTAGE_FACTOR),
+            rtt(row.min),
+            rtt(row.median),
+            rtt(row.max),
+        ]);
+    }
+
+    table
+}
+
+/// Width in characters of the longest bar drawn by [`format_archive_graph`].
+const GRAPH_WIDTH: usize = 50;
+
+/// Draws the median RTT of every row as a horizontal bar scaled to the slowest median, with
+/// the loss next to it.  Rows without replies get no bar.
+pub fn format_archive_graph(rows: &[Row]) -> String {
+    const EIGHTHS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+    let slowest = rows
+        .iter()
+        .filter_map(|row| row.median)
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    for row in rows {
+        let (bar, median) = match row.median {
+            Some(median) if !slowest.is_zero() => {
+                let eighths = (median.as_secs_f64() / slowest.as_secs_f64()
+                    * (GRAPH_WIDTH * 8) as f64)
+                    .round()
+                    .max(1.0) as usize;
+                let mut bar = "█".repeat(eighths / 8);
+                if !eighths.is_multiple_of(8) {
+                    bar.push(EIGHTHS[eighths % 8 - 1]);
+                }
+                (bar, median.display())
+            }
+            Some(median) => (String::new(), median.display()),
+            None => (String::new(), "N/A".to_string()),
+        };
+        out.push_str(&format!(
+            "{}  {:<width$}  {:>10}  {:>5.1}% loss\n",
+            display_time(row.start),
+            bar,
+            median,
+            row.loss_rate() * PERCENTAGE_FACTOR,
+            width = GRAPH_WIDTH
+        ));
+    }
+    out
+}
+
+/// Builds the payload sweep table with one row per host and payload size.  The last column
+/// holds the estimated bottleneck bandwidth on each host's first row.
+pub fn create_sweep_table(results: &[SweepResults]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Host",
+        "Addr",
+        "Size",
+        "Sent",
+        "Recv",
+        "Loss",
+        "Min",
+        "Max",
+        "Avg",
+        "Bandwidth",
+    ]);
+
+    for result in results {
+        let bandwidth = result
+            .estimate()
+            .map(|e| display_bandwidth(e.bits_per_second()))
+            .unwrap_or_else(|| "N/A".to_string());
+
+        for (index, (size, bucket)) in result.buckets.iter().enumerate() {
+            let first = index == 0;
+            table.add_row(vec![
+                if first {
+                    result.target.label()
+                } else {
+                    String::new()
+                },
+                if first {
+                    result.target.addr.to_string()
+                } else {
+                    String::new()
+                },
+                size.to_string(),
+                bucket.total_count().to_string(),
+                bucket.num_recv.to_string(),
+                format!("{:.1}%", bucket.loss_rate() * PERCENTAGE_FACTOR as f32),
+                bucket
+                    .min_duration
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+                bucket
+                    .max_duration
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+                bucket
+                    .avg_duration()
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+                if first {
+                    bandwidth.clone()
+                } else {
+                    String::new()
+                },
+            ]);
+        }
+    }
+
+    table
+}
+
+/// Marks loss at an intermediate hop that does not carry through to the destination.
+pub const TRANSIENT_LOSS_MARKER: &str = "~";
+
+/// Builds the traceroute hop table.  Hops that never answered show `*`, and loss that does not
+/// persist to the destination is dimmed and marked with [`TRANSIENT_LOSS_MARKER`].
+pub fn create_hop_table(hops: &[Hop]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec!["Hop", "Addr", "Host", "Loss", "Min", "Max", "Avg"]);
+
+    for (index, hop) in hops.iter().enumerate() {
+        let responder = hop.responder();
+        let results = &hop.results;
+        let loss = format!("{:.1}%", results.loss_rate() * PERCENTAGE_FACTOR as f32);
+        let loss = if is_transient_loss(hops, index) {
+            Cell::new(format!("{} {}", loss, TRANSIENT_LOSS_MARKER)).fg(Color::DarkGrey)
+        } else {
+            Cell::new(loss)
+        };
+
+        table.add_row(vec![
+            Cell::new(hop.ttl),
+            Cell::new(
+                responder
+                    .map(|t| t.addr.to_string())
+                    .unwrap_or_else(|| "*".to_string()),
+            ),
+            Cell::new(
+                responder
+                    .and_then(|t| t.host.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            loss,
+            Cell::new(
+                results
+                    .min_duration
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+            ),
+            Cell::new(
+                results
+                    .max_duration
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+            ),
+            Cell::new(
+                results
+                    .avg_duration()
+                    .map(|d| d.display())
+                    .unwrap_or_else(|| "N/A".to_string()),
+            ),
+        ]);
+    }
+
+    table
+}
+
+/// Largest size that got through, followed by where "fragmentation needed" errors came from,
+/// e.g. `1400 (too big at 10.0.0.1, mtu 1400)`.
+fn format_path_mtu(path_mtu: &PathMtu) -> String {
+    let largest = path_mtu
+        .largest
+        .map(|size| size.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    if path_mtu.too_big.is_empty() {
+        return largest;
+    }
+
+    let sources = path_mtu
+        .too_big
+        .iter()
+        .map(|too_big| {
+            let from = too_big
+                .from
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| "local interface".to_string());
+            match too_big.mtu {
+                Some(mtu) => format!("{}, mtu {}", from, mtu),
+                None => from,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!("{} (too big at {})", largest, sources)
+}
+
+fn format_reachability(outcome: &Reachability) -> String {
+    match outcome {
+        Reachability::Reachable(rtt) => format!("reachable {}", rtt.display()),
+        Reachability::Refused(rtt) => format!("refused {}", rtt.display()),
+        Reachability::Filtered => "filtered".to_string(),
+    }
+}
+
 pub fn sort_results(results: &mut [PingResults]) {
     results.sort_by(|a, b| {
         let a_avg = a.avg_duration().map(|d| d.as_micros());
@@ -76,8 +482,11 @@ mod tests {
     use super::*;
     use crate::network::client::PingTarget;
     use crate::network::ping::{PingResponse, PingResults};
+    use crate::network::pmtu::TooBig;
+    use crate::network::reachability::Check;
+    use crate::network::tls::CertificateInfo;
     use std::net::IpAddr;
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
     fn make_target(ip: &str) -> PingTarget {
         PingTarget::new(ip.parse::<IpAddr>().unwrap())
@@ -85,9 +494,7 @@ mod tests {
 
     fn make_results_with_avg(avg_ms: u64) -> PingResults {
         let mut results = PingResults::new(make_target("8.8.8.8"));
-        results.add_received(PingResponse {
-            duration: Duration::from_millis(avg_ms),
-        });
+        results.add_received(PingResponse::new(Duration::from_millis(avg_ms)));
         results
     }
 
@@ -104,12 +511,8 @@ mod tests {
     #[test]
     fn overall_stats_single_result_all_received() {
         let mut r = PingResults::new(make_target("8.8.8.8"));
-        r.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
-        r.add_received(PingResponse {
-            duration: Duration::from_millis(20),
-        });
+        r.add_received(PingResponse::new(Duration::from_millis(10)));
+        r.add_received(PingResponse::new(Duration::from_millis(20)));
 
         let stats = OverallStats::from_results(&[r]);
         assert_eq!(stats.total_sent, 2);
@@ -121,24 +524,19 @@ mod tests {
     #[test]
     fn overall_stats_mixed_loss_and_received() {
         let mut r1 = PingResults::new(make_target("8.8.8.8"));
-        r1.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        r1.add_received(PingResponse::new(Duration::from_millis(10)));
         r1.add_loss();
 
         let mut r2 = PingResults::new(make_target("1.1.1.1"));
-        r2.add_received(PingResponse {
-            duration: Duration::from_millis(5),
-        });
-        r2.add_received(PingResponse {
-            duration: Duration::from_millis(15),
-        });
+        r2.add_received(PingResponse::new(Duration::from_millis(5)));
+        r2.add_received(PingResponse::new(Duration::from_millis(15)));
 
         let stats = OverallStats::from_results(&[r1, r2]);
         assert_eq!(stats.total_sent, 4);
         assert_eq!(stats.total_received, 3);
         assert_eq!(stats.total_lost, 1);
         assert!((stats.loss_percentage - 25.0).abs() < f64::EPSILON);
+        assert_eq!(stats.hosts_alive, 2);
     }
 
     #[test]
@@ -152,6 +550,7 @@ mod tests {
         assert_eq!(stats.total_received, 0);
         assert_eq!(stats.total_lost, 2);
         assert_eq!(stats.loss_percentage, 100.0);
+        assert_eq!(stats.hosts_alive, 0);
     }
 
     #[test]
@@ -213,9 +612,7 @@ mod tests {
     #[test]
     fn create_results_table_has_correct_row_count() {
         let mut r = PingResults::new(make_target("8.8.8.8"));
-        r.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        r.add_received(PingResponse::new(Duration::from_millis(10)));
 
         let results = vec![r];
         let table = create_results_table(&results);
@@ -228,9 +625,7 @@ mod tests {
         for i in 0..100 {
             let mut r = PingResults::new(make_target(&format!("10.0.0.{}", i)));
             if i % 2 == 0 {
-                r.add_received(PingResponse {
-                    duration: Duration::from_millis(10),
-                });
+                r.add_received(PingResponse::new(Duration::from_millis(10)));
             } else {
                 r.add_loss();
             }
@@ -259,9 +654,7 @@ mod tests {
     #[test]
     fn overall_stats_single_host_single_received() {
         let mut r = PingResults::new(make_target("8.8.8.8"));
-        r.add_received(PingResponse {
-            duration: Duration::from_millis(5),
-        });
+        r.add_received(PingResponse::new(Duration::from_millis(5)));
 
         let stats = OverallStats::from_results(&[r]);
         assert_eq!(stats.total_sent, 1);
@@ -276,14 +669,10 @@ mod tests {
         let t2 = PingTarget::new("10.0.0.2".parse::<IpAddr>().unwrap());
 
         let mut r1 = PingResults::new(t1);
-        r1.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        r1.add_received(PingResponse::new(Duration::from_millis(10)));
 
         let mut r2 = PingResults::new(t2);
-        r2.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        r2.add_received(PingResponse::new(Duration::from_millis(10)));
 
         let mut results = vec![r1, r2];
         sort_results(&mut results);
@@ -344,9 +733,7 @@ mod tests {
     #[test]
     fn create_results_table_multiple_rows() {
         let mut r1 = PingResults::new(make_target("8.8.8.8"));
-        r1.add_received(PingResponse {
-            duration: Duration::from_millis(10),
-        });
+        r1.add_received(PingResponse::new(Duration::from_millis(10)));
 
         let mut r2 = PingResults::new(make_target("1.1.1.1"));
         r2.add_loss();
@@ -363,6 +750,274 @@ mod tests {
         assert_eq!(table.row_count(), 0);
     }
 
+    #[test]
+    fn create_results_table_hides_expiry_without_certificates() {
+        let results = vec![make_results_with_avg(10)];
+        let mut table = create_results_table(&results);
+        assert_eq!(table.column_count(), 8);
+    }
+
+    #[test]
+    fn create_results_table_shows_expiry_with_certificate() {
+        let mut r = make_results_with_avg(10);
+        r.certificate = Some(CertificateInfo {
+            subject: "CN=example.com".to_string(),
+            not_after: SystemTime::now() + Duration::from_secs(30 * 86_400 + 60),
+        });
+
+        let results = vec![r, make_results_with_avg(20)];
+        let mut table = create_results_table(&results);
+        assert_eq!(table.column_count(), 9);
+        assert_eq!(
+            table.column_iter().count(),
+            table.header().unwrap().cell_count()
+        );
+    }
+
+    #[test]
+    fn create_results_table_shows_path_mtu_and_reporting_router() {
+        let mut r = make_results_with_avg(10);
+        r.path_mtu = Some(PathMtu {
+            largest: Some(1400),
+            too_big: vec![TooBig {
+                from: Some("10.0.0.1".parse().unwrap()),
+                mtu: Some(1400),
+            }],
+        });
+
+        let table = create_results_table(&[r]);
+        let rendered = table.to_string();
+        assert!(rendered.contains("PMTU"));
+        assert!(rendered.contains("1400 (too big at 10.0.0.1, mtu 1400)"));
+    }
+
+    #[test]
+    fn format_path_mtu_without_errors_is_size_only() {
+        let path_mtu = PathMtu {
+            largest: Some(1500),
+            too_big: vec![],
+        };
+        assert_eq!(format_path_mtu(&path_mtu), "1500");
+    }
+
+    #[test]
+    fn format_path_mtu_local_interface_limit() {
+        let path_mtu = PathMtu {
+            largest: None,
+            too_big: vec![TooBig {
+                from: None,
+                mtu: None,
+            }],
+        };
+        assert_eq!(format_path_mtu(&path_mtu), "- (too big at local interface)");
+    }
+
+    #[test]
+    fn create_sweep_table_has_row_per_size_and_bandwidth_once() {
+        let target = make_target("10.0.0.1");
+        let buckets = [(100, 1_100), (1100, 2_100)]
+            .into_iter()
+            .map(|(size, micros)| {
+                let mut bucket = PingResults::new(target.clone());
+                bucket.add_received(PingResponse::new(Duration::from_micros(micros)));
+                (size, bucket)
+            })
+            .collect();
+        let results = vec![SweepResults { target, buckets }];
+
+        let table = create_sweep_table(&results);
+        assert_eq!(table.row_count(), 2);
+        let header = table
+            .header()
+            .unwrap()
+            .cell_iter()
+            .map(|cell| cell.content())
+            .collect::<Vec<_>>();
+        assert_eq!(header[6..9], ["Min", "Max", "Avg"]);
+        assert_eq!(table.to_string().matches("Mbit/s").count(), 1);
+    }
+
+    #[test]
+    fn create_results_table_shows_worst_burst_and_spread() {
+        let mut r = PingResults::new(make_target("10.0.0.1"));
+        r.add_burst(vec![
+            Some(PingResponse::new(Duration::from_millis(5))),
+            None,
+            Some(PingResponse::new(Duration::from_millis(7))),
+        ]);
+
+        let mut table = create_results_table(&[r]);
+        assert_eq!(table.column_count(), 10);
+        let rendered = table.to_string();
+        assert!(rendered.contains("1/3 lost"));
+        assert!(rendered.contains("2.00 ms"));
+    }
+
+    #[test]
+    fn create_availability_table_lists_outages() {
+        let mut r = PingResults::new(make_target("10.0.0.1"));
+        r.add_received(PingResponse::new(Duration::from_millis(5)));
+        for _ in 0..3 {
+            r.add_loss();
+        }
+
+        let table = create_availability_table(&[r]);
+        let rendered = table.to_string();
+        assert_eq!(table.row_count(), 1);
+        assert!(rendered.contains("down"));
+        assert!(rendered.contains("ongoing"));
+    }
+
+    #[test]
+    fn create_history_table_adds_total_row() {
+        let run = |run_id, avg_ms| RunStats {
+            run_id,
+            started: SystemTime::UNIX_EPOCH,
+            target: "db1".to_string(),
+            addr: "10.0.0.2".to_string(),
+            sent: 10,
+            received: 9,
+            min: Some(Duration::from_millis(avg_ms)),
+            avg: Some(Duration::from_millis(avg_ms)),
+            max: Some(Duration::from_millis(avg_ms)),
+        };
+        let table = create_history_table(&[run(1, 10)]);
+        assert_eq!(table.row_count(), 1);
+
+        let table = create_history_table(&[run(1, 10), run(2, 30)]);
+        assert_eq!(table.row_count(), 3);
+        let rendered = table.to_string();
+        assert!(rendered.contains("All"));
+        assert!(rendered.contains("20.00 ms"));
+        assert!(rendered.contains("10.0%"));
+    }
+
+    fn archive_row(minute: u64, median_ms: Option<u64>) -> Row {
+        let median = median_ms.map(Duration::from_millis);
+        Row {
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(minute * 60),
+            sent: 4,
+            lost: if median.is_some() { 1 } else { 4 },
+            median,
+            min: median,
+            max: median,
+        }
+    }
+
+    #[test]
+    fn create_archive_table_has_row_per_step() {
+        let table = create_archive_table(&[archive_row(0, Some(5)), archive_row(1, None)]);
+        assert_eq!(table.row_count(), 2);
+        let rendered = table.to_string();
+        assert!(rendered.contains("25.0%"));
+        assert!(rendered.contains("N/A"));
+    }
+
+    #[test]
+    fn format_archive_graph_scales_bars_to_slowest_median() {
+        let graph = format_archive_graph(&[
+            archive_row(0, Some(10)),
+            archive_row(1, Some(5)),
+            archive_row(2, None),
+        ]);
+        let lines = graph.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].matches('█').count(), GRAPH_WIDTH);
+        assert_eq!(lines[1].matches('█').count(), GRAPH_WIDTH / 2);
+        assert!(!lines[2].contains('█'));
+        assert!(lines[2].ends_with("100.0% loss"));
+    }
+
+    #[test]
+    fn format_outage_shows_duration() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let outage = Outage {
+            start,
+            end: Some(start + Duration::from_secs(30)),
+        };
+        assert!(format_outage(&outage, start).ends_with("(30.00 s)"));
+    }
+
+    #[test]
+    fn create_results_table_shows_offset_for_ntp_results() {
+        let mut r = PingResults::new(make_target("10.0.0.1"));
+        r.add_received(PingResponse::new(Duration::from_millis(5)).with_offset(-1200));
+
+        let results = vec![r, make_results_with_avg(20)];
+        let mut table = create_results_table(&results);
+        assert_eq!(table.column_count(), 9);
+    }
+
+    #[test]
+    fn create_matrix_table_has_column_per_check() {
+        let results = vec![ReachabilityResults {
+            target: make_target("10.0.0.1"),
+            outcomes: vec![
+                (
+                    Check::Icmp,
+                    Reachability::Reachable(Duration::from_millis(3)),
+                ),
+                (
+                    Check::Tcp(22),
+                    Reachability::Refused(Duration::from_millis(4)),
+                ),
+                (Check::Udp(53), Reachability::Filtered),
+            ],
+        }];
+        let mut table = create_matrix_table(&results);
+        assert_eq!(table.row_count(), 1);
+        assert_eq!(table.column_count(), 5);
+    }
+
+    #[test]
+    fn format_reachability_includes_rtt() {
+        assert_eq!(
+            format_reachability(&Reachability::Reachable(Duration::from_millis(2))),
+            "reachable 2.00 ms"
+        );
+        assert_eq!(
+            format_reachability(&Reachability::Refused(Duration::from_micros(80))),
+            "refused 80.00 μs"
+        );
+        assert_eq!(format_reachability(&Reachability::Filtered), "filtered");
+    }
+
+    #[test]
+    fn create_hop_table_has_row_per_hop() {
+        let hops = vec![
+            Hop {
+                ttl: 1,
+                results: make_results_with_avg(1),
+            },
+            Hop {
+                ttl: 2,
+                results: PingResults::new(make_target("0.0.0.0")),
+            },
+        ];
+        let mut table = create_hop_table(&hops);
+        assert_eq!(table.row_count(), 2);
+        assert_eq!(table.column_count(), 7);
+    }
+
+    #[test]
+    fn create_hop_table_marks_transient_loss() {
+        let mut lossy = make_results_with_avg(2);
+        lossy.add_loss();
+        let hops = vec![
+            Hop {
+                ttl: 1,
+                results: lossy,
+            },
+            Hop {
+                ttl: 2,
+                results: make_results_with_avg(3),
+            },
+        ];
+        let table = create_hop_table(&hops);
+        let rendered = table.to_string();
+        assert!(rendered.contains(&format!("50.0% {}", TRANSIENT_LOSS_MARKER)));
+    }
+
     #[test]
     fn create_results_table_with_all_loss_shows_na_for_durations() {
         let mut r = PingResults::new(make_target("8.8.8.8"));
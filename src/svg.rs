@@ -0,0 +1,423 @@
+//! Smokeping-style latency graphs, one SVG file per target.
+//!
+//! The samples kept of the run, the most recent
+//! [`SAMPLE_HISTORY`](crate::core::constants::SAMPLE_HISTORY) probes, are split into at most
+//! [`SVG_COLUMNS`] equal time buckets.  Every bucket shows the spread of its RTTs as grey
+//! "smoke", darkest where most replies fell, and its median as a line colored by the loss in
+//! that bucket.  Buckets without any reply are shaded in the color of total loss.
+
+use crate::archive;
+use crate::core::constants::{PERCENTAGE_FACTOR, SVG_COLUMNS};
+use crate::display::DurationExt;
+use crate::network::client::PingTarget;
+use crate::network::ping::{PingResults, Sample};
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 320.0;
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 50.0;
+const BOTTOM: f64 = 70.0;
+/// Number of nested quantile bands drawn as smoke in every bucket.
+const SMOKE_BANDS: usize = 10;
+/// Opacity of one smoke band, so the innermost band is drawn at ten times this.
+const SMOKE_OPACITY: f64 = 0.08;
+const Y_TICKS: u32 = 5;
+const X_TICKS: usize = 6;
+
+/// Highest loss rate of every median color, with its legend label, as in Smokeping.
+const LOSS_COLORS: [(f64, &str, &str); 7] = [
+    (0.0, "#26ff00", "0"),
+    (0.05, "#00b8ff", "≤5%"),
+    (0.10, "#0059ff", "≤10%"),
+    (0.20, "#5e00ff", "≤20%"),
+    (0.50, "#dd00ff", "≤50%"),
+    (1.0 - f64::EPSILON, "#ff0000", "<100%"),
+    (1.0, "#a10000", "100%"),
+];
+
+/// Probes of one time bucket.
+#[derive(Debug, Default)]
+struct Bucket {
+    sent: usize,
+    /// Sorted RTTs of the answered probes.
+    rtts: Vec<Duration>,
+}
+
+impl Bucket {
+    fn loss_rate(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (self.sent - self.rtts.len()) as f64 / self.sent as f64
+    }
+
+    /// Nearest-rank quantile `q` in `0.0..=1.0`.
+    fn quantile(&self, q: f64) -> Duration {
+        self.rtts[((self.rtts.len() - 1) as f64 * q).round() as usize]
+    }
+}
+
+/// Writes the graph of every target into `dir`, which is created if needed.
+pub fn write_all(dir: &Path, results: &[PingResults]) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for result in results {
+        let path = dir.join(file_name(&result.target));
+        fs::write(&path, render(result))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// `<host>.svg`, named like the archive files, e.g. `api.example.com_tls_443.svg`.
+pub fn file_name(target: &PingTarget) -> String {
+    format!("{}.svg", archive::file_stem(target))
+}
+
+/// Draws the smoke graph of all samples of `result`.
+pub fn render(result: &PingResults) -> String {
+    let samples = &result.samples;
+    let start = samples.iter().map(|s| s.at).min();
+    let end = samples.iter().map(|s| s.at).max();
+    let (buckets, step) = match (start, end) {
+        (Some(start), Some(end)) => bucketize(samples, start, end),
+        _ => (Vec::new(), Duration::from_secs(1)),
+    };
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let column = plot_width / buckets.len().max(1) as f64;
+
+    let mut all = samples.iter().filter_map(|s| s.rtt).collect::<Vec<_>>();
+    all.sort();
+    let slowest_median = buckets
+        .iter()
+        .filter(|b| !b.rtts.is_empty())
+        .map(|b| b.quantile(0.5))
+        .max();
+    // Outliers above the 95th percentile are clipped rather than squashing the rest
+    let top = all
+        .get((all.len() * 95).div_ceil(100).saturating_sub(1))
+        .copied()
+        .max(slowest_median)
+        .map(|rtt| nice_ceiling(rtt.mul_f64(1.1)))
+        .unwrap_or(Duration::from_millis(1));
+    let y = |rtt: Duration| {
+        TOP + plot_height * (1.0 - (rtt.as_secs_f64() / top.as_secs_f64()).min(1.0))
+    };
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"11\">",
+        w = WIDTH,
+        h = HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        "<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>",
+        WIDTH, HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"20\" font-size=\"14\" font-weight=\"bold\">{}</text>",
+        LEFT,
+        escape(&format!("{} {}", result.target.label(), result.target.addr))
+    );
+    let median = all.get(all.len().saturating_sub(1) / 2);
+    let lost = samples.len() - all.len();
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"38\">{} probes, {} lost ({:.1}%), median {}, {} per column</text>",
+        LEFT,
+        samples.len(),
+        lost,
+        lost as f64 / samples.len().max(1) as f64 * PERCENTAGE_FACTOR,
+        median
+            .map(|m| m.display())
+            .unwrap_or_else(|| "N/A".to_string()),
+        display_step(step)
+    );
+
+    // Grid and RTT axis
+    for tick in 0..=Y_TICKS {
+        let rtt = top * tick / Y_TICKS;
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{}\" y1=\"{y:.1}\" x2=\"{}\" y2=\"{y:.1}\" stroke=\"#dddddd\"/>",
+            LEFT,
+            WIDTH - RIGHT,
+            y = y(rtt)
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            LEFT - 6.0,
+            y(rtt) + 4.0,
+            if tick == 0 {
+                "0".to_string()
+            } else {
+                escape(&rtt.display())
+            }
+        );
+    }
+
+    for (i, bucket) in buckets.iter().enumerate() {
+        let x = LEFT + column * i as f64;
+        if bucket.sent == 0 {
+            continue;
+        }
+        if bucket.rtts.is_empty() {
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\" fill-opacity=\"0.2\"/>",
+                x,
+                TOP,
+                column,
+                plot_height,
+                loss_color(1.0)
+            );
+            continue;
+        }
+        for band in 0..SMOKE_BANDS {
+            let q = band as f64 / (2 * SMOKE_BANDS) as f64;
+            let (low, high) = (y(bucket.quantile(q)), y(bucket.quantile(1.0 - q)));
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#000000\" fill-opacity=\"{}\"/>",
+                x,
+                high,
+                column,
+                (low - high).max(0.5),
+                SMOKE_OPACITY
+            );
+        }
+        let median = y(bucket.quantile(0.5));
+        let _ = writeln!(
+            svg,
+            "<line class=\"median\" x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"2\"/>",
+            x,
+            median,
+            x + column,
+            median,
+            loss_color(bucket.loss_rate())
+        );
+    }
+
+    // Time axis
+    let _ = writeln!(
+        svg,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#000000\"/>",
+        LEFT, TOP, plot_width, plot_height
+    );
+    if let Some(start) = start {
+        let ticks = buckets.len().min(X_TICKS);
+        let long = step * buckets.len() as u32 >= Duration::from_secs(86_400);
+        for tick in 0..=ticks {
+            let i = buckets.len() * tick / ticks.max(1);
+            let at = DateTime::<Local>::from(start + step * i as u32);
+            let _ = writeln!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                LEFT + column * i as f64,
+                TOP + plot_height + 16.0,
+                at.format(if long { "%m-%d %H:%M" } else { "%H:%M:%S" })
+            );
+        }
+    }
+
+    // Legend
+    let legend = TOP + plot_height + 44.0;
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\">median, loss:</text>",
+        LEFT,
+        legend + 4.0
+    );
+    for (i, (_, color, label)) in LOSS_COLORS.iter().enumerate() {
+        let x = LEFT + 90.0 + 70.0 * i as f64;
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"4\"/>",
+            x,
+            legend,
+            x + 20.0,
+            legend,
+            color
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\">{}</text>",
+            x + 24.0,
+            legend + 4.0,
+            escape(label)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Splits the samples into at most [`SVG_COLUMNS`] buckets of whole seconds.
+fn bucketize(
+    samples: &VecDeque<Sample>,
+    start: SystemTime,
+    end: SystemTime,
+) -> (Vec<Bucket>, Duration) {
+    let span = end.duration_since(start).unwrap_or_default();
+    let step = Duration::from_secs(span.as_secs().div_ceil(SVG_COLUMNS as u64).max(1));
+    let columns = (span.as_secs() / step.as_secs()) as usize + 1;
+    let mut buckets = (0..columns).map(|_| Bucket::default()).collect::<Vec<_>>();
+    for sample in samples {
+        let offset = sample.at.duration_since(start).unwrap_or_default();
+        let bucket = &mut buckets[(offset.as_secs() / step.as_secs()) as usize];
+        bucket.sent += 1;
+        bucket.rtts.extend(sample.rtt);
+    }
+    for bucket in &mut buckets {
+        bucket.rtts.sort();
+    }
+    (buckets, step)
+}
+
+fn loss_color(loss_rate: f64) -> &'static str {
+    LOSS_COLORS
+        .iter()
+        .find(|(max, _, _)| loss_rate <= *max)
+        .map_or(LOSS_COLORS[LOSS_COLORS.len() - 1].1, |(_, color, _)| color)
+}
+
+/// Rounds up to 1, 2 or 5 times a power of ten microseconds, so axis ticks are round.
+fn nice_ceiling(rtt: Duration) -> Duration {
+    let micros = rtt.as_micros().max(1) as u64;
+    let mut power = 1;
+    while power * 10 < micros {
+        power *= 10;
+    }
+    let nice = [1, 2, 5, 10]
+        .into_iter()
+        .map(|m| m * power)
+        .find(|nice| *nice >= micros)
+        .unwrap_or(10 * power);
+    Duration::from_micros(nice)
+}
+
+fn display_step(step: Duration) -> String {
+    match step.as_secs() {
+        s if s % 3_600 == 0 => format!("{} h", s / 3_600),
+        s if s % 60 == 0 => format!("{} min", s / 60),
+        s => format!("{} s", s),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::client::ProbeKind;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::UNIX_EPOCH;
+
+    fn results(samples: &[(u64, Option<u64>)]) -> PingResults {
+        let mut results = PingResults::new(PingTarget::with_host(
+            "db1.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        results.samples = samples
+            .iter()
+            .map(|&(secs, rtt)| Sample {
+                at: UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs),
+                rtt: rtt.map(Duration::from_millis),
+                ttl: None,
+            })
+            .collect();
+        results
+    }
+
+    #[test]
+    fn buckets_cover_the_run_in_whole_seconds() {
+        let results = results(&[(0, Some(1)), (300, None), (599, Some(3))]);
+        let start = results.samples[0].at;
+        let (buckets, step) = bucketize(&results.samples, start, results.samples[2].at);
+        assert_eq!(step, Duration::from_secs(5));
+        assert_eq!(buckets.len(), 120);
+        assert_eq!(buckets[60].sent, 1);
+        assert_eq!(buckets[60].loss_rate(), 1.0);
+        assert_eq!(buckets[119].rtts, vec![Duration::from_millis(3)]);
+    }
+
+    #[test]
+    fn medians_are_colored_by_loss() {
+        let svg = render(&results(&[
+            (0, Some(10)),
+            (0, Some(12)),
+            (1, Some(20)),
+            (1, None),
+            (2, None),
+        ]));
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">db1.example.com 10.0.0.1</text>"));
+        assert!(svg.contains("5 probes, 2 lost (40.0%), median 12.00 ms, 1 s per column"));
+        assert_eq!(svg.matches("class=\"median\"").count(), 2);
+        assert!(svg.contains("stroke=\"#26ff00\" stroke-width=\"2\""));
+        assert!(svg.contains("stroke=\"#dd00ff\" stroke-width=\"2\""));
+        // The last second had no reply at all
+        assert!(svg.contains("fill=\"#a10000\" fill-opacity=\"0.2\""));
+        assert_eq!(svg.matches("fill=\"#000000\"").count(), 2 * SMOKE_BANDS);
+        assert!(svg.contains(">&lt;100%</text>"));
+    }
+
+    #[test]
+    fn empty_results_still_render() {
+        let svg = render(&results(&[]));
+        assert!(svg.contains("0 probes, 0 lost (0.0%), median N/A"));
+        assert!(!svg.contains("class=\"median\""));
+    }
+
+    #[test]
+    fn labels_are_escaped_and_files_named_per_target() {
+        let mut results = results(&[(0, Some(1))]);
+        results.target =
+            PingTarget::with_host("a<b>&c".to_string(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+                .with_kind(ProbeKind::Tls { port: 443 });
+        assert!(render(&results).contains(">tls://a&lt;b&gt;&amp;c:443 10.0.0.1</text>"));
+        assert_eq!(file_name(&results.target), "a_b__c_tls_443.svg");
+    }
+
+    #[test]
+    fn axis_tops_are_round() {
+        assert_eq!(
+            nice_ceiling(Duration::from_micros(1)),
+            Duration::from_micros(1)
+        );
+        assert_eq!(
+            nice_ceiling(Duration::from_micros(130)),
+            Duration::from_micros(200)
+        );
+        assert_eq!(
+            nice_ceiling(Duration::from_micros(4_100)),
+            Duration::from_millis(5)
+        );
+        assert_eq!(
+            nice_ceiling(Duration::from_micros(6_000)),
+            Duration::from_millis(10)
+        );
+        assert_eq!(
+            nice_ceiling(Duration::from_millis(100)),
+            Duration::from_millis(100)
+        );
+    }
+}
@@ -0,0 +1,664 @@
+//! JSON webhook notifications for host state changes and threshold breaches.
+
+use crate::core::constants::{
+    PERCENTAGE_FACTOR, WEBHOOK_ATTEMPTS, WEBHOOK_DEDUP_WINDOW, WEBHOOK_RETRY_DELAY, WEBHOOK_TIMEOUT,
+};
+use crate::display::{DurationExt, rfc3339};
+use crate::events::{RECENT_SAMPLES, RecentStats, StateChange};
+use crate::network::client::PingTarget;
+use crate::network::http::{HttpUrl, post_json};
+use crate::network::ping::PingResults;
+use crate::state::HostState;
+use futures::future::join_all;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use tokio::time::{self, Instant};
+
+/// Shape of the JSON document posted to a webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WebhookFormat {
+    /// All alert details as separate fields.
+    #[default]
+    Json,
+    /// Slack incoming webhook message.
+    Slack,
+    /// Microsoft Teams connector card.
+    Teams,
+}
+
+impl FromStr for WebhookFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(WebhookFormat::Json),
+            "slack" => Ok(WebhookFormat::Slack),
+            "teams" => Ok(WebhookFormat::Teams),
+            _ => Err(format!(
+                "unknown webhook format '{}', expected json, slack or teams",
+                s
+            )),
+        }
+    }
+}
+
+/// A webhook given as `[FORMAT=]URL`, e.g. `slack=https://hooks.slack.com/services/...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub format: WebhookFormat,
+    pub url: HttpUrl,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A URL may contain `=` in its query, so only a known format name counts as a prefix
+        let (format, url) = match s.split_once('=') {
+            Some((name, url)) if name.parse::<WebhookFormat>().is_ok() => (name.parse()?, url),
+            _ => (WebhookFormat::Json, s),
+        };
+        Ok(Self {
+            format,
+            url: url.parse()?,
+        })
+    }
+}
+
+/// Limits on the statistics of a host above which an alert is sent, checked against the recent
+/// probes while the run is in progress and against the whole run at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AlertThresholds {
+    /// Packet loss in percent.
+    pub loss_percent: Option<f64>,
+    pub avg_rtt: Option<Duration>,
+}
+
+impl AlertThresholds {
+    /// Alerts for every limit that `results` exceeds.
+    pub fn breaches(&self, results: &PingResults) -> Vec<Alert> {
+        if results.total_count() == 0 {
+            return Vec::new();
+        }
+        let at = results
+            .last_sample()
+            .map_or_else(SystemTime::now, |sample| sample.at);
+        self.check(
+            &results.target,
+            at,
+            results.loss_rate() as f64 * PERCENTAGE_FACTOR,
+            results.avg_duration(),
+        )
+    }
+
+    /// Alerts for every limit that the last [`RECENT_SAMPLES`] probes of `target` exceed, as of
+    /// the probe at `at`.  Nothing is checked until that many probes were sent.
+    pub fn recent_breaches(
+        &self,
+        target: &PingTarget,
+        at: SystemTime,
+        recent: RecentStats,
+    ) -> Vec<Alert> {
+        if recent.probes < RECENT_SAMPLES {
+            return Vec::new();
+        }
+        self.check(target, at, recent.loss * PERCENTAGE_FACTOR, recent.rtt)
+    }
+
+    fn check(
+        &self,
+        target: &PingTarget,
+        at: SystemTime,
+        loss_percent: f64,
+        avg_rtt: Option<Duration>,
+    ) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if let Some(limit) = self.loss_percent
+            && loss_percent > limit
+        {
+            alerts.push(Alert::Loss {
+                target: target.clone(),
+                at,
+                loss_percent,
+                limit,
+            });
+        }
+        if let (Some(limit), Some(avg_rtt)) = (self.avg_rtt, avg_rtt)
+            && avg_rtt > limit
+        {
+            alerts.push(Alert::Rtt {
+                target: target.clone(),
+                at,
+                avg_rtt,
+                limit,
+            });
+        }
+        alerts
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Alert {
+    /// The host went down or recovered from an outage.
+    State(StateChange),
+    /// Packet loss over the recent probes or the whole run exceeded the limit.
+    Loss {
+        target: PingTarget,
+        at: SystemTime,
+        loss_percent: f64,
+        limit: f64,
+    },
+    /// Average RTT over the recent probes or the whole run exceeded the limit.
+    Rtt {
+        target: PingTarget,
+        at: SystemTime,
+        avg_rtt: Duration,
+        limit: Duration,
+    },
+}
+
+impl Alert {
+    /// An alert for `change` if it takes the host down or brings it back up from an outage.
+    /// Changes between up and degraded are not worth a notification.
+    pub fn from_state_change(change: StateChange) -> Option<Self> {
+        let (from, to) = (change.transition.from, change.transition.to);
+        (to == HostState::Down || from == HostState::Down).then_some(Alert::State(change))
+    }
+
+    pub fn target(&self) -> &PingTarget {
+        match self {
+            Alert::State(change) => &change.target,
+            Alert::Loss { target, .. } | Alert::Rtt { target, .. } => target,
+        }
+    }
+
+    /// Short name of the alert, also sent as the `event` field.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Alert::State(change) if change.transition.to == HostState::Down => "down",
+            Alert::State(_) => "up",
+            Alert::Loss { .. } => "loss",
+            Alert::Rtt { .. } => "rtt",
+        }
+    }
+
+    pub fn at(&self) -> SystemTime {
+        match self {
+            Alert::State(change) => change.transition.at,
+            Alert::Loss { at, .. } | Alert::Rtt { at, .. } => *at,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let target = self.target();
+        match self {
+            Alert::State(change) if change.transition.to == HostState::Down => format!(
+                "{} is down (was {}) with {:.0} % recent loss",
+                target,
+                change.transition.from,
+                change.recent_loss * PERCENTAGE_FACTOR
+            ),
+            Alert::State(change) => match change.outage {
+                Some(outage) => format!("{} is up again after {} down", target, outage.display()),
+                None => format!("{} is up again", target),
+            },
+            Alert::Loss {
+                loss_percent,
+                limit,
+                ..
+            } => format!(
+                "{} lost {:.2} % of probes, above the {} % alert threshold",
+                target, loss_percent, limit
+            ),
+            Alert::Rtt { avg_rtt, limit, .. } => format!(
+                "{} average RTT {} is above the {} alert threshold",
+                target,
+                avg_rtt.display(),
+                limit.display()
+            ),
+        }
+    }
+
+    /// The document posted to a webhook of the given `format`.
+    pub fn payload(&self, format: WebhookFormat) -> Value {
+        let message = self.message();
+        match format {
+            WebhookFormat::Json => self.json(message),
+            WebhookFormat::Slack => json!({ "text": message }),
+            WebhookFormat::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "themeColor": if self.event() == "up" { "2EB886" } else { "D93F0B" },
+                "summary": message,
+                "title": format!("mping: {} {}", self.target().label(), self.event()),
+                "text": message,
+            }),
+        }
+    }
+
+    fn json(&self, message: String) -> Value {
+        let target = self.target();
+        let mut payload = json!({
+            "event": self.event(),
+            "host": target.label(),
+            "addr": target.addr.to_string(),
+            "timestamp": rfc3339(self.at()),
+            "message": message,
+        });
+        let details = match self {
+            Alert::State(change) => json!({
+                "state": change.transition.to.to_string(),
+                "previous_state": change.transition.from.to_string(),
+                "outage_seconds": change.outage.map(|d| d.as_secs_f64()),
+                "loss_percent": change.recent_loss * PERCENTAGE_FACTOR,
+                "rtt_ms": change.recent_rtt.map(millis),
+            }),
+            Alert::Loss {
+                loss_percent,
+                limit,
+                ..
+            } => json!({ "loss_percent": loss_percent, "limit_percent": limit }),
+            Alert::Rtt { avg_rtt, limit, .. } => {
+                json!({ "rtt_ms": millis(*avg_rtt), "limit_ms": millis(*limit) })
+            }
+        };
+        if let (Some(payload), Value::Object(details)) = (payload.as_object_mut(), details) {
+            payload.extend(details);
+        }
+        payload
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Drops alerts that repeat the last alert sent for the same host within a time window, so a
+/// flapping host does not flood the receivers.
+///
+/// Down and up alerts share one entry per host: an alert for the opposite state always goes
+/// out and restarts the window, so the receivers never miss that a host went down again.
+/// Loss and RTT alerts are tracked on their own.
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: Duration,
+    last_sent: HashMap<String, (&'static str, Instant)>,
+}
+
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new(WEBHOOK_DEDUP_WINDOW)
+    }
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Whether `alert` should be sent at `now`; remembers it if so.
+    pub fn admit(&mut self, alert: &Alert, now: Instant) -> bool {
+        let kind = match alert {
+            Alert::State(_) => "state",
+            _ => alert.event(),
+        };
+        let key = format!("{} {}", alert.target().key(), kind);
+        let event = alert.event();
+        if self
+            .last_sent
+            .get(&key)
+            .is_some_and(|(last, sent)| *last == event && now.duration_since(*sent) < self.window)
+        {
+            return false;
+        }
+        self.last_sent.insert(key, (event, now));
+        true
+    }
+}
+
+/// A webhook that could not be delivered.
+#[derive(Debug)]
+pub struct DeliveryError {
+    pub url: HttpUrl,
+    pub attempts: u32,
+    pub reason: String,
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "webhook {} failed after {} attempts: {}",
+            self.url, self.attempts, self.reason
+        )
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+/// The configured webhooks and how hard to try delivering to them.
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    webhooks: Vec<Webhook>,
+    attempts: u32,
+    retry_delay: Duration,
+    timeout: Duration,
+}
+
+impl Webhooks {
+    pub fn new(webhooks: Vec<Webhook>) -> Self {
+        Self {
+            webhooks,
+            attempts: WEBHOOK_ATTEMPTS,
+            retry_delay: WEBHOOK_RETRY_DELAY,
+            timeout: WEBHOOK_TIMEOUT,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+    }
+
+    /// Posts `alert` to all webhooks at once and returns the deliveries that failed.
+    pub async fn send(&self, alert: &Alert) -> Vec<DeliveryError> {
+        join_all(self.webhooks.iter().map(|webhook| {
+            let body = alert.payload(webhook.format).to_string();
+            async move { self.deliver(&webhook.url, &body).await }
+        }))
+        .await
+        .into_iter()
+        .filter_map(Result::err)
+        .collect()
+    }
+
+    /// Posts `body` to `url`, retrying with exponential backoff on connection errors, timeouts,
+    /// 429 and 5xx responses.  Other error responses are not retried.
+    async fn deliver(&self, url: &HttpUrl, body: &str) -> Result<(), DeliveryError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (reason, retry) = match time::timeout(self.timeout, post_json(url, body)).await {
+                Ok(Ok(status)) if (200..300).contains(&status) => return Ok(()),
+                Ok(Ok(status)) => (
+                    format!("HTTP status {}", status),
+                    status == 429 || status >= 500,
+                ),
+                Ok(Err(e)) => (e.to_string(), true),
+                Err(_) => ("timed out".to_string(), true),
+            };
+            if !retry || attempt >= self.attempts {
+                return Err(DeliveryError {
+                    url: url.clone(),
+                    attempts: attempt,
+                    reason,
+                });
+            }
+            time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Transition;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::UNIX_EPOCH;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    fn target() -> PingTarget {
+        PingTarget::with_host(
+            "gw.example.com".to_string(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        )
+    }
+
+    fn change(from: HostState, to: HostState) -> StateChange {
+        StateChange {
+            target: target(),
+            transition: Transition {
+                from,
+                to,
+                at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            },
+            outage: Some(Duration::from_millis(30_500)),
+            recent_loss: 0.4,
+            recent_rtt: Some(Duration::from_micros(12_250)),
+        }
+    }
+
+    fn down() -> Alert {
+        Alert::from_state_change(change(HostState::Degraded, HostState::Down)).unwrap()
+    }
+
+    /// Answers one request per entry of `statuses` and returns the request bodies received.
+    async fn spawn_listener(statuses: Vec<u16>) -> (HttpUrl, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let body = loop {
+                    let mut buf = [0; 4096];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .and_then(|length| length.parse::<usize>().ok())
+                            .unwrap();
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                bodies.push(body);
+                let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    fn webhooks(url: HttpUrl, format: WebhookFormat) -> Webhooks {
+        let mut webhooks = Webhooks::new(vec![Webhook { format, url }]);
+        webhooks.retry_delay = Duration::from_millis(10);
+        webhooks
+    }
+
+    #[test]
+    fn parses_webhook_with_optional_format() {
+        let webhook = "slack=https://hooks.slack.com/services/T0/B0/x"
+            .parse::<Webhook>()
+            .unwrap();
+        assert_eq!(webhook.format, WebhookFormat::Slack);
+        assert_eq!(webhook.url.host, "hooks.slack.com");
+
+        let webhook = "http://localhost:8080/alert?key=a"
+            .parse::<Webhook>()
+            .unwrap();
+        assert_eq!(webhook.format, WebhookFormat::Json);
+        assert_eq!(webhook.url.path, "/alert?key=a");
+
+        assert!("discord=https://example.com".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn only_outages_and_recoveries_raise_alerts() {
+        assert_eq!(down().event(), "down");
+        let up = Alert::from_state_change(change(HostState::Down, HostState::Up)).unwrap();
+        assert_eq!(up.event(), "up");
+        assert!(Alert::from_state_change(change(HostState::Up, HostState::Degraded)).is_none());
+        assert!(Alert::from_state_change(change(HostState::Degraded, HostState::Up)).is_none());
+    }
+
+    #[test]
+    fn json_payload_has_state_details() {
+        let payload = down().payload(WebhookFormat::Json);
+        assert_eq!(payload["event"], "down");
+        assert_eq!(payload["host"], "gw.example.com");
+        assert_eq!(payload["addr"], "10.0.0.1");
+        assert_eq!(payload["state"], "down");
+        assert_eq!(payload["previous_state"], "degraded");
+        assert_eq!(payload["outage_seconds"], 30.5);
+        assert_eq!(payload["loss_percent"], 40.0);
+        assert_eq!(payload["rtt_ms"], 12.25);
+        assert_eq!(payload["timestamp"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(
+            payload["message"],
+            "gw.example.com (10.0.0.1) is down (was degraded) with 40 % recent loss"
+        );
+    }
+
+    #[test]
+    fn chat_payloads_carry_message() {
+        let up = Alert::from_state_change(change(HostState::Down, HostState::Up)).unwrap();
+        let message = "gw.example.com (10.0.0.1) is up again after 30.50 s down";
+        assert_eq!(up.payload(WebhookFormat::Slack), json!({ "text": message }));
+
+        let card = up.payload(WebhookFormat::Teams);
+        assert_eq!(card["@type"], "MessageCard");
+        assert_eq!(card["text"], message);
+        assert_eq!(card["themeColor"], "2EB886");
+    }
+
+    #[test]
+    fn breaches_report_each_exceeded_limit() {
+        let mut results = PingResults::new(target());
+        results.add_received(crate::network::ping::PingResponse::new(
+            Duration::from_millis(150),
+        ));
+        results.add_loss();
+
+        let thresholds = AlertThresholds {
+            loss_percent: Some(10.0),
+            avg_rtt: Some(Duration::from_millis(100)),
+        };
+        let alerts = thresholds.breaches(&results);
+        assert_eq!(
+            alerts.iter().map(Alert::event).collect::<Vec<_>>(),
+            vec!["loss", "rtt"]
+        );
+        let payload = alerts[1].payload(WebhookFormat::Json);
+        assert_eq!(payload["rtt_ms"], 150.0);
+        assert_eq!(payload["limit_ms"], 100.0);
+
+        let lenient = AlertThresholds {
+            loss_percent: Some(50.0),
+            avg_rtt: Some(Duration::from_millis(200)),
+        };
+        assert!(lenient.breaches(&results).is_empty());
+        assert!(AlertThresholds::default().breaches(&results).is_empty());
+    }
+
+    #[test]
+    fn recent_breaches_wait_for_a_full_window() {
+        let thresholds = AlertThresholds {
+            loss_percent: Some(10.0),
+            avg_rtt: None,
+        };
+        let recent = RecentStats {
+            probes: RECENT_SAMPLES,
+            loss: 0.2,
+            rtt: Some(Duration::from_millis(5)),
+        };
+        let alerts = thresholds.recent_breaches(&target(), SystemTime::now(), recent);
+        assert_eq!(
+            alerts.iter().map(Alert::event).collect::<Vec<_>>(),
+            vec!["loss"]
+        );
+        assert_eq!(
+            alerts[0].message(),
+            "gw.example.com (10.0.0.1) lost 20.00 % of probes, above the 10 % alert threshold"
+        );
+
+        let early = RecentStats {
+            probes: RECENT_SAMPLES - 1,
+            ..recent
+        };
+        assert!(
+            thresholds
+                .recent_breaches(&target(), SystemTime::now(), early)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn deduplicator_drops_repeats_within_window() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(dedup.admit(&down(), start));
+        assert!(!dedup.admit(&down(), start + Duration::from_secs(30)));
+
+        let up = Alert::from_state_change(change(HostState::Down, HostState::Up)).unwrap();
+        assert!(dedup.admit(&up, start + Duration::from_secs(30)));
+        assert!(!dedup.admit(&up, start + Duration::from_secs(40)));
+        assert!(dedup.admit(&down(), start + Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn deduplicator_sends_every_flap_within_window() {
+        let mut dedup = Deduplicator::new(Duration::from_secs(300));
+        let start = Instant::now();
+        let up = Alert::from_state_change(change(HostState::Down, HostState::Up)).unwrap();
+        assert!(dedup.admit(&down(), start));
+        assert!(dedup.admit(&up, start + Duration::from_secs(10)));
+        assert!(dedup.admit(&down(), start + Duration::from_secs(20)));
+        assert!(!dedup.admit(&down(), start + Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn send_posts_payload() {
+        let (url, server) = spawn_listener(vec![204]).await;
+        let errors = webhooks(url, WebhookFormat::Slack).send(&down()).await;
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let bodies = server.await.unwrap();
+        let body: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(body, down().payload(WebhookFormat::Slack));
+    }
+
+    #[tokio::test]
+    async fn send_retries_server_errors() {
+        let (url, server) = spawn_listener(vec![503, 500, 200]).await;
+        let errors = webhooks(url, WebhookFormat::Json).send(&down()).await;
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_gives_up_after_last_attempt() {
+        let (url, server) = spawn_listener(vec![500, 500, 500]).await;
+        let errors = webhooks(url, WebhookFormat::Json).send(&down()).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].attempts, WEBHOOK_ATTEMPTS);
+        assert_eq!(errors[0].reason, "HTTP status 500");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_does_not_retry_client_errors() {
+        let (url, server) = spawn_listener(vec![400]).await;
+        let errors = webhooks(url, WebhookFormat::Json).send(&down()).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].attempts, 1);
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+}